@@ -0,0 +1,18 @@
+ALTER TABLE guildsettings
+  DROP COLUMN user_capabilities,
+  DROP COLUMN moderator_capabilities,
+  ADD COLUMN user_role_id NUMERIC,
+  ADD COLUMN moderator_role_id NUMERIC;
+
+-- Only one role per level can be restored
+UPDATE guildsettings SET user_role_id = (
+  SELECT MIN(role_id) FROM guildroles
+  WHERE guildroles.guild_id = guildsettings.id AND permission_level = 'user'
+);
+
+UPDATE guildsettings SET moderator_role_id = (
+  SELECT MIN(role_id) FROM guildroles
+  WHERE guildroles.guild_id = guildsettings.id AND permission_level = 'moderator'
+);
+
+DROP TABLE guildroles;
@@ -0,0 +1,20 @@
+CREATE TABLE guildroles (
+  guild_id NUMERIC NOT NULL,
+  role_id NUMERIC NOT NULL,
+  permission_level VARCHAR(16) NOT NULL,
+  PRIMARY KEY(guild_id, role_id),
+  constraint permission_level_valid check (permission_level IN ('user', 'moderator'))
+);
+
+INSERT INTO guildroles (guild_id, role_id, permission_level)
+SELECT id, user_role_id, 'user' FROM guildsettings WHERE user_role_id IS NOT NULL;
+
+INSERT INTO guildroles (guild_id, role_id, permission_level)
+SELECT id, moderator_role_id, 'moderator' FROM guildsettings WHERE moderator_role_id IS NOT NULL
+ON CONFLICT (guild_id, role_id) DO UPDATE SET permission_level = 'moderator';
+
+ALTER TABLE guildsettings
+  DROP COLUMN user_role_id,
+  DROP COLUMN moderator_role_id,
+  ADD COLUMN user_capabilities TEXT[] NOT NULL DEFAULT '{play,record,download_recordings}',
+  ADD COLUMN moderator_capabilities TEXT[] NOT NULL DEFAULT '{play,record,download_recordings,manage_sounds}';
@@ -0,0 +1,5 @@
+DROP TABLE categoryrestrictions;
+
+ALTER TABLE sounds
+  DROP COLUMN allowed_role_ids,
+  DROP COLUMN allowed_channel_ids;
@@ -0,0 +1,11 @@
+ALTER TABLE sounds
+  ADD COLUMN allowed_role_ids NUMERIC[] NOT NULL DEFAULT '{}',
+  ADD COLUMN allowed_channel_ids NUMERIC[] NOT NULL DEFAULT '{}';
+
+CREATE TABLE categoryrestrictions (
+  guild_id NUMERIC NOT NULL,
+  category VARCHAR(64) NOT NULL,
+  allowed_role_ids NUMERIC[] NOT NULL DEFAULT '{}',
+  allowed_channel_ids NUMERIC[] NOT NULL DEFAULT '{}',
+  PRIMARY KEY(guild_id, category)
+);
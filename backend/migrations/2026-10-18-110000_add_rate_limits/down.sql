@@ -0,0 +1,6 @@
+ALTER TABLE sounds
+  DROP COLUMN cooldown;
+
+ALTER TABLE guildsettings
+  DROP COLUMN user_plays_per_minute,
+  DROP COLUMN guild_plays_per_minute;
@@ -0,0 +1,6 @@
+ALTER TABLE guildsettings
+  ADD COLUMN user_plays_per_minute INTEGER,
+  ADD COLUMN guild_plays_per_minute INTEGER;
+
+ALTER TABLE sounds
+  ADD COLUMN cooldown REAL;
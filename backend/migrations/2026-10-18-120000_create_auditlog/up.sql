@@ -0,0 +1,12 @@
+CREATE TABLE auditlog (
+  id SERIAL PRIMARY KEY,
+  guild_id NUMERIC NOT NULL,
+  user_id NUMERIC,
+  action VARCHAR(32) NOT NULL,
+  target VARCHAR(128),
+  before JSONB,
+  after JSONB,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX auditlog_guild_id_created_at_idx ON auditlog (guild_id, created_at);
@@ -0,0 +1,11 @@
+CREATE TABLE plays (
+  id SERIAL PRIMARY KEY,
+  guild_id NUMERIC NOT NULL,
+  sound_id INTEGER NOT NULL REFERENCES sounds(id) ON DELETE CASCADE,
+  user_id NUMERIC NOT NULL,
+  source VARCHAR(16) NOT NULL,
+  played_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX plays_guild_id_played_at_idx ON plays (guild_id, played_at);
+CREATE INDEX plays_sound_id_idx ON plays (sound_id);
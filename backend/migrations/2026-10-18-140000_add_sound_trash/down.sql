@@ -0,0 +1,5 @@
+DROP INDEX sounds_deleted_at_idx;
+
+ALTER TABLE sounds
+  DROP COLUMN deleted_at,
+  DROP COLUMN deleted_by_user_id;
@@ -0,0 +1,5 @@
+ALTER TABLE sounds
+  ADD COLUMN deleted_at TIMESTAMP,
+  ADD COLUMN deleted_by_user_id NUMERIC;
+
+CREATE INDEX sounds_deleted_at_idx ON sounds (deleted_at);
@@ -0,0 +1,16 @@
+CREATE TABLE soundfileversions (
+  id SERIAL PRIMARY KEY,
+  sound_id INTEGER NOT NULL,
+  file_name VARCHAR(64) NOT NULL,
+  max_volume REAL NOT NULL,
+  mean_volume REAL NOT NULL,
+  length REAL NOT NULL,
+  uploaded_by_user_id NUMERIC,
+  uploaded_at TIMESTAMP NOT NULL,
+  FOREIGN KEY(sound_id) REFERENCES sounds(id)
+  ON DELETE CASCADE,
+  FOREIGN KEY(uploaded_by_user_id) REFERENCES users(id)
+  ON DELETE SET NULL
+);
+
+CREATE INDEX soundfileversions_sound_id_idx ON soundfileversions (sound_id);
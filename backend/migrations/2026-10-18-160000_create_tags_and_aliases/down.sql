@@ -0,0 +1,3 @@
+DROP TABLE soundaliases;
+DROP TABLE soundtags;
+DROP TABLE tags;
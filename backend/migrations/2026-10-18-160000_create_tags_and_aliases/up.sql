@@ -0,0 +1,24 @@
+CREATE TABLE tags (
+  id SERIAL PRIMARY KEY,
+  guild_id NUMERIC NOT NULL,
+  name VARCHAR(64) NOT NULL,
+  UNIQUE (guild_id, name)
+);
+
+CREATE TABLE soundtags (
+  sound_id INTEGER NOT NULL,
+  tag_id INTEGER NOT NULL,
+  PRIMARY KEY (sound_id, tag_id),
+  FOREIGN KEY(sound_id) REFERENCES sounds(id)
+  ON DELETE CASCADE,
+  FOREIGN KEY(tag_id) REFERENCES tags(id)
+  ON DELETE CASCADE
+);
+
+CREATE TABLE soundaliases (
+  sound_id INTEGER NOT NULL,
+  alias VARCHAR(64) NOT NULL,
+  PRIMARY KEY (sound_id, alias),
+  FOREIGN KEY(sound_id) REFERENCES sounds(id)
+  ON DELETE CASCADE
+);
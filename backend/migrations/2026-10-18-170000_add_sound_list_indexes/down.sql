@@ -0,0 +1,14 @@
+DROP INDEX soundaliases_alias_trgm_idx;
+DROP INDEX tags_name_trgm_idx;
+DROP INDEX sounds_category_trgm_idx;
+DROP INDEX sounds_name_trgm_idx;
+
+DROP INDEX soundtags_tag_id_idx;
+DROP INDEX soundfiles_length_idx;
+DROP INDEX sounds_guild_id_category_idx;
+DROP INDEX sounds_guild_id_play_count_idx;
+DROP INDEX sounds_guild_id_created_at_idx;
+DROP INDEX sounds_guild_id_name_idx;
+
+ALTER TABLE sounds
+  DROP COLUMN play_count;
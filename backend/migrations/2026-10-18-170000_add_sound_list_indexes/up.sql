@@ -0,0 +1,19 @@
+ALTER TABLE sounds
+  ADD COLUMN play_count INTEGER NOT NULL DEFAULT 0;
+
+UPDATE sounds
+  SET play_count = (SELECT COUNT(*) FROM plays WHERE plays.sound_id = sounds.id);
+
+CREATE INDEX sounds_guild_id_name_idx ON sounds (guild_id, name, id);
+CREATE INDEX sounds_guild_id_created_at_idx ON sounds (guild_id, created_at, id);
+CREATE INDEX sounds_guild_id_play_count_idx ON sounds (guild_id, play_count, id);
+CREATE INDEX sounds_guild_id_category_idx ON sounds (guild_id, category);
+CREATE INDEX soundfiles_length_idx ON soundfiles (length);
+CREATE INDEX soundtags_tag_id_idx ON soundtags (tag_id);
+
+-- Trigram indexes speed up the text search, which uses ILIKE with leading wildcards
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+CREATE INDEX sounds_name_trgm_idx ON sounds USING gin (name gin_trgm_ops);
+CREATE INDEX sounds_category_trgm_idx ON sounds USING gin (category gin_trgm_ops);
+CREATE INDEX tags_name_trgm_idx ON tags USING gin (name gin_trgm_ops);
+CREATE INDEX soundaliases_alias_trgm_idx ON soundaliases USING gin (alias gin_trgm_ops);
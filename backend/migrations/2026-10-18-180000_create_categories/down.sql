@@ -0,0 +1,6 @@
+ALTER TABLE categoryrestrictions
+  DROP CONSTRAINT categoryrestrictions_category_fkey;
+ALTER TABLE sounds
+  DROP CONSTRAINT sounds_category_fkey;
+
+DROP TABLE categories;
@@ -0,0 +1,24 @@
+CREATE TABLE categories (
+  id SERIAL PRIMARY KEY,
+  guild_id NUMERIC NOT NULL,
+  name VARCHAR(64) NOT NULL,
+  display_order INTEGER NOT NULL DEFAULT 0,
+  color VARCHAR(16),
+  emoji VARCHAR(64),
+  description TEXT,
+  UNIQUE (guild_id, name)
+);
+
+-- Categories were free-form strings before
+INSERT INTO categories (guild_id, name)
+  SELECT DISTINCT guild_id, category FROM sounds
+  UNION
+  SELECT DISTINCT guild_id, category FROM categoryrestrictions;
+
+-- Renaming a category is applied to the sounds and restrictions using it
+ALTER TABLE sounds
+  ADD CONSTRAINT sounds_category_fkey FOREIGN KEY (guild_id, category) REFERENCES categories(guild_id, name)
+  ON UPDATE CASCADE ON DELETE RESTRICT;
+ALTER TABLE categoryrestrictions
+  ADD CONSTRAINT categoryrestrictions_category_fkey FOREIGN KEY (guild_id, category) REFERENCES categories(guild_id, name)
+  ON UPDATE CASCADE ON DELETE CASCADE;
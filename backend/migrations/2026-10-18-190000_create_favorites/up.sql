@@ -0,0 +1,9 @@
+CREATE TABLE favorites (
+  user_id NUMERIC NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  sound_id INTEGER NOT NULL REFERENCES sounds(id) ON DELETE CASCADE,
+  position INTEGER NOT NULL DEFAULT 0,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (user_id, sound_id)
+);
+
+CREATE INDEX favorites_sound_id_idx ON favorites (sound_id);
@@ -0,0 +1,4 @@
+ALTER TABLE soundfileversions
+  DROP COLUMN content_hash;
+ALTER TABLE soundfiles
+  DROP COLUMN content_hash;
@@ -0,0 +1,8 @@
+-- Hashes of existing files are computed on startup
+ALTER TABLE soundfiles
+  ADD COLUMN content_hash VARCHAR(64);
+ALTER TABLE soundfileversions
+  ADD COLUMN content_hash VARCHAR(64);
+
+CREATE INDEX soundfiles_content_hash_idx ON soundfiles (content_hash);
+CREATE INDEX soundfileversions_content_hash_idx ON soundfileversions (content_hash);
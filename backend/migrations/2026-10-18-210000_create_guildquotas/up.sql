@@ -0,0 +1,8 @@
+-- Overrides of the quotas configured via environment variables. Maintained by the bot operator.
+CREATE TABLE guildquotas (
+  guild_id NUMERIC PRIMARY KEY,
+  max_sound_storage_mb INTEGER,
+  max_sounds INTEGER,
+  max_clip_length REAL,
+  max_recording_storage_mb INTEGER
+);
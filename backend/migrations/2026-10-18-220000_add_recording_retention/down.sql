@@ -0,0 +1,4 @@
+ALTER TABLE guildsettings
+  DROP COLUMN recording_max_age_days,
+  DROP COLUMN recording_max_count,
+  DROP COLUMN recording_max_storage_mb;
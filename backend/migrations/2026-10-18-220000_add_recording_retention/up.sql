@@ -0,0 +1,5 @@
+-- Recordings exceeding any of these limits are removed automatically, unless they are pinned. NULL means no limit.
+ALTER TABLE guildsettings
+  ADD COLUMN recording_max_age_days INTEGER,
+  ADD COLUMN recording_max_count INTEGER,
+  ADD COLUMN recording_max_storage_mb INTEGER;
@@ -0,0 +1,5 @@
+ALTER TABLE guildsettings
+  DROP COLUMN recording_opt_in_required;
+
+DROP TABLE recordingconsents;
+DROP TABLE recordingoptouts;
@@ -0,0 +1,17 @@
+-- Users who do not want to be recorded in any guild
+CREATE TABLE recordingoptouts (
+  user_id NUMERIC PRIMARY KEY
+);
+
+-- Choices of users for single guilds. They take precedence over the default of the guild, but not over an opt-out
+-- for all guilds.
+CREATE TABLE recordingconsents (
+  user_id NUMERIC NOT NULL,
+  guild_id NUMERIC NOT NULL,
+  consent BOOLEAN NOT NULL,
+  PRIMARY KEY (user_id, guild_id)
+);
+
+-- Only record users who explicitly opted in
+ALTER TABLE guildsettings
+  ADD COLUMN recording_opt_in_required BOOLEAN NOT NULL DEFAULT false;
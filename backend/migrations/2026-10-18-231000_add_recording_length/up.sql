@@ -0,0 +1,3 @@
+-- Length of the rolling recording buffer in seconds. NULL uses the maximum configured by the operator.
+ALTER TABLE guildsettings
+  ADD COLUMN recording_length INTEGER;
@@ -0,0 +1,2 @@
+ALTER TABLE guildsettings
+  DROP COLUMN recording_format;
@@ -0,0 +1,3 @@
+-- Format the tracks of saved recordings are encoded in, e.g. mp3 or flac
+ALTER TABLE guildsettings
+  ADD COLUMN recording_format TEXT NOT NULL DEFAULT 'mp3';
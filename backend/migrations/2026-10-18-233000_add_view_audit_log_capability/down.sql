@@ -0,0 +1,6 @@
+UPDATE guildsettings SET
+  user_capabilities = array_remove(user_capabilities, 'view_audit_log'),
+  moderator_capabilities = array_remove(moderator_capabilities, 'view_audit_log');
+
+ALTER TABLE guildsettings
+  ALTER COLUMN moderator_capabilities SET DEFAULT '{play,record,download_recordings,manage_sounds}';
@@ -0,0 +1,6 @@
+-- Moderators could always view the audit log, so they keep that capability
+ALTER TABLE guildsettings
+  ALTER COLUMN moderator_capabilities SET DEFAULT '{play,record,download_recordings,manage_sounds,view_audit_log}';
+
+UPDATE guildsettings SET moderator_capabilities = array_append(moderator_capabilities, 'view_audit_log')
+WHERE NOT ('view_audit_log' = ANY(moderator_capabilities));
@@ -0,0 +1,4 @@
+ALTER TABLE soundfileversions
+  DROP COLUMN file_size;
+ALTER TABLE soundfiles
+  DROP COLUMN file_size;
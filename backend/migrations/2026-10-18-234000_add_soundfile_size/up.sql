@@ -0,0 +1,6 @@
+-- Size of the file in bytes, so that the storage usage of a guild can be summed up in the database.
+-- Files stored before are measured on startup.
+ALTER TABLE soundfiles
+  ADD COLUMN file_size BIGINT;
+ALTER TABLE soundfileversions
+  ADD COLUMN file_size BIGINT;
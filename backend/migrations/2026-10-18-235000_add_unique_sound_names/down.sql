@@ -0,0 +1 @@
+DROP INDEX sounds_guild_id_name_key;
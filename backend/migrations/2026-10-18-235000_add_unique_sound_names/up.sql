@@ -0,0 +1,14 @@
+-- Sound names were not unique before. Later duplicates get their id appended.
+UPDATE sounds
+  SET name = LEFT(name, 64 - LENGTH(' (' || id || ')')) || ' (' || id || ')'
+  WHERE deleted_at IS NULL
+    AND EXISTS (
+      SELECT 1 FROM sounds AS other
+      WHERE other.guild_id = sounds.guild_id
+        AND other.name = sounds.name
+        AND other.deleted_at IS NULL
+        AND other.id < sounds.id
+    );
+
+-- Trashed sounds may share the name of a new sound. Restoring them fails in that case.
+CREATE UNIQUE INDEX sounds_guild_id_name_key ON sounds (guild_id, name) WHERE deleted_at IS NULL;
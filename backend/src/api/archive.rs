@@ -0,0 +1,896 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::io::Read;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use bigdecimal::BigDecimal;
+use bigdecimal::FromPrimitive;
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use rocket::fs::NamedFile;
+use rocket::fs::TempFile;
+use rocket::http::Header;
+use rocket::http::Status;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::Json;
+use rocket::Request;
+use rocket::Route;
+use rocket::State;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_with::serde_as;
+use serde_with::TimestampSeconds;
+use serenity::model::id::GuildId;
+use thiserror::Error;
+use tokio::fs;
+
+use crate::api::audit_log;
+use crate::api::audit_log::AuditAction;
+use crate::api::audit_log::AuditEntry;
+use crate::api::auth::UserId;
+use crate::api::categories::ensure_category;
+use crate::api::quotas;
+use crate::api::quotas::GuildQuota;
+use crate::api::quotas::QuotaError;
+use crate::api::sounds::load_tags_and_aliases;
+use crate::api::sounds::new_file_name;
+use crate::api::sounds::set_aliases;
+use crate::api::sounds::set_tags;
+use crate::api::sounds::store_sound_file;
+use crate::api::sounds::validate_labels;
+use crate::api::Snowflake;
+use crate::db::models;
+use crate::db::DbConn;
+use crate::discord::management::check_guild_capability;
+use crate::discord::management::Capability;
+use crate::discord::management::PermissionError;
+use crate::file_handling;
+use crate::storage::LocalFile;
+use crate::storage::STORAGE;
+use crate::CacheHttp;
+
+pub fn get_routes() -> Vec<Route> {
+    routes![export_guild, import_guild]
+}
+
+/// Increased whenever the manifest changes in an incompatible way
+const MANIFEST_VERSION: u32 = 1;
+const MANIFEST_FILE: &str = "manifest.json";
+const SOUNDS_DIR: &str = "sounds";
+
+#[derive(Debug, Error)]
+enum ArchiveError {
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("Number handling error")]
+    NumericalError,
+
+    #[error("Database error: {0}")]
+    DieselError(#[from] DieselError),
+
+    #[error("Insufficient permission: you do not have the permission to perform this action")]
+    InsufficientPermission(#[from] PermissionError),
+
+    #[error("Invalid archive: {0}")]
+    InvalidArchive(String),
+
+    #[error("{0}")]
+    QuotaError(#[from] QuotaError),
+}
+
+impl ArchiveError {
+    fn status_code(&self) -> Status {
+        match self {
+            Self::IoError(_) => Status::InternalServerError,
+            Self::NumericalError => Status::InternalServerError,
+            Self::DieselError(_) => Status::InternalServerError,
+            Self::InsufficientPermission(_) => Status::Forbidden,
+            Self::InvalidArchive(_) => Status::BadRequest,
+            Self::QuotaError(err) => err.status_code(),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ArchiveError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.status_code();
+        let error_message = self.to_string();
+
+        Response::build_from(error_message.respond_to(req)?)
+            .status(status)
+            .ok()
+    }
+}
+
+/// Describes the content of an archive. Role and channel restrictions are not exported as they
+/// only make sense in the guild they were created in.
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    version: u32,
+    guild_id: Snowflake,
+    #[serde_as(as = "TimestampSeconds<String>")]
+    exported_at: SystemTime,
+    #[serde(default)]
+    categories: Vec<ManifestCategory>,
+    #[serde(default)]
+    random_infixes: Vec<ManifestRandomInfix>,
+    #[serde(default)]
+    sounds: Vec<ManifestSound>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ManifestCategory {
+    name: String,
+    display_order: i32,
+    color: Option<String>,
+    emoji: Option<String>,
+    description: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ManifestRandomInfix {
+    infix: String,
+    display_name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ManifestSound {
+    name: String,
+    category: String,
+    volume_adjustment: Option<f32>,
+    cooldown: Option<f32>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    aliases: Vec<String>,
+    file: Option<ManifestSoundfile>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ManifestSoundfile {
+    /// Path of the file within the archive
+    path: String,
+    max_volume: f32,
+    mean_volume: f32,
+    length: f32,
+}
+
+#[derive(Responder)]
+#[response(content_type = "application/x-tar")]
+struct ArchiveDownload {
+    file: NamedFile,
+    disposition: Header<'static>,
+}
+
+/// Exports the sounds of the guild including their files, categories and random infixes as a tar archive.
+/// Sounds in the trash are not exported.
+#[get("/guilds/<guild_id>/export")]
+async fn export_guild(
+    guild_id: u64,
+    user: UserId,
+    db: DbConn,
+    cache_http: &State<CacheHttp>,
+) -> Result<ArchiveDownload, ArchiveError> {
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.into(),
+        GuildId::new(guild_id),
+        Capability::ManageSounds,
+    )
+    .await?;
+
+    let gid = BigDecimal::from_u64(guild_id).ok_or(ArchiveError::NumericalError)?;
+    let (categories, random_infixes, guild_sounds, mut tags, mut aliases) = db
+        .run(move |c| {
+            use crate::db::schema::categories;
+            use crate::db::schema::randominfixes;
+            use crate::db::schema::soundfiles;
+            use crate::db::schema::sounds;
+
+            let categories = categories::table
+                .filter(categories::guild_id.eq(&gid))
+                .order((categories::display_order.asc(), categories::name.asc()))
+                .load::<models::Category>(c)?;
+            let random_infixes = randominfixes::table
+                .filter(randominfixes::guild_id.eq(&gid))
+                .load::<models::RandomInfix>(c)?;
+            let guild_sounds = sounds::table
+                .left_join(soundfiles::table)
+                .filter(sounds::guild_id.eq(&gid))
+                .filter(sounds::deleted_at.is_null())
+                .order(sounds::id.asc())
+                .load::<(models::Sound, Option<models::Soundfile>)>(c)?;
+            let sound_ids = guild_sounds
+                .iter()
+                .map(|(sound, _)| sound.id)
+                .collect::<Vec<_>>();
+            let (tags, aliases) = load_tags_and_aliases(c, &sound_ids)?;
+
+            Ok::<_, DieselError>((categories, random_infixes, guild_sounds, tags, aliases))
+        })
+        .await?;
+
+    let mut files = vec![];
+    let mut manifest_sounds = vec![];
+    for (sound, soundfile) in guild_sounds {
+        let file = match soundfile {
+            Some(soundfile) => {
+                let path = format!("{}/{}.mp3", SOUNDS_DIR, sound.id);
+                files.push((
+                    path.clone(),
+                    STORAGE
+                        .local_path(&file_handling::sound_key(&soundfile.file_name))
+                        .await?,
+                ));
+                Some(ManifestSoundfile {
+                    path,
+                    max_volume: soundfile.max_volume,
+                    mean_volume: soundfile.mean_volume,
+                    length: soundfile.length,
+                })
+            }
+            None => None,
+        };
+        manifest_sounds.push(ManifestSound {
+            tags: tags.remove(&sound.id).unwrap_or_default(),
+            aliases: aliases.remove(&sound.id).unwrap_or_default(),
+            name: sound.name,
+            category: sound.category,
+            volume_adjustment: sound.volume_adjustment,
+            cooldown: sound.cooldown,
+            file,
+        });
+    }
+
+    let manifest = Manifest {
+        version: MANIFEST_VERSION,
+        guild_id: Snowflake(guild_id),
+        exported_at: SystemTime::now(),
+        categories: categories
+            .into_iter()
+            .map(|category| ManifestCategory {
+                name: category.name,
+                display_order: category.display_order,
+                color: category.color,
+                emoji: category.emoji,
+                description: category.description,
+            })
+            .collect(),
+        random_infixes: random_infixes
+            .into_iter()
+            .map(|infix| ManifestRandomInfix {
+                infix: infix.infix,
+                display_name: infix.display_name,
+            })
+            .collect(),
+        sounds: manifest_sounds,
+    };
+    let manifest = serde_json::to_vec_pretty(&manifest)
+        .map_err(|err| ArchiveError::IoError(std::io::Error::other(err)))?;
+
+    let archive_path = file_handling::temp_path("tar");
+    {
+        let archive_path = archive_path.clone();
+        tokio::task::spawn_blocking(move || write_archive(&archive_path, &manifest, files))
+            .await
+            .map_err(|err| ArchiveError::IoError(std::io::Error::other(err)))??;
+    }
+
+    // The open file stays readable after it has been removed
+    let file = NamedFile::open(&archive_path).await;
+    fs::remove_file(&archive_path).await?;
+
+    Ok(ArchiveDownload {
+        file: file?,
+        disposition: Header::new(
+            "Content-Disposition",
+            format!("attachment; filename=\"soundboard-{}.tar\"", guild_id),
+        ),
+    })
+}
+
+fn write_archive(
+    path: &Path,
+    manifest: &[u8],
+    files: Vec<(String, LocalFile)>,
+) -> Result<(), std::io::Error> {
+    let mut builder = tar::Builder::new(std::fs::File::create(path)?);
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0),
+    );
+    header.set_cksum();
+    builder.append_data(&mut header, MANIFEST_FILE, manifest)?;
+
+    for (name, file_path) in files {
+        builder.append_path_with_name(file_path, name)?;
+    }
+
+    builder.into_inner()?.sync_all()
+}
+
+/// Extracts the manifest and the sound files to the given directory. Entries outside of the sounds
+/// directory are ignored.
+fn read_archive(path: &Path, out_dir: &Path) -> Result<Option<Vec<u8>>, std::io::Error> {
+    read_entries(tar::Archive::new(std::fs::File::open(path)?), out_dir)
+}
+
+/// Archives are uploaded by users, so only regular files are read and they are written to files created
+/// here instead of unpacking them. Links or paths leaving the archive are rejected.
+fn read_entries<R: Read>(
+    mut archive: tar::Archive<R>,
+    out_dir: &Path,
+) -> Result<Option<Vec<u8>>, std::io::Error> {
+    let mut manifest = None;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = normalize_entry_path(&entry.path()?)?;
+
+        match entry.header().entry_type() {
+            tar::EntryType::Regular => {}
+            // Directories carry no content and are never created
+            tar::EntryType::Directory => continue,
+            entry_type => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "{} is not a regular file ({:?})",
+                        entry_path.display(),
+                        entry_type
+                    ),
+                ))
+            }
+        }
+
+        if entry_path == Path::new(MANIFEST_FILE) {
+            let mut content = vec![];
+            entry.read_to_end(&mut content)?;
+            manifest = Some(content);
+        } else if entry_path.parent() == Some(Path::new(SOUNDS_DIR)) {
+            if let Some(file_name) = entry_path.file_name() {
+                let mut file = std::fs::File::create_new(
+                    out_dir.join(sanitize_filename::sanitize(file_name.to_string_lossy())),
+                )?;
+                std::io::copy(&mut entry, &mut file)?;
+            }
+        }
+    }
+
+    Ok(manifest)
+}
+
+/// The path of an entry relative to the root of the archive, without `.` components
+fn normalize_entry_path(path: &Path) -> Result<PathBuf, std::io::Error> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => normalized.push(part),
+            Component::CurDir => {}
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{} leaves the archive", path.display()),
+                ))
+            }
+        }
+    }
+    Ok(normalized)
+}
+
+/// How to handle sounds of the archive whose name is already taken in the guild
+#[derive(FromFormField, Debug, Clone, Copy, PartialEq, Eq)]
+enum ConflictStrategy {
+    /// Keep the existing sound and do not import the one from the archive
+    Skip,
+    /// Import the sound under a new name
+    Rename,
+    /// Replace the properties and the file of the existing sound. Its previous file is kept as a version.
+    Overwrite,
+}
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct ImportResult {
+    created: Vec<String>,
+    overwritten: Vec<String>,
+    skipped: Vec<String>,
+    renamed: HashMap<String, String>,
+    /// Sounds that could not be imported by name, with the reason. They are left unchanged.
+    failed: HashMap<String, String>,
+}
+
+/// Returns a name that is not taken yet by appending a number
+fn free_name(name: &str, taken: &HashSet<String>) -> String {
+    (2..)
+        .map(|n| format!("{} ({})", name, n))
+        .find(|candidate| !taken.contains(candidate))
+        .unwrap_or_else(|| name.to_string())
+}
+
+/// Imports an archive created by the export. Existing sounds are matched by name, `conflict` decides what
+/// happens to them and defaults to `skip`. Categories and random infixes that already exist are only
+/// changed when overwriting.
+#[post(
+    "/guilds/<guild_id>/import?<conflict>",
+    format = "application/x-tar",
+    data = "<file>"
+)]
+async fn import_guild(
+    guild_id: u64,
+    conflict: Option<ConflictStrategy>,
+    mut file: TempFile<'_>,
+    user: UserId,
+    db: DbConn,
+    cache_http: &State<CacheHttp>,
+) -> Result<Json<ImportResult>, ArchiveError> {
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.clone().into(),
+        GuildId::new(guild_id),
+        Capability::ManageSounds,
+    )
+    .await?;
+
+    let conflict = conflict.unwrap_or(ConflictStrategy::Skip);
+    let archive_path = file_handling::temp_path("tar");
+    let out_dir = file_handling::temp_path("d");
+    file.move_copy_to(&archive_path).await?;
+    fs::create_dir_all(&out_dir).await?;
+
+    let result = import_archive(guild_id, conflict, &user, &archive_path, &out_dir, &db).await;
+
+    if let Err(err) = fs::remove_file(&archive_path).await {
+        warn!(?err, "Failed to remove imported archive");
+    }
+    if let Err(err) = fs::remove_dir_all(&out_dir).await {
+        warn!(?err, "Failed to remove extracted files of imported archive");
+    }
+
+    let (source_guild_id, result) = result?;
+    audit_log::log(
+        &db,
+        AuditEntry::new(
+            GuildId::new(guild_id),
+            user.into(),
+            AuditAction::SoundboardImported,
+        )
+        .target(source_guild_id.0)
+        .after(&result),
+    )
+    .await;
+
+    Ok(Json(result))
+}
+
+async fn import_archive(
+    guild_id: u64,
+    conflict: ConflictStrategy,
+    user: &UserId,
+    archive_path: &Path,
+    out_dir: &Path,
+    db: &DbConn,
+) -> Result<(Snowflake, ImportResult), ArchiveError> {
+    let manifest = {
+        let archive_path = archive_path.to_path_buf();
+        let out_dir = out_dir.to_path_buf();
+        tokio::task::spawn_blocking(move || read_archive(&archive_path, &out_dir))
+            .await
+            .map_err(|err| ArchiveError::IoError(std::io::Error::other(err)))?
+            .map_err(|err| ArchiveError::InvalidArchive(err.to_string()))?
+            .ok_or_else(|| ArchiveError::InvalidArchive(format!("{} is missing", MANIFEST_FILE)))?
+    };
+    let manifest = serde_json::from_slice::<Manifest>(&manifest)
+        .map_err(|err| ArchiveError::InvalidArchive(err.to_string()))?;
+    if manifest.version != MANIFEST_VERSION {
+        return Err(ArchiveError::InvalidArchive(format!(
+            "unsupported manifest version {}",
+            manifest.version
+        )));
+    }
+
+    let gid = BigDecimal::from_u64(guild_id).ok_or(ArchiveError::NumericalError)?;
+    let uid = BigDecimal::from_u64(user.0).ok_or(ArchiveError::NumericalError)?;
+    let overwrite = conflict == ConflictStrategy::Overwrite;
+
+    // The whole archive has to fit into the quota, so imports are not cut off halfway
+    let quota = quotas::get_quota(db, GuildId::new(guild_id)).await?;
+    let existing_names = {
+        let gid = gid.clone();
+        db.run(move |c| {
+            use crate::db::schema::sounds;
+
+            sounds::table
+                .filter(sounds::guild_id.eq(&gid))
+                .filter(sounds::deleted_at.is_null())
+                .select(sounds::name)
+                .load::<String>(c)
+        })
+        .await?
+        .into_iter()
+        .collect::<HashSet<_>>()
+    };
+    let mut new_sounds = 0;
+    let mut new_storage = 0;
+    for sound in &manifest.sounds {
+        validate_labels("tags", &sound.tags)
+            .and_then(|_| validate_labels("aliases", &sound.aliases))
+            .map_err(ArchiveError::InvalidArchive)?;
+        let exists = existing_names.contains(&sound.name);
+        if exists && conflict == ConflictStrategy::Skip {
+            continue;
+        }
+        if !exists || conflict == ConflictStrategy::Rename {
+            new_sounds += 1;
+        }
+        if let Some(path) = sound
+            .file
+            .as_ref()
+            .and_then(|file| extracted_path(file, out_dir))
+        {
+            new_storage += fs::metadata(path).await?.len();
+        }
+    }
+    quotas::check_sound_count(db, GuildId::new(guild_id), new_sounds).await?;
+    quotas::check_sound_storage(db, GuildId::new(guild_id), new_storage).await?;
+
+    let existing_sounds = {
+        let gid = gid.clone();
+        let categories = manifest.categories;
+        let random_infixes = manifest.random_infixes;
+        db.run(move |c| {
+            use crate::db::schema::categories;
+            use crate::db::schema::randominfixes;
+            use crate::db::schema::soundfiles;
+            use crate::db::schema::sounds;
+
+            c.transaction(|c| {
+                for category in categories {
+                    let values = (
+                        categories::guild_id.eq(&gid),
+                        categories::name.eq(&category.name),
+                        categories::display_order.eq(category.display_order),
+                        categories::color.eq(&category.color),
+                        categories::emoji.eq(&category.emoji),
+                        categories::description.eq(&category.description),
+                    );
+                    let insert = diesel::insert_into(categories::table)
+                        .values(values)
+                        .on_conflict((categories::guild_id, categories::name));
+                    if overwrite {
+                        insert
+                            .do_update()
+                            .set((
+                                categories::display_order.eq(category.display_order),
+                                categories::color.eq(&category.color),
+                                categories::emoji.eq(&category.emoji),
+                                categories::description.eq(&category.description),
+                            ))
+                            .execute(c)?;
+                    } else {
+                        insert.do_nothing().execute(c)?;
+                    }
+                }
+
+                for infix in random_infixes {
+                    let insert = diesel::insert_into(randominfixes::table)
+                        .values((
+                            randominfixes::guild_id.eq(&gid),
+                            randominfixes::infix.eq(&infix.infix),
+                            randominfixes::display_name.eq(&infix.display_name),
+                        ))
+                        .on_conflict((randominfixes::guild_id, randominfixes::infix));
+                    if overwrite {
+                        insert
+                            .do_update()
+                            .set(randominfixes::display_name.eq(&infix.display_name))
+                            .execute(c)?;
+                    } else {
+                        insert.do_nothing().execute(c)?;
+                    }
+                }
+
+                sounds::table
+                    .left_join(soundfiles::table)
+                    .filter(sounds::guild_id.eq(&gid))
+                    .filter(sounds::deleted_at.is_null())
+                    .select((sounds::name, sounds::id, soundfiles::file_name.nullable()))
+                    .load::<(String, i32, Option<String>)>(c)
+            })
+        })
+        .await?
+    };
+    let mut taken_names = existing_sounds
+        .iter()
+        .map(|(name, _, _)| name.clone())
+        .collect::<HashSet<_>>();
+    let existing_sounds = existing_sounds
+        .into_iter()
+        .map(|(name, id, file_name)| (name, (id, file_name.is_some())))
+        .collect::<HashMap<_, _>>();
+
+    let mut result = ImportResult::default();
+    for sound in manifest.sounds {
+        let existing = existing_sounds.get(&sound.name).copied();
+        let (name, existing) = match (existing, conflict) {
+            (None, _) => (sound.name.clone(), None),
+            (Some(_), ConflictStrategy::Skip) => {
+                result.skipped.push(sound.name);
+                continue;
+            }
+            (Some(_), ConflictStrategy::Rename) => (free_name(&sound.name, &taken_names), None),
+            (Some(existing), ConflictStrategy::Overwrite) => (sound.name.clone(), Some(existing)),
+        };
+        taken_names.insert(name.clone());
+
+        if let Err(err) = import_sound(
+            guild_id, &gid, &uid, &name, &sound, existing, &quota, out_dir, db,
+        )
+        .await
+        {
+            warn!(?err, name, "Failed to import sound");
+            result.failed.insert(sound.name, err.to_string());
+            continue;
+        }
+
+        match existing {
+            Some(_) => result.overwritten.push(name),
+            None if name != sound.name => {
+                result.renamed.insert(sound.name, name);
+            }
+            None => result.created.push(name),
+        }
+    }
+
+    Ok((manifest.guild_id, result))
+}
+
+/// Where the file of the manifest was extracted to, if it is part of the archive
+fn extracted_path(soundfile: &ManifestSoundfile, out_dir: &Path) -> Option<PathBuf> {
+    Path::new(&soundfile.path)
+        .file_name()
+        .map(|file_name| out_dir.join(sanitize_filename::sanitize(file_name.to_string_lossy())))
+        .filter(|source| source.exists())
+}
+
+/// Imports a single sound with its file. Nothing is changed if that fails: new sounds are removed again
+/// and existing ones are only updated once their file is stored.
+#[allow(clippy::too_many_arguments)]
+async fn import_sound(
+    guild_id: u64,
+    gid: &BigDecimal,
+    uid: &BigDecimal,
+    name: &str,
+    sound: &ManifestSound,
+    existing: Option<(i32, bool)>,
+    quota: &GuildQuota,
+    out_dir: &Path,
+    db: &DbConn,
+) -> Result<(), ArchiveError> {
+    let source = match &sound.file {
+        Some(soundfile) => Some(extracted_path(soundfile, out_dir).ok_or_else(|| {
+            ArchiveError::InvalidArchive(format!("{} is missing", soundfile.path))
+        })?),
+        None => None,
+    };
+
+    let (sound_id, has_file) = match existing {
+        Some(existing) => existing,
+        None => {
+            let gid = gid.clone();
+            let uid = uid.clone();
+            let name = name.to_string();
+            let category = sound.category.clone();
+            let (volume_adjustment, cooldown) = (sound.volume_adjustment, sound.cooldown);
+            let sound_id = db
+                .run(move |c| {
+                    use crate::db::schema::sounds;
+
+                    c.transaction(|c| {
+                        ensure_category(c, &gid, &category)?;
+                        diesel::insert_into(sounds::table)
+                            .values((
+                                sounds::guild_id.eq(&gid),
+                                sounds::name.eq(name),
+                                sounds::category.eq(category),
+                                sounds::volume_adjustment.eq(volume_adjustment),
+                                sounds::cooldown.eq(cooldown),
+                                sounds::created_by_user_id.eq(Some(uid.clone())),
+                                sounds::last_edited_by_user_id.eq(Some(uid)),
+                            ))
+                            .returning(sounds::id)
+                            .get_result::<i32>(c)
+                    })
+                })
+                .await?;
+            (sound_id, false)
+        }
+    };
+
+    // Imported files are analyzed like uploads instead of trusting the manifest
+    if let Some(source) = source {
+        let file_name = new_file_name(guild_id, sound_id, has_file);
+        let store_res =
+            store_sound_file(sound_id, uid.clone(), file_name, &source, quota, db).await;
+        if let Err(err) = store_res {
+            if existing.is_none() {
+                db.run(move |c| {
+                    use crate::db::schema::sounds;
+
+                    diesel::delete(sounds::table.find(sound_id)).execute(c)
+                })
+                .await?;
+            }
+            return Err(ArchiveError::InvalidArchive(err.to_string()));
+        }
+    }
+
+    let gid = gid.clone();
+    let uid = uid.clone();
+    let category = sound.category.clone();
+    let tags = sound.tags.clone();
+    let aliases = sound.aliases.clone();
+    let (volume_adjustment, cooldown) = (sound.volume_adjustment, sound.cooldown);
+    let overwrite = existing.is_some();
+    db.run(move |c| {
+        use crate::db::schema::sounds;
+
+        c.transaction(|c| {
+            if overwrite {
+                ensure_category(c, &gid, &category)?;
+                diesel::update(sounds::table.find(sound_id))
+                    .set((
+                        sounds::category.eq(category),
+                        sounds::volume_adjustment.eq(volume_adjustment),
+                        sounds::cooldown.eq(cooldown),
+                        sounds::last_edited_at.eq(SystemTime::now()),
+                        sounds::last_edited_by_user_id.eq(Some(uid)),
+                    ))
+                    .execute(c)?;
+            }
+            set_tags(c, &gid, sound_id, tags)?;
+            set_aliases(c, sound_id, aliases)
+        })
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("archive-test-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn regular_header(size: usize) -> tar::Header {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(size as u64);
+        header.set_mode(0o644);
+        header
+    }
+
+    /// Sets the path without the validation of the builder, like a crafted archive would
+    fn set_raw_path(header: &mut tar::Header, path: &str) {
+        let name = &mut header.as_old_mut().name;
+        name.fill(0);
+        name[..path.len()].copy_from_slice(path.as_bytes());
+        header.set_cksum();
+    }
+
+    fn read(archive: Vec<u8>, out_dir: &Path) -> Result<Option<Vec<u8>>, std::io::Error> {
+        read_entries(tar::Archive::new(archive.as_slice()), out_dir)
+    }
+
+    #[test]
+    fn extracts_manifest_and_sound_files() {
+        let mut builder = tar::Builder::new(vec![]);
+        builder
+            .append_data(&mut regular_header(2), MANIFEST_FILE, &b"{}"[..])
+            .unwrap();
+        builder
+            .append_data(&mut regular_header(3), "sounds/1.mp3", &b"mp3"[..])
+            .unwrap();
+        builder
+            .append_data(&mut regular_header(5), "other/2.mp3", &b"other"[..])
+            .unwrap();
+        let out_dir = temp_dir();
+
+        let manifest = read(builder.into_inner().unwrap(), &out_dir).unwrap();
+
+        assert_eq!(manifest, Some(b"{}".to_vec()));
+        assert_eq!(std::fs::read(out_dir.join("1.mp3")).unwrap(), b"mp3");
+        assert!(!out_dir.join("2.mp3").exists());
+        std::fs::remove_dir_all(out_dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_symlinks() {
+        let mut builder = tar::Builder::new(vec![]);
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "sounds/1.mp3", "/proc/self/environ")
+            .unwrap();
+        let out_dir = temp_dir();
+
+        let result = read(builder.into_inner().unwrap(), &out_dir);
+
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        assert!(std::fs::symlink_metadata(out_dir.join("1.mp3")).is_err());
+        std::fs::remove_dir_all(out_dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_hardlinks() {
+        let mut builder = tar::Builder::new(vec![]);
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Link);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "sounds/1.mp3", "manifest.json")
+            .unwrap();
+        let out_dir = temp_dir();
+
+        let result = read(builder.into_inner().unwrap(), &out_dir);
+
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        std::fs::remove_dir_all(out_dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_paths_leaving_the_archive() {
+        for path in ["../sounds/1.mp3", "sounds/../../1.mp3", "/sounds/1.mp3"] {
+            let mut header = regular_header(3);
+            set_raw_path(&mut header, path);
+            let mut archive = tar::Builder::new(vec![]);
+            archive.append(&header, &b"mp3"[..]).unwrap();
+            let out_dir = temp_dir();
+
+            let result = read(archive.into_inner().unwrap(), &out_dir);
+
+            assert!(result.is_err(), "{} was accepted", path);
+            assert_eq!(std::fs::read_dir(&out_dir).unwrap().count(), 0);
+            std::fs::remove_dir_all(out_dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn ignores_current_dir_components() {
+        let mut builder = tar::Builder::new(vec![]);
+        builder
+            .append_data(&mut regular_header(3), "./sounds/1.mp3", &b"mp3"[..])
+            .unwrap();
+        let out_dir = temp_dir();
+
+        let manifest = read(builder.into_inner().unwrap(), &out_dir).unwrap();
+
+        assert_eq!(manifest, None);
+        assert_eq!(std::fs::read(out_dir.join("1.mp3")).unwrap(), b"mp3");
+        std::fs::remove_dir_all(out_dir).unwrap();
+    }
+}
@@ -0,0 +1,345 @@
+use std::convert::TryFrom;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use bigdecimal::BigDecimal;
+use bigdecimal::FromPrimitive;
+use bigdecimal::ToPrimitive;
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use rocket::http::Status;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::Json;
+use rocket::Request;
+use rocket::Route;
+use rocket::State;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use serde_with::serde_as;
+use serde_with::TimestampSeconds;
+use serenity::model::id::GuildId;
+use serenity::model::id::UserId as SerenityUserId;
+use thiserror::Error;
+
+use crate::api::auth::UserId;
+use crate::api::Snowflake;
+use crate::db::models;
+use crate::db::DbConn;
+use crate::discord::management::check_guild_capability;
+use crate::discord::management::Capability;
+use crate::discord::management::PermissionError;
+use crate::CacheHttp;
+
+pub fn get_routes() -> Vec<Route> {
+    routes![get_audit_log]
+}
+
+/// Number of entries returned if the request does not specify a limit
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(Debug, Clone, Copy)]
+pub enum AuditAction {
+    SoundCreated,
+    SoundUpdated,
+    SoundDeleted,
+    SoundRestored,
+    SoundUploaded,
+    SoundReverted,
+    SoundVersionDeleted,
+    SoundCopied,
+    SoundCreatedFromRecording,
+    SettingsUpdated,
+    RandomInfixesUpdated,
+    CategoryRestrictionsUpdated,
+    CategoryCreated,
+    CategoryUpdated,
+    CategoryDeleted,
+    CategoriesRenamed,
+    SoundboardImported,
+    RecordingSaved,
+    RecordingDeleted,
+    RecordingPinned,
+    RecordingUnpinned,
+    RecordingPurged,
+    PlaybackStarted,
+    PlaybackStopped,
+    ChannelJoined,
+    ChannelLeft,
+}
+
+impl AuditAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::SoundCreated => "sound_created",
+            Self::SoundUpdated => "sound_updated",
+            Self::SoundDeleted => "sound_deleted",
+            Self::SoundRestored => "sound_restored",
+            Self::SoundUploaded => "sound_uploaded",
+            Self::SoundReverted => "sound_reverted",
+            Self::SoundVersionDeleted => "sound_version_deleted",
+            Self::SoundCopied => "sound_copied",
+            Self::SoundCreatedFromRecording => "sound_created_from_recording",
+            Self::SettingsUpdated => "settings_updated",
+            Self::RandomInfixesUpdated => "random_infixes_updated",
+            Self::CategoryRestrictionsUpdated => "category_restrictions_updated",
+            Self::CategoryCreated => "category_created",
+            Self::CategoryUpdated => "category_updated",
+            Self::CategoryDeleted => "category_deleted",
+            Self::CategoriesRenamed => "categories_renamed",
+            Self::SoundboardImported => "soundboard_imported",
+            Self::RecordingSaved => "recording_saved",
+            Self::RecordingDeleted => "recording_deleted",
+            Self::RecordingPinned => "recording_pinned",
+            Self::RecordingUnpinned => "recording_unpinned",
+            Self::RecordingPurged => "recording_purged",
+            Self::PlaybackStarted => "playback_started",
+            Self::PlaybackStopped => "playback_stopped",
+            Self::ChannelJoined => "channel_joined",
+            Self::ChannelLeft => "channel_left",
+        }
+    }
+}
+
+/// A single entry to be written to the audit log
+#[derive(Debug)]
+pub struct AuditEntry {
+    pub guild_id: GuildId,
+    /// `None` for actions of the system, e.g. the retention policy
+    pub user_id: Option<SerenityUserId>,
+    pub action: AuditAction,
+    pub target: Option<String>,
+    pub before: Option<JsonValue>,
+    pub after: Option<JsonValue>,
+}
+
+impl AuditEntry {
+    pub fn new(guild_id: GuildId, user_id: SerenityUserId, action: AuditAction) -> Self {
+        Self {
+            guild_id,
+            user_id: Some(user_id),
+            action,
+            target: None,
+            before: None,
+            after: None,
+        }
+    }
+
+    /// An action that was not triggered by a user
+    pub fn system(guild_id: GuildId, action: AuditAction) -> Self {
+        Self {
+            guild_id,
+            user_id: None,
+            action,
+            target: None,
+            before: None,
+            after: None,
+        }
+    }
+
+    pub fn target(mut self, target: impl ToString) -> Self {
+        self.target = Some(target.to_string());
+        self
+    }
+
+    pub fn before(mut self, before: impl Serialize) -> Self {
+        self.before = serde_json::to_value(before).ok();
+        self
+    }
+
+    pub fn after(mut self, after: impl Serialize) -> Self {
+        self.after = serde_json::to_value(after).ok();
+        self
+    }
+}
+
+/// Writes an entry to the audit log. Failing to do so does not fail the action that is logged.
+#[instrument(skip(db))]
+pub async fn log(db: &DbConn, entry: AuditEntry) {
+    let (gid, uid) = match (
+        BigDecimal::from_u64(entry.guild_id.get()),
+        entry
+            .user_id
+            .map(|user_id| BigDecimal::from_u64(user_id.get())),
+    ) {
+        (Some(gid), Some(Some(uid))) => (gid, Some(uid)),
+        (Some(gid), None) => (gid, None),
+        _ => {
+            warn!("Failed to convert ids for audit log");
+            return;
+        }
+    };
+
+    let result = db
+        .run(move |c| {
+            use crate::db::schema::auditlog;
+
+            diesel::insert_into(auditlog::table)
+                .values((
+                    auditlog::guild_id.eq(gid),
+                    auditlog::user_id.eq(uid),
+                    auditlog::action.eq(entry.action.as_str()),
+                    auditlog::target.eq(entry.target),
+                    auditlog::before.eq(entry.before),
+                    auditlog::after.eq(entry.after),
+                ))
+                .execute(c)
+        })
+        .await;
+
+    if let Err(err) = result {
+        error!(?err, "Failed to write audit log");
+    }
+}
+
+#[derive(Debug, Error)]
+enum AuditLogError {
+    #[error("Number handling error")]
+    NumericalError,
+
+    #[error("Invalid parameter: {0}")]
+    InvalidParameter(String),
+
+    #[error("Database error: {0}")]
+    DieselError(#[from] DieselError),
+
+    #[error("Insufficient permission: you do not have the permission to perform this action")]
+    InsufficientPermission(#[from] PermissionError),
+}
+
+impl AuditLogError {
+    fn status_code(&self) -> Status {
+        match self {
+            Self::NumericalError => Status::InternalServerError,
+            Self::InvalidParameter(_) => Status::BadRequest,
+            Self::DieselError(_) => Status::InternalServerError,
+            Self::InsufficientPermission(_) => Status::Forbidden,
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for AuditLogError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.status_code();
+        let error_message = self.to_string();
+
+        Response::build_from(error_message.respond_to(req)?)
+            .status(status)
+            .ok()
+    }
+}
+
+#[serde_as]
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AuditLogEntry {
+    id: i32,
+    guild_id: Snowflake,
+    user_id: Option<Snowflake>,
+    action: String,
+    target: Option<String>,
+    before: Option<JsonValue>,
+    after: Option<JsonValue>,
+    #[serde_as(as = "TimestampSeconds<String>")]
+    created_at: SystemTime,
+}
+
+impl TryFrom<models::AuditLogEntry> for AuditLogEntry {
+    type Error = AuditLogError;
+
+    fn try_from(entry: models::AuditLogEntry) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: entry.id,
+            guild_id: Snowflake(
+                entry
+                    .guild_id
+                    .to_u64()
+                    .ok_or(AuditLogError::NumericalError)?,
+            ),
+            user_id: entry
+                .user_id
+                .map(|uid| uid.to_u64().ok_or(AuditLogError::NumericalError))
+                .transpose()?
+                .map(Snowflake),
+            action: entry.action,
+            target: entry.target,
+            before: entry.before,
+            after: entry.after,
+            created_at: entry.created_at,
+        })
+    }
+}
+
+/// Entries are returned newest first. To get the next page, pass the id of the last entry as `before`.
+/// `from` and `to` are unix timestamps in seconds.
+#[allow(clippy::too_many_arguments)]
+#[get("/guilds/<guild_id>/audit-log?<action>&<user_id>&<from>&<to>&<before>&<limit>")]
+async fn get_audit_log(
+    guild_id: u64,
+    action: Option<String>,
+    user_id: Option<u64>,
+    from: Option<u64>,
+    to: Option<u64>,
+    before: Option<i32>,
+    limit: Option<i64>,
+    user: UserId,
+    db: DbConn,
+    cache_http: &State<CacheHttp>,
+) -> Result<Json<Vec<AuditLogEntry>>, AuditLogError> {
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.into(),
+        GuildId::new(guild_id),
+        Capability::ViewAuditLog,
+    )
+    .await?;
+
+    let gid = BigDecimal::from_u64(guild_id).ok_or(AuditLogError::NumericalError)?;
+    let uid = user_id
+        .map(|uid| BigDecimal::from_u64(uid).ok_or(AuditLogError::NumericalError))
+        .transpose()?;
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let to_time = |secs: u64| {
+        SystemTime::UNIX_EPOCH
+            .checked_add(Duration::from_secs(secs))
+            .ok_or_else(|| AuditLogError::InvalidParameter(format!("Invalid timestamp {secs}")))
+    };
+    let from = from.map(to_time).transpose()?;
+    let to = to.map(to_time).transpose()?;
+
+    let entries = db
+        .run(move |c| {
+            use crate::db::schema::auditlog;
+
+            let mut query = auditlog::table
+                .filter(auditlog::guild_id.eq(gid))
+                .into_boxed();
+            if let Some(action) = action {
+                query = query.filter(auditlog::action.eq(action));
+            }
+            if let Some(uid) = uid {
+                query = query.filter(auditlog::user_id.eq(uid));
+            }
+            if let Some(from) = from {
+                query = query.filter(auditlog::created_at.ge(from));
+            }
+            if let Some(to) = to {
+                query = query.filter(auditlog::created_at.lt(to));
+            }
+            if let Some(before) = before {
+                query = query.filter(auditlog::id.lt(before));
+            }
+
+            query
+                .order(auditlog::id.desc())
+                .limit(limit)
+                .load::<models::AuditLogEntry>(c)
+        })
+        .await?
+        .into_iter()
+        .map(AuditLogEntry::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Json(entries))
+}
@@ -1,5 +1,8 @@
+use std::collections::HashSet;
+use std::env::var;
 use std::error::Error as StdError;
 use std::iter;
+use std::sync::LazyLock;
 use std::time::{Duration, SystemTime};
 
 use bigdecimal::BigDecimal;
@@ -49,11 +52,20 @@ use crate::api::DISCORD_CLIENT_SECRET;
 use crate::db::models;
 use crate::db::DbConn;
 use crate::discord::management::get_guilds_for_user;
+use crate::discord::management::Capability;
 use crate::discord::management::UserPermission;
 use crate::CacheHttp;
 use crate::BASE_URL;
 
 static SESSION_COOKIE: &str = "auth_session";
+// Discord users operating this instance of the bot, separated by commas
+static OPERATOR_USER_IDS: LazyLock<HashSet<u64>> = LazyLock::new(|| {
+    var("OPERATOR_USER_IDS")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|id| id.trim().parse::<u64>().ok())
+        .collect()
+});
 static LOGIN_COOKIE: &str = "auth_login";
 
 // Type alias for a BasicClient with auth and token endpoints configured
@@ -151,14 +163,42 @@ impl<'r> FromRequest<'r> for UserId {
     }
 }
 
+/// A user operating this instance of the bot. Endpoints with information about all guilds require it.
+#[derive(Debug, Clone)]
+pub struct OperatorUserId;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for OperatorUserId {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let user = try_outcome!(request.guard::<UserId>().await);
+        if OPERATOR_USER_IDS.contains(&user.0) {
+            Outcome::Success(Self)
+        } else {
+            Outcome::Error((Status::Forbidden, ()))
+        }
+    }
+}
+
 /// This represents a user that has authenticated using an auth token. Currently, there are is only one type of token
 /// that has limited permissions. This struct is used to distinguish it from regular cookie authentication.
 #[derive(Debug, Clone, Copy)]
-pub struct TokenUserId(u64);
+pub struct TokenUserId {
+    user_id: u64,
+    via_token: bool,
+}
+
+impl TokenUserId {
+    /// Whether the user authenticated using an auth token instead of a session
+    pub fn via_token(&self) -> bool {
+        self.via_token
+    }
+}
 
 impl From<TokenUserId> for SerenityUserId {
     fn from(user: TokenUserId) -> Self {
-        SerenityUserId::new(user.0)
+        SerenityUserId::new(user.user_id)
     }
 }
 
@@ -189,7 +229,10 @@ impl<'r> FromRequest<'r> for TokenUserId {
                 .await
                 .ok()
                 .and_then(|auth_token| auth_token.user_id.to_u64())
-                .map(TokenUserId);
+                .map(|user_id| TokenUserId {
+                    user_id,
+                    via_token: true,
+                });
 
             if let Some(uid) = res {
                 return Outcome::Success(uid);
@@ -197,10 +240,10 @@ impl<'r> FromRequest<'r> for TokenUserId {
         }
 
         // If there is no auth token, we try to parse a normal UserId from a session
-        request
-            .guard::<UserId>()
-            .await
-            .map(|user_id| TokenUserId(user_id.0))
+        request.guard::<UserId>().await.map(|user_id| TokenUserId {
+            user_id: user_id.0,
+            via_token: false,
+        })
     }
 }
 
@@ -300,6 +343,7 @@ struct GuildInfo {
     name: String,
     icon_url: Option<String>,
     role: UserPermission,
+    capabilities: Vec<Capability>,
 }
 
 impl Serialize for UserPermission {
@@ -307,11 +351,7 @@ impl Serialize for UserPermission {
     where
         S: Serializer,
     {
-        serializer.serialize_str(match *self {
-            UserPermission::Admin => "admin",
-            UserPermission::Moderator => "moderator",
-            UserPermission::User => "user",
-        })
+        serializer.serialize_str(self.as_str())
     }
 }
 
@@ -339,7 +379,8 @@ async fn user(
             id: Snowflake(guild.id.get()),
             icon_url: guild.icon_url(),
             name: guild.name,
-            role: perm,
+            role: perm.permission,
+            capabilities: perm.capabilities,
         });
     }
 
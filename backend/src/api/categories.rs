@@ -0,0 +1,671 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use bigdecimal::BigDecimal;
+use bigdecimal::FromPrimitive;
+use bigdecimal::ToPrimitive;
+use diesel::prelude::*;
+use diesel::result::DatabaseErrorKind;
+use diesel::result::Error as DieselError;
+use rocket::http::Status;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::Json;
+use rocket::Request;
+use rocket::Route;
+use rocket::State;
+use serde::Deserialize;
+use serde::Serialize;
+use serenity::model::id::GuildId;
+use thiserror::Error;
+
+use crate::api::audit_log;
+use crate::api::audit_log::AuditAction;
+use crate::api::audit_log::AuditEntry;
+use crate::api::auth::UserId;
+use crate::api::Snowflake;
+use crate::db::models;
+use crate::db::DbConn;
+use crate::discord::management::check_guild_capability;
+use crate::discord::management::get_permission_level;
+use crate::discord::management::Capability;
+use crate::discord::management::PermissionError;
+use crate::CacheHttp;
+
+pub fn get_routes() -> Vec<Route> {
+    routes![
+        list_categories,
+        create_category,
+        update_category,
+        delete_category,
+        rename_categories
+    ]
+}
+
+const MAX_NAME_LENGTH: usize = 64;
+const MAX_COLOR_LENGTH: usize = 16;
+const MAX_EMOJI_LENGTH: usize = 64;
+
+#[derive(Debug, Error)]
+enum CategoriesError {
+    #[error("Number handling error")]
+    NumericalError,
+
+    #[error("Database error: {0}")]
+    DieselError(DieselError),
+
+    #[error("Insufficient permission: you do not have the permission to perform this action")]
+    InsufficientPermission(#[from] PermissionError),
+
+    #[error("Not found: a category with the given id does not exist")]
+    NotFound,
+
+    #[error("Not found: there is no category named {0}")]
+    NameNotFound(String),
+
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Invalid parameter: {0}")]
+    InvalidParameter(String),
+}
+
+impl From<DieselError> for CategoriesError {
+    fn from(err: DieselError) -> Self {
+        match err {
+            DieselError::NotFound => Self::NotFound,
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => Self::Conflict(
+                String::from("a category with this name already exists in the guild"),
+            ),
+            err => Self::DieselError(err),
+        }
+    }
+}
+
+impl CategoriesError {
+    fn status_code(&self) -> Status {
+        match self {
+            Self::NumericalError => Status::InternalServerError,
+            Self::DieselError(_) => Status::InternalServerError,
+            Self::InsufficientPermission(_) => Status::Forbidden,
+            Self::NotFound => Status::NotFound,
+            Self::NameNotFound(_) => Status::NotFound,
+            Self::Conflict(_) => Status::Conflict,
+            Self::InvalidParameter(_) => Status::BadRequest,
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for CategoriesError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.status_code();
+        let error_message = self.to_string();
+
+        Response::build_from(error_message.respond_to(req)?)
+            .status(status)
+            .ok()
+    }
+}
+
+/// Creates the category in the guild if it does not exist yet. Sounds and category restrictions
+/// reference their category, so this has to be called before assigning a category to them.
+pub fn ensure_category(
+    c: &mut PgConnection,
+    guild_id: &BigDecimal,
+    name: &str,
+) -> Result<(), DieselError> {
+    use crate::db::schema::categories;
+
+    diesel::insert_into(categories::table)
+        .values((categories::guild_id.eq(guild_id), categories::name.eq(name)))
+        .on_conflict((categories::guild_id, categories::name))
+        .do_nothing()
+        .execute(c)
+        .map(|_| ())
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Category {
+    id: i32,
+    guild_id: Snowflake,
+    name: String,
+    display_order: i32,
+    color: Option<String>,
+    emoji: Option<String>,
+    description: Option<String>,
+    sound_count: i64,
+}
+
+impl TryFrom<(models::Category, i64)> for Category {
+    type Error = CategoriesError;
+
+    fn try_from((category, sound_count): (models::Category, i64)) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: category.id,
+            guild_id: Snowflake(
+                category
+                    .guild_id
+                    .to_u64()
+                    .ok_or(CategoriesError::NumericalError)?,
+            ),
+            name: category.name,
+            display_order: category.display_order,
+            color: category.color,
+            emoji: category.emoji,
+            description: category.description,
+            sound_count,
+        })
+    }
+}
+
+/// Trims the name and checks that it is neither empty nor too long. Otherwise, the reason is returned.
+pub fn normalize_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        Err(String::from("the name of a category must not be empty"))
+    } else if name.chars().count() > MAX_NAME_LENGTH {
+        Err(format!(
+            "the name of a category must not be longer than {} characters",
+            MAX_NAME_LENGTH
+        ))
+    } else {
+        Ok(name.to_string())
+    }
+}
+
+fn validate_name(name: &str) -> Result<String, CategoriesError> {
+    normalize_name(name).map_err(CategoriesError::InvalidParameter)
+}
+
+/// Checks that an optional attribute of a category fits into its column
+fn validate_length(
+    attribute: &str,
+    value: Option<&str>,
+    max_length: usize,
+) -> Result<(), CategoriesError> {
+    if value.is_some_and(|value| value.chars().count() > max_length) {
+        Err(CategoriesError::InvalidParameter(format!(
+            "the {} of a category must not be longer than {} characters",
+            attribute, max_length
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Loads a category together with the number of sounds in it, trashed sounds excluded
+fn load_category(
+    c: &mut PgConnection,
+    category: models::Category,
+) -> Result<Category, CategoriesError> {
+    use crate::db::schema::sounds;
+
+    let sound_count = sounds::table
+        .filter(sounds::guild_id.eq(&category.guild_id))
+        .filter(sounds::category.eq(&category.name))
+        .filter(sounds::deleted_at.is_null())
+        .count()
+        .get_result::<i64>(c)?;
+
+    Category::try_from((category, sound_count))
+}
+
+/// Categories are ordered by their display order and then by name
+#[get("/guilds/<guild_id>/categories")]
+async fn list_categories(
+    guild_id: u64,
+    user: UserId,
+    db: DbConn,
+    cache_http: &State<CacheHttp>,
+) -> Result<Json<Vec<Category>>, CategoriesError> {
+    get_permission_level(cache_http.inner(), &db, user.into(), GuildId::new(guild_id)).await?;
+
+    let gid = BigDecimal::from_u64(guild_id).ok_or(CategoriesError::NumericalError)?;
+    let categories = db
+        .run(move |c| {
+            use crate::db::schema::categories;
+            use crate::db::schema::sounds;
+
+            let categories = categories::table
+                .filter(categories::guild_id.eq(&gid))
+                .order((categories::display_order.asc(), categories::name.asc()))
+                .load::<models::Category>(c)?;
+            let sound_counts = sounds::table
+                .filter(sounds::guild_id.eq(&gid))
+                .filter(sounds::deleted_at.is_null())
+                .group_by(sounds::category)
+                .select((sounds::category, diesel::dsl::count_star()))
+                .load::<(String, i64)>(c)?
+                .into_iter()
+                .collect::<HashMap<_, _>>();
+
+            Ok::<_, DieselError>((categories, sound_counts))
+        })
+        .await
+        .map_err(CategoriesError::from)
+        .and_then(|(categories, sound_counts)| {
+            categories
+                .into_iter()
+                .map(|category| {
+                    let sound_count = sound_counts.get(&category.name).copied().unwrap_or(0);
+                    Category::try_from((category, sound_count))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+
+    Ok(Json(categories))
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CreateCategoryParameter {
+    name: String,
+    display_order: Option<i32>,
+    color: Option<String>,
+    emoji: Option<String>,
+    description: Option<String>,
+}
+
+/// New categories are placed after the existing ones unless a display order is given
+#[post("/guilds/<guild_id>/categories", format = "json", data = "<params>")]
+async fn create_category(
+    guild_id: u64,
+    user: UserId,
+    db: DbConn,
+    cache_http: &State<CacheHttp>,
+    params: Json<CreateCategoryParameter>,
+) -> Result<Json<Category>, CategoriesError> {
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.clone().into(),
+        GuildId::new(guild_id),
+        Capability::ManageSounds,
+    )
+    .await?;
+
+    let params = params.into_inner();
+    let name = validate_name(&params.name)?;
+    validate_length("color", params.color.as_deref(), MAX_COLOR_LENGTH)?;
+    validate_length("emoji", params.emoji.as_deref(), MAX_EMOJI_LENGTH)?;
+    let gid = BigDecimal::from_u64(guild_id).ok_or(CategoriesError::NumericalError)?;
+    let category = db
+        .run(move |c| {
+            use crate::db::schema::categories;
+
+            let display_order = match params.display_order {
+                Some(display_order) => display_order,
+                None => categories::table
+                    .filter(categories::guild_id.eq(&gid))
+                    .select(diesel::dsl::max(categories::display_order))
+                    .first::<Option<i32>>(c)?
+                    .map(|max| max + 1)
+                    .unwrap_or(0),
+            };
+
+            let category = diesel::insert_into(categories::table)
+                .values((
+                    categories::guild_id.eq(gid),
+                    categories::name.eq(name),
+                    categories::display_order.eq(display_order),
+                    categories::color.eq(params.color),
+                    categories::emoji.eq(params.emoji),
+                    categories::description.eq(params.description),
+                ))
+                .get_result::<models::Category>(c)?;
+            Category::try_from((category, 0))
+        })
+        .await?;
+
+    audit_log::log(
+        &db,
+        AuditEntry::new(
+            GuildId::new(guild_id),
+            user.into(),
+            AuditAction::CategoryCreated,
+        )
+        .target(&category.name)
+        .after(&category),
+    )
+    .await;
+
+    Ok(Json(category))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct UpdateCategoryParameter {
+    name: Option<String>,
+    display_order: Option<i32>,
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    color: Option<Option<String>>,
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    emoji: Option<Option<String>>,
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    description: Option<Option<String>>,
+}
+
+/// Renaming a category also renames it on all of its sounds and category restrictions
+#[put(
+    "/guilds/<guild_id>/categories/<category_id>",
+    format = "json",
+    data = "<params>"
+)]
+async fn update_category(
+    guild_id: u64,
+    category_id: i32,
+    user: UserId,
+    db: DbConn,
+    cache_http: &State<CacheHttp>,
+    params: Json<UpdateCategoryParameter>,
+) -> Result<(), CategoriesError> {
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.clone().into(),
+        GuildId::new(guild_id),
+        Capability::ManageSounds,
+    )
+    .await?;
+
+    let params = params.into_inner();
+    if params.name.is_none()
+        && params.display_order.is_none()
+        && params.color.is_none()
+        && params.emoji.is_none()
+        && params.description.is_none()
+    {
+        return Err(CategoriesError::InvalidParameter(String::from(
+            "at least one attribute of the category must be changed",
+        )));
+    }
+    validate_length(
+        "color",
+        params.color.as_ref().and_then(Option::as_deref),
+        MAX_COLOR_LENGTH,
+    )?;
+    validate_length(
+        "emoji",
+        params.emoji.as_ref().and_then(Option::as_deref),
+        MAX_EMOJI_LENGTH,
+    )?;
+    let changeset = models::CategoryChangeset {
+        name: params.name.as_deref().map(validate_name).transpose()?,
+        display_order: params.display_order,
+        color: params.color,
+        emoji: params.emoji,
+        description: params.description,
+    };
+    let gid = BigDecimal::from_u64(guild_id).ok_or(CategoriesError::NumericalError)?;
+    let (before, after) = db
+        .run(move |c| {
+            use crate::db::schema::categories;
+
+            c.transaction(|c| {
+                let before = categories::table
+                    .find(category_id)
+                    .filter(categories::guild_id.eq(&gid))
+                    .first::<models::Category>(c)?;
+                let after = diesel::update(categories::table.find(category_id))
+                    .set(&changeset)
+                    .get_result::<models::Category>(c)?;
+
+                Ok::<_, CategoriesError>((load_category(c, before)?, load_category(c, after)?))
+            })
+        })
+        .await?;
+
+    audit_log::log(
+        &db,
+        AuditEntry::new(
+            GuildId::new(guild_id),
+            user.into(),
+            AuditAction::CategoryUpdated,
+        )
+        .target(category_id)
+        .before(before)
+        .after(after),
+    )
+    .await;
+
+    Ok(())
+}
+
+/// Only categories without sounds, including the ones in the trash, can be deleted. Their category
+/// restrictions are removed as well.
+#[delete("/guilds/<guild_id>/categories/<category_id>")]
+async fn delete_category(
+    guild_id: u64,
+    category_id: i32,
+    user: UserId,
+    db: DbConn,
+    cache_http: &State<CacheHttp>,
+) -> Result<(), CategoriesError> {
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.clone().into(),
+        GuildId::new(guild_id),
+        Capability::ManageSounds,
+    )
+    .await?;
+
+    let gid = BigDecimal::from_u64(guild_id).ok_or(CategoriesError::NumericalError)?;
+    let category = db
+        .run(move |c| {
+            use crate::db::schema::categories;
+            use crate::db::schema::sounds;
+
+            c.transaction(|c| {
+                let category = categories::table
+                    .find(category_id)
+                    .filter(categories::guild_id.eq(&gid))
+                    .first::<models::Category>(c)?;
+
+                let sound_count = sounds::table
+                    .filter(sounds::guild_id.eq(&gid))
+                    .filter(sounds::category.eq(&category.name))
+                    .count()
+                    .get_result::<i64>(c)?;
+                if sound_count > 0 {
+                    return Err(CategoriesError::Conflict(format!(
+                        "the category still contains {} sounds, including the ones in the trash",
+                        sound_count
+                    )));
+                }
+
+                diesel::delete(categories::table.find(category_id)).execute(c)?;
+                Category::try_from((category, 0))
+            })
+        })
+        .await?;
+
+    audit_log::log(
+        &db,
+        AuditEntry::new(
+            GuildId::new(guild_id),
+            user.into(),
+            AuditAction::CategoryDeleted,
+        )
+        .target(category_id)
+        .before(category),
+    )
+    .await;
+
+    Ok(())
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RenameCategoryParameter {
+    from: String,
+    to: String,
+}
+
+/// Renames are applied one after another. A category that is renamed must not be the target of another rename,
+/// as e.g. swapping two names would merge both categories into one.
+fn check_renames(renames: &[(String, String)]) -> Result<(), CategoriesError> {
+    let renames = renames
+        .iter()
+        .filter(|(from, to)| from != to)
+        .collect::<Vec<_>>();
+    match renames
+        .iter()
+        .find(|(_, to)| renames.iter().any(|(from, _)| from == to))
+    {
+        Some((_, to)) => Err(CategoriesError::InvalidParameter(format!(
+            "the category {} is renamed and must not be the target of another rename",
+            to
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Renames several categories at once. If a category with the new name already exists, the categories
+/// are merged: the sounds are moved to the existing category and the renamed category is removed. The
+/// category restrictions of the existing category take precedence in that case.
+#[post(
+    "/guilds/<guild_id>/categories/rename",
+    format = "json",
+    data = "<params>"
+)]
+async fn rename_categories(
+    guild_id: u64,
+    user: UserId,
+    db: DbConn,
+    cache_http: &State<CacheHttp>,
+    params: Json<Vec<RenameCategoryParameter>>,
+) -> Result<Json<Vec<Category>>, CategoriesError> {
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.clone().into(),
+        GuildId::new(guild_id),
+        Capability::ManageSounds,
+    )
+    .await?;
+
+    let params = params
+        .into_inner()
+        .into_iter()
+        .map(|rename| {
+            Ok(RenameCategoryParameter {
+                from: rename.from,
+                to: validate_name(&rename.to)?,
+            })
+        })
+        .collect::<Result<Vec<_>, CategoriesError>>()?;
+    let renames = params
+        .iter()
+        .map(|rename| (rename.from.clone(), rename.to.clone()))
+        .collect::<Vec<_>>();
+    check_renames(&renames)?;
+    let gid = BigDecimal::from_u64(guild_id).ok_or(CategoriesError::NumericalError)?;
+    let categories = db
+        .run(move |c| {
+            use crate::db::schema::categories;
+            use crate::db::schema::categoryrestrictions;
+            use crate::db::schema::sounds;
+
+            c.transaction(|c| {
+                for (from, to) in renames.iter().filter(|(from, to)| from != to) {
+                    let source = categories::table
+                        .filter(categories::guild_id.eq(&gid))
+                        .filter(categories::name.eq(from))
+                        .first::<models::Category>(c)
+                        .optional()?
+                        .ok_or_else(|| CategoriesError::NameNotFound(from.clone()))?;
+                    let target = categories::table
+                        .filter(categories::guild_id.eq(&gid))
+                        .filter(categories::name.eq(to))
+                        .first::<models::Category>(c)
+                        .optional()?;
+
+                    if target.is_none() {
+                        // Sounds and category restrictions follow the new name
+                        diesel::update(categories::table.find(source.id))
+                            .set(categories::name.eq(to))
+                            .execute(c)?;
+                        continue;
+                    }
+
+                    diesel::update(
+                        sounds::table
+                            .filter(sounds::guild_id.eq(&gid))
+                            .filter(sounds::category.eq(from)),
+                    )
+                    .set(sounds::category.eq(to))
+                    .execute(c)?;
+
+                    let target_restricted = categoryrestrictions::table
+                        .find((&gid, to))
+                        .first::<models::CategoryRestriction>(c)
+                        .optional()?
+                        .is_some();
+                    if !target_restricted {
+                        diesel::update(categoryrestrictions::table.find((&gid, from)))
+                            .set(categoryrestrictions::category.eq(to))
+                            .execute(c)?;
+                    }
+
+                    // Remaining restrictions of the source are removed by the cascade
+                    diesel::delete(categories::table.find(source.id)).execute(c)?;
+                }
+
+                let targets = renames.iter().map(|(_, to)| to.clone()).collect::<Vec<_>>();
+                categories::table
+                    .filter(categories::guild_id.eq(&gid))
+                    .filter(categories::name.eq_any(targets))
+                    .order((categories::display_order.asc(), categories::name.asc()))
+                    .load::<models::Category>(c)?
+                    .into_iter()
+                    .map(|category| load_category(c, category))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+        })
+        .await?;
+
+    audit_log::log(
+        &db,
+        AuditEntry::new(
+            GuildId::new(guild_id),
+            user.into(),
+            AuditAction::CategoriesRenamed,
+        )
+        .after(params),
+    )
+    .await;
+
+    Ok(Json(categories))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_trimmed_and_limited() {
+        assert_eq!(validate_name("  Memes ").unwrap(), "Memes");
+        assert!(validate_name("   ").is_err());
+        assert!(validate_name(&"ä".repeat(MAX_NAME_LENGTH)).is_ok());
+        assert!(validate_name(&"ä".repeat(MAX_NAME_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    fn renamed_categories_must_not_be_targets() {
+        let rename = |from: &str, to: &str| (from.to_string(), to.to_string());
+
+        assert!(check_renames(&[rename("a", "b"), rename("c", "b")]).is_ok());
+        assert!(check_renames(&[rename("a", "a"), rename("b", "a")]).is_ok());
+        assert!(check_renames(&[rename("a", "b"), rename("b", "a")]).is_err());
+        assert!(check_renames(&[rename("a", "b"), rename("b", "c")]).is_err());
+    }
+
+    #[test]
+    fn attributes_are_limited_to_their_columns() {
+        assert!(validate_length("color", None, MAX_COLOR_LENGTH).is_ok());
+        assert!(validate_length("color", Some("#ff0000"), MAX_COLOR_LENGTH).is_ok());
+        assert!(validate_length("color", Some(&"f".repeat(17)), MAX_COLOR_LENGTH).is_err());
+        assert!(validate_length("emoji", Some("🔊"), MAX_EMOJI_LENGTH).is_ok());
+    }
+}
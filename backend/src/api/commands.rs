@@ -1,14 +1,27 @@
+use crate::api::audit_log;
+use crate::api::audit_log::AuditAction;
+use crate::api::audit_log::AuditEntry;
 use crate::api::auth::TokenUserId;
+use crate::api::quotas;
+use crate::api::quotas::QuotaError;
+use crate::api::rate_limiter::PlaybackLimits;
+use crate::api::rate_limiter::RateLimiter;
+use crate::api::stats;
+use crate::api::stats::PlaySource;
 use crate::api::EventBus;
 use crate::db::models;
 use crate::db::DbConn;
 use crate::discord::client::Client;
 use crate::discord::client::ClientError;
-use crate::discord::management::check_guild_user;
+use crate::discord::management::check_guild_capability;
+use crate::discord::management::check_play_restrictions;
+use crate::discord::management::Capability;
 use crate::discord::management::PermissionError;
+use crate::discord::management::UserPermission;
 use crate::discord::recorder::RecordingError;
 use crate::discord::CacheHttp;
 use crate::file_handling;
+use crate::storage::STORAGE;
 use bigdecimal::BigDecimal;
 use bigdecimal::FromPrimitive;
 use bigdecimal::ToPrimitive;
@@ -19,7 +32,9 @@ use rocket::response::{self, Responder, Response};
 use rocket::Request;
 use rocket::Route;
 use rocket::State;
+use serde_json::json;
 use serenity::model::id::GuildId;
+use std::time::Duration;
 use thiserror::Error;
 
 pub fn get_routes() -> Vec<Route> {
@@ -28,8 +43,14 @@ pub fn get_routes() -> Vec<Route> {
 
 #[derive(Debug, Error)]
 enum CommandError {
-    #[error("Not a member: you must be a member of the guild to perform this task")]
-    NotAMember(#[from] PermissionError),
+    #[error("Insufficient permission: you do not have the permission to perform this action")]
+    InsufficientPermission(#[from] PermissionError),
+
+    #[error("Playback restricted: {0}")]
+    PlaybackRestricted(PermissionError),
+
+    #[error("Rate limited: try again in {} seconds", .0.as_secs_f32().ceil())]
+    RateLimited(Duration),
 
     #[error("Failed to stop playback: {0}")]
     StopPlaybackError(#[from] ClientError),
@@ -40,18 +61,28 @@ enum CommandError {
     #[error("Database error: {0}")]
     DieselError(#[from] DieselError),
 
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+
     #[error("Number handling error")]
     BigDecimalError,
+
+    #[error("{0}")]
+    QuotaError(#[from] QuotaError),
 }
 
 impl CommandError {
     fn status_code(&self) -> Status {
         match self {
-            Self::NotAMember(_) => Status::Forbidden,
+            Self::InsufficientPermission(_) => Status::Forbidden,
+            Self::PlaybackRestricted(_) => Status::Forbidden,
+            Self::RateLimited(_) => Status::TooManyRequests,
             Self::StopPlaybackError(_) => Status::InternalServerError,
             Self::RecordingError(_) => Status::InternalServerError,
             Self::DieselError(_) => Status::InternalServerError,
+            Self::IoError(_) => Status::InternalServerError,
             Self::BigDecimalError => Status::InternalServerError,
+            Self::QuotaError(err) => err.status_code(),
         }
     }
 }
@@ -61,9 +92,15 @@ impl<'r> Responder<'r, 'static> for CommandError {
         let status = self.status_code();
         let error_message = self.to_string();
 
-        Response::build_from(error_message.respond_to(req)?)
-            .status(status)
-            .ok()
+        let mut response = Response::build_from(error_message.respond_to(req)?);
+        response.status(status);
+        if let Self::RateLimited(retry_after) = self {
+            response.raw_header(
+                "Retry-After",
+                (retry_after.as_secs_f32().ceil() as u64).to_string(),
+            );
+        }
+        response.ok()
     }
 }
 
@@ -77,16 +114,27 @@ async fn join(
     user: TokenUserId,
 ) -> Result<String, CommandError> {
     let guild_id = GuildId::new(guild_id);
-    let permission = check_guild_user(cache_http.inner(), &db, user.into(), guild_id).await?;
+    let permission = check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.into(),
+        guild_id,
+        Capability::Play,
+    )
+    .await?;
 
     let (channel_id, _) = client.join_user(guild_id, user.into(), cache_http).await?;
-    event_bus.channel_joined(
-        &permission.member,
-        channel_id
-            .name(cache_http.inner())
-            .await
-            .unwrap_or_else(|_| String::from("")),
-    );
+    let channel_name = channel_id
+        .name(cache_http.inner())
+        .await
+        .unwrap_or_else(|_| String::from(""));
+
+    audit_log::log(
+        &db,
+        AuditEntry::new(guild_id, user.into(), AuditAction::ChannelJoined).target(&channel_name),
+    )
+    .await;
+    event_bus.channel_joined(&permission.member, channel_name);
 
     Ok(String::from("Joined channel"))
 }
@@ -101,9 +149,22 @@ async fn leave(
     user: TokenUserId,
 ) -> Result<String, CommandError> {
     let guild_id = GuildId::new(guild_id);
-    let permission = check_guild_user(cache_http.inner(), &db, user.into(), guild_id).await?;
+    let permission = check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.into(),
+        guild_id,
+        Capability::Play,
+    )
+    .await?;
 
     client.leave(guild_id).await?;
+
+    audit_log::log(
+        &db,
+        AuditEntry::new(guild_id, user.into(), AuditAction::ChannelLeft),
+    )
+    .await;
     event_bus.channel_left(&permission.member);
 
     Ok(String::from("Left channel"))
@@ -119,9 +180,22 @@ async fn stop(
     user: TokenUserId,
 ) -> Result<String, CommandError> {
     let guild_id = GuildId::new(guild_id);
-    let permission = check_guild_user(cache_http.inner(), &db, user.into(), guild_id).await?;
+    let permission = check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.into(),
+        guild_id,
+        Capability::Play,
+    )
+    .await?;
 
     client.stop(guild_id).await?;
+
+    audit_log::log(
+        &db,
+        AuditEntry::new(guild_id, user.into(), AuditAction::PlaybackStopped),
+    )
+    .await;
     event_bus.inner().playback_stopped(&permission.member);
 
     Ok(String::from("Stopped playback"))
@@ -136,16 +210,18 @@ async fn play(
     client: &State<Client>,
     cache_http: &State<CacheHttp>,
     event_bus: &State<EventBus>,
+    rate_limiter: &State<RateLimiter>,
     db: DbConn,
     user: TokenUserId,
 ) -> Result<(), CommandError> {
     // Check permission to play on this guild
     let serenity_user = user.into();
-    let permission = check_guild_user(
+    let permission = check_guild_capability(
         cache_http.inner(),
         &db,
         serenity_user,
         GuildId::new(guild_id),
+        Capability::Play,
     )
     .await?;
 
@@ -156,6 +232,7 @@ async fn play(
 
             sounds::table
                 .find(sound_id)
+                .filter(sounds::deleted_at.is_null())
                 .inner_join(soundfiles::table)
                 .first::<(models::Sound, models::Soundfile)>(c)
         })
@@ -166,14 +243,32 @@ async fn play(
         .guild_id
         .to_u64()
         .ok_or_else(|| CommandError::BigDecimalError)?;
-    check_guild_user(
+    let sound_permission = check_guild_capability(
         cache_http.inner(),
         &db,
         serenity_user,
         GuildId::new(sound_gid),
+        Capability::Play,
     )
     .await?;
 
+    // Check the restrictions of the sound against the channel it would be played in
+    let channel_id = if autojoin {
+        client
+            .user_channel(GuildId::new(guild_id), serenity_user, cache_http.inner())
+            .ok()
+    } else {
+        client.current_channel(GuildId::new(guild_id)).await
+    };
+    check_play_restrictions(&db, &sound, &sound_permission, channel_id)
+        .await
+        .map_err(|err| match err {
+            PermissionError::RoleRestricted | PermissionError::ChannelRestricted => {
+                CommandError::PlaybackRestricted(err)
+            }
+            err => CommandError::from(err),
+        })?;
+
     let gid = BigDecimal::from_u64(guild_id).ok_or_else(|| CommandError::BigDecimalError)?;
     let guild_settings = db
         .run(move |c| {
@@ -187,6 +282,7 @@ async fn play(
         .await?;
 
     let (target_max_volume, target_mean_volume) = guild_settings
+        .as_ref()
         .map(|guild_settings| {
             (
                 guild_settings.target_max_volume,
@@ -195,25 +291,76 @@ async fn play(
         })
         .unwrap_or((0.0, -13.0));
 
+    let limits = PlaybackLimits {
+        user_plays_per_minute: guild_settings
+            .as_ref()
+            .and_then(|guild_settings| guild_settings.user_plays_per_minute)
+            .map(|limit| limit.max(0) as u32),
+        guild_plays_per_minute: guild_settings
+            .as_ref()
+            .and_then(|guild_settings| guild_settings.guild_plays_per_minute)
+            .map(|limit| limit.max(0) as u32),
+        sound_cooldown: sound
+            .cooldown
+            .filter(|cooldown| *cooldown > 0.0)
+            .and_then(|cooldown| Duration::try_from_secs_f32(cooldown).ok()),
+    };
+    // Moderators and admins are not rate limited, but their plays still count
+    let played_at = rate_limiter
+        .acquire(
+            GuildId::new(guild_id),
+            serenity_user,
+            sound_id,
+            &limits,
+            permission.permission == UserPermission::User,
+        )
+        .map_err(CommandError::RateLimited)?;
+
     let adjustment = sound.volume_adjustment.unwrap_or_else(|| {
         (target_max_volume - soundfile.max_volume)
             .max(target_mean_volume - soundfile.mean_volume)
             .max(0.0)
     });
 
-    if autojoin {
+    let playback = async {
+        if autojoin {
+            client
+                .join_user(GuildId::new(guild_id), user.into(), cache_http.inner())
+                .await?;
+        }
+
+        let sound_path = STORAGE
+            .local_path(&file_handling::sound_key(&soundfile.file_name))
+            .await?;
         client
-            .join_user(GuildId::new(guild_id), user.into(), cache_http.inner())
+            .play(sound_path, adjustment, GuildId::new(guild_id))
             .await?;
+        Ok::<_, CommandError>(())
+    };
+    if let Err(err) = playback.await {
+        // A failed playback should not count towards the limits
+        rate_limiter.release(GuildId::new(guild_id), serenity_user, sound_id, played_at);
+        return Err(err);
     }
 
-    client
-        .play(
-            &file_handling::get_full_sound_path(&soundfile.file_name),
-            adjustment,
+    let source = if user.via_token() {
+        PlaySource::Token
+    } else {
+        PlaySource::Web
+    };
+    stats::record_play(&db, GuildId::new(guild_id), serenity_user, sound_id, source).await;
+
+    audit_log::log(
+        &db,
+        AuditEntry::new(
             GuildId::new(guild_id),
+            serenity_user,
+            AuditAction::PlaybackStarted,
         )
-        .await?;
+        .target(sound.id)
+        .after(json!({ "soundName": sound.name })),
+    )
+    .await;
 
     event_bus
         .inner()
@@ -222,10 +369,12 @@ async fn play(
     Ok(())
 }
 
+/// Saves the buffered audio. `seconds` limits the recording to the last seconds.
 #[instrument(skip(client, cache_http, event_bus, db, user))]
-#[post("/<guild_id>/record")]
+#[post("/<guild_id>/record?<seconds>")]
 async fn record(
     guild_id: u64,
+    seconds: Option<u64>,
     client: &State<Client>,
     cache_http: &State<CacheHttp>,
     event_bus: &State<EventBus>,
@@ -233,13 +382,26 @@ async fn record(
     user: TokenUserId,
 ) -> Result<String, CommandError> {
     let guild_id = GuildId::new(guild_id);
-    let permission = check_guild_user(cache_http.inner(), &db, user.into(), guild_id).await?;
+    let permission = check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.into(),
+        guild_id,
+        Capability::Record,
+    )
+    .await?;
+    quotas::check_recording_storage(&db, guild_id).await?;
 
     client
         .recorder
-        .save_recording(guild_id, cache_http.inner())
+        .save_recording(guild_id, cache_http.inner(), Some(user.into()), seconds)
         .await?;
 
+    audit_log::log(
+        &db,
+        AuditEntry::new(guild_id, user.into(), AuditAction::RecordingSaved),
+    )
+    .await;
     event_bus.inner().recording_saved(&permission.member);
     Ok(String::from("Recording saved"))
 }
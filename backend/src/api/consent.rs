@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use bigdecimal::BigDecimal;
+use bigdecimal::FromPrimitive;
+use bigdecimal::ToPrimitive;
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use rocket::http::Status;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::Json;
+use rocket::Request;
+use rocket::Route;
+use rocket::State;
+use rocket_sync_db_pools::ConnectionPool;
+use serde::Deserialize;
+use serde::Serialize;
+use serenity::model::id::UserId as SerenityUserId;
+use thiserror::Error;
+use tokio::time::sleep;
+
+use crate::api::auth::TokenUserId;
+use crate::api::Snowflake;
+use crate::db::models;
+use crate::db::DbConn;
+use crate::discord::client::Client;
+use crate::discord::recorder::RecordingConsent;
+
+const LOAD_RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_LOAD_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+pub fn get_routes() -> Vec<Route> {
+    routes![
+        get_recording_consent,
+        set_recording_opt_out,
+        set_guild_consent
+    ]
+}
+
+#[derive(Debug, Error)]
+#[allow(clippy::enum_variant_names)]
+enum ConsentError {
+    #[error("Internal error: {0}")]
+    InternalError(String),
+
+    #[error("Number handling error")]
+    NumericalError,
+
+    #[error("Database error: {0}")]
+    DieselError(#[from] DieselError),
+}
+
+impl ConsentError {
+    fn status_code(&self) -> Status {
+        match self {
+            Self::InternalError(_) => Status::InternalServerError,
+            Self::NumericalError => Status::InternalServerError,
+            Self::DieselError(_) => Status::InternalServerError,
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ConsentError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.status_code();
+        let error_message = self.to_string();
+
+        Response::build_from(error_message.respond_to(req)?)
+            .status(status)
+            .ok()
+    }
+}
+
+/// Loads who may be recorded into the recorder, which cannot access the database itself. Nobody is
+/// recorded until this succeeded, so it is retried until then.
+pub async fn load_recording_consent(pool: ConnectionPool<DbConn, PgConnection>, client: Client) {
+    let mut delay = LOAD_RETRY_DELAY;
+    while let Err(err) = client.recorder.load_consent(load_consent(&pool)).await {
+        error!(?err, ?delay, "Failed to load recording consent, retrying");
+        sleep(delay).await;
+        delay = (delay * 2).min(MAX_LOAD_RETRY_DELAY);
+    }
+}
+
+#[instrument(skip(pool), err)]
+async fn load_consent(
+    pool: &ConnectionPool<DbConn, PgConnection>,
+) -> Result<RecordingConsent, ConsentError> {
+    let conn = pool.get().await.ok_or_else(|| {
+        ConsentError::InternalError(String::from("No database connection available"))
+    })?;
+
+    let (opted_out_users, guild_choices, opt_in_guilds) = conn
+        .run(|c| {
+            use crate::db::schema::guildsettings;
+            use crate::db::schema::recordingconsents;
+            use crate::db::schema::recordingoptouts;
+
+            let opted_out_users = recordingoptouts::table
+                .select(recordingoptouts::user_id)
+                .load::<BigDecimal>(c)?;
+            let guild_choices = recordingconsents::table.load::<models::RecordingConsent>(c)?;
+            let opt_in_guilds = guildsettings::table
+                .filter(guildsettings::recording_opt_in_required)
+                .select(guildsettings::id)
+                .load::<BigDecimal>(c)?;
+
+            Ok::<_, DieselError>((opted_out_users, guild_choices, opt_in_guilds))
+        })
+        .await?;
+
+    Ok(RecordingConsent {
+        opted_out_users: opted_out_users
+            .iter()
+            .filter_map(BigDecimal::to_u64)
+            .collect(),
+        guild_choices: guild_choices
+            .into_iter()
+            .filter_map(|choice| {
+                Some((
+                    (choice.guild_id.to_u64()?, choice.user_id.to_u64()?),
+                    choice.consent,
+                ))
+            })
+            .collect(),
+        opt_in_guilds: opt_in_guilds
+            .iter()
+            .filter_map(BigDecimal::to_u64)
+            .collect(),
+    })
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RecordingConsentSettings {
+    /// The user is not recorded in any guild
+    opted_out: bool,
+    /// Whether the user may be recorded in a guild. Guilds without a choice use their default.
+    guild_choices: HashMap<Snowflake, bool>,
+}
+
+#[get("/user/recording-consent")]
+async fn get_recording_consent(
+    user: TokenUserId,
+    db: DbConn,
+) -> Result<Json<RecordingConsentSettings>, ConsentError> {
+    let uid = BigDecimal::from_u64(SerenityUserId::from(user).get())
+        .ok_or(ConsentError::NumericalError)?;
+
+    let (opted_out, guild_choices) = db
+        .run(move |c| {
+            use crate::db::schema::recordingconsents;
+            use crate::db::schema::recordingoptouts;
+
+            let opted_out = recordingoptouts::table
+                .find(&uid)
+                .count()
+                .get_result::<i64>(c)?
+                > 0;
+            let guild_choices = recordingconsents::table
+                .filter(recordingconsents::user_id.eq(&uid))
+                .load::<models::RecordingConsent>(c)?;
+
+            Ok::<_, DieselError>((opted_out, guild_choices))
+        })
+        .await?;
+
+    Ok(Json(RecordingConsentSettings {
+        opted_out,
+        guild_choices: guild_choices
+            .into_iter()
+            .map(|choice| {
+                Ok((
+                    Snowflake(
+                        choice
+                            .guild_id
+                            .to_u64()
+                            .ok_or(ConsentError::NumericalError)?,
+                    ),
+                    choice.consent,
+                ))
+            })
+            .collect::<Result<_, ConsentError>>()?,
+    }))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OptOutParameter {
+    opted_out: bool,
+}
+
+/// Opts the user out of being recorded in all guilds, or back in
+#[put("/user/recording-consent", format = "json", data = "<params>")]
+async fn set_recording_opt_out(
+    user: TokenUserId,
+    db: DbConn,
+    client: &State<Client>,
+    params: Json<OptOutParameter>,
+) -> Result<(), ConsentError> {
+    let user_id = SerenityUserId::from(user).get();
+    let uid = BigDecimal::from_u64(user_id).ok_or(ConsentError::NumericalError)?;
+    let opted_out = params.opted_out;
+
+    db.run(move |c| {
+        use crate::db::schema::recordingoptouts;
+
+        if opted_out {
+            diesel::insert_into(recordingoptouts::table)
+                .values(recordingoptouts::user_id.eq(&uid))
+                .on_conflict_do_nothing()
+                .execute(c)
+        } else {
+            diesel::delete(recordingoptouts::table.find(&uid)).execute(c)
+        }
+    })
+    .await?;
+    client.recorder.set_user_opted_out(user_id, opted_out).await;
+
+    Ok(())
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GuildConsentParameter {
+    /// `None` uses the default of the guild
+    consent: Option<bool>,
+}
+
+/// Sets whether the user may be recorded in the guild. Permissions are not checked, as everyone in a voice
+/// channel may be recorded, not only users of the soundboard.
+#[put(
+    "/guilds/<guild_id>/recording-consent",
+    format = "json",
+    data = "<params>"
+)]
+async fn set_guild_consent(
+    guild_id: u64,
+    user: TokenUserId,
+    db: DbConn,
+    client: &State<Client>,
+    params: Json<GuildConsentParameter>,
+) -> Result<(), ConsentError> {
+    let user_id = SerenityUserId::from(user).get();
+    let uid = BigDecimal::from_u64(user_id).ok_or(ConsentError::NumericalError)?;
+    let gid = BigDecimal::from_u64(guild_id).ok_or(ConsentError::NumericalError)?;
+    let choice = params.consent;
+
+    db.run(move |c| {
+        use crate::db::schema::recordingconsents;
+
+        match choice {
+            Some(consent) => diesel::insert_into(recordingconsents::table)
+                .values(models::RecordingConsent {
+                    user_id: uid,
+                    guild_id: gid,
+                    consent,
+                })
+                .on_conflict((recordingconsents::user_id, recordingconsents::guild_id))
+                .do_update()
+                .set(recordingconsents::consent.eq(consent))
+                .execute(c),
+            None => diesel::delete(recordingconsents::table.find((uid, gid))).execute(c),
+        }
+    })
+    .await?;
+    client
+        .recorder
+        .set_guild_choice(guild_id, user_id, choice)
+        .await;
+
+    Ok(())
+}
@@ -3,7 +3,8 @@ use crate::api::utils::AvatarOrDefault;
 use crate::api::Snowflake;
 use crate::db::models::Sound;
 use crate::db::DbConn;
-use crate::discord::management::check_guild_user;
+use crate::discord::management::check_guild_capability;
+use crate::discord::management::Capability;
 use crate::CacheHttp;
 use rocket::http::Status;
 use rocket::response::stream::Event;
@@ -146,13 +147,14 @@ async fn events(
     user: TokenUserId,
     mut end: Shutdown,
 ) -> Result<EventStream![], Status> {
-    // Only users may get events from this guild
+    // Only users that may play sounds get events from this guild
     let serenity_user = user.into();
-    check_guild_user(
+    check_guild_capability(
         cache_http.inner(),
         &db,
         serenity_user,
         GuildId::new(guild_id),
+        Capability::Play,
     )
     .await
     .map_err(|_| Status::Forbidden)?;
@@ -0,0 +1,250 @@
+use std::collections::HashSet;
+use std::convert::TryFrom;
+
+use bigdecimal::BigDecimal;
+use bigdecimal::FromPrimitive;
+use bigdecimal::ToPrimitive;
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use rocket::http::Status;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::Json;
+use rocket::Request;
+use rocket::Route;
+use rocket::State;
+use serde::Serialize;
+use serenity::model::id::GuildId;
+use serenity::model::id::UserId as SerenityUserId;
+use thiserror::Error;
+
+use crate::api::auth::TokenUserId;
+use crate::api::auth::UserId;
+use crate::api::Snowflake;
+use crate::db::DbConn;
+use crate::discord::management::check_guild_capability;
+use crate::discord::management::Capability;
+use crate::discord::management::PermissionError;
+use crate::CacheHttp;
+
+pub fn get_routes() -> Vec<Route> {
+    routes![list_favorites, set_favorites, add_favorite, remove_favorite]
+}
+
+#[derive(Debug, Error)]
+enum FavoritesError {
+    #[error("Number handling error")]
+    NumericalError,
+
+    #[error("Database error: {0}")]
+    DieselError(#[from] DieselError),
+
+    #[error("Insufficient permission: you do not have the permission to perform this action")]
+    InsufficientPermission(#[from] PermissionError),
+
+    #[error("Not found: a sound with the given id does not exist")]
+    NotFound,
+}
+
+impl FavoritesError {
+    fn status_code(&self) -> Status {
+        match self {
+            Self::NumericalError => Status::InternalServerError,
+            Self::DieselError(_) => Status::InternalServerError,
+            Self::InsufficientPermission(_) => Status::Forbidden,
+            Self::NotFound => Status::NotFound,
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for FavoritesError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.status_code();
+        let error_message = self.to_string();
+
+        Response::build_from(error_message.respond_to(req)?)
+            .status(status)
+            .ok()
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Favorite {
+    sound_id: Snowflake,
+    guild_id: Snowflake,
+    position: i32,
+}
+
+/// Checks that the sounds exist and that the user may play sounds in their guilds
+async fn check_sounds(
+    cache_http: &CacheHttp,
+    db: &DbConn,
+    user: UserId,
+    sound_ids: Vec<i32>,
+) -> Result<(), FavoritesError> {
+    let expected = sound_ids.iter().copied().collect::<HashSet<_>>();
+    let sounds = db
+        .run(move |c| {
+            use crate::db::schema::sounds;
+
+            sounds::table
+                .filter(sounds::id.eq_any(sound_ids))
+                .filter(sounds::deleted_at.is_null())
+                .select((sounds::id, sounds::guild_id))
+                .load::<(i32, BigDecimal)>(c)
+        })
+        .await?;
+    if sounds.len() != expected.len() {
+        return Err(FavoritesError::NotFound);
+    }
+
+    let guild_ids = sounds
+        .into_iter()
+        .map(|(_, guild_id)| guild_id.to_u64().ok_or(FavoritesError::NumericalError))
+        .collect::<Result<HashSet<_>, _>>()?;
+    for guild_id in guild_ids {
+        check_guild_capability(
+            cache_http,
+            db,
+            user.clone().into(),
+            GuildId::new(guild_id),
+            Capability::Play,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// The favorites of the user in their personal order. Sounds in the trash are left out.
+#[get("/user/favorites")]
+async fn list_favorites(
+    user: TokenUserId,
+    db: DbConn,
+) -> Result<Json<Vec<Favorite>>, FavoritesError> {
+    let uid = BigDecimal::from_u64(SerenityUserId::from(user).get())
+        .ok_or(FavoritesError::NumericalError)?;
+
+    let favorites = db
+        .run(move |c| {
+            use crate::db::schema::favorites;
+            use crate::db::schema::sounds;
+
+            favorites::table
+                .inner_join(sounds::table)
+                .filter(favorites::user_id.eq(uid))
+                .filter(sounds::deleted_at.is_null())
+                .select((favorites::sound_id, sounds::guild_id, favorites::position))
+                .order((favorites::position.asc(), favorites::created_at.asc()))
+                .load::<(i32, BigDecimal, i32)>(c)
+        })
+        .await?
+        .into_iter()
+        .map(|(sound_id, guild_id, position)| {
+            Ok(Favorite {
+                sound_id: Snowflake(
+                    u64::try_from(sound_id).map_err(|_| FavoritesError::NumericalError)?,
+                ),
+                guild_id: Snowflake(guild_id.to_u64().ok_or(FavoritesError::NumericalError)?),
+                position,
+            })
+        })
+        .collect::<Result<Vec<_>, FavoritesError>>()?;
+
+    Ok(Json(favorites))
+}
+
+/// Replaces the favorites of the user. The sounds are stored in the given order.
+#[put("/user/favorites", format = "json", data = "<sound_ids>")]
+async fn set_favorites(
+    user: UserId,
+    db: DbConn,
+    cache_http: &State<CacheHttp>,
+    sound_ids: Json<Vec<Snowflake>>,
+) -> Result<(), FavoritesError> {
+    let mut sound_ids = sound_ids
+        .into_inner()
+        .into_iter()
+        .map(|id| i32::try_from(id.0).map_err(|_| FavoritesError::NotFound))
+        .collect::<Result<Vec<_>, _>>()?;
+    // Duplicates keep their first position
+    let mut seen = HashSet::new();
+    sound_ids.retain(|sound_id| seen.insert(*sound_id));
+    check_sounds(cache_http.inner(), &db, user.clone(), sound_ids.clone()).await?;
+
+    let uid = BigDecimal::from_u64(user.0).ok_or(FavoritesError::NumericalError)?;
+    db.run(move |c| {
+        use crate::db::schema::favorites;
+
+        c.transaction(|c| {
+            diesel::delete(favorites::table.filter(favorites::user_id.eq(&uid))).execute(c)?;
+            diesel::insert_into(favorites::table)
+                .values(
+                    sound_ids
+                        .iter()
+                        .enumerate()
+                        .map(|(position, sound_id)| {
+                            (
+                                favorites::user_id.eq(&uid),
+                                favorites::sound_id.eq(*sound_id),
+                                favorites::position.eq(position as i32),
+                            )
+                        })
+                        .collect::<Vec<_>>(),
+                )
+                .execute(c)
+        })
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Adds the sound to the end of the favorites of the user
+#[put("/user/favorites/<sound_id>")]
+async fn add_favorite(
+    sound_id: i32,
+    user: UserId,
+    db: DbConn,
+    cache_http: &State<CacheHttp>,
+) -> Result<(), FavoritesError> {
+    check_sounds(cache_http.inner(), &db, user.clone(), vec![sound_id]).await?;
+
+    let uid = BigDecimal::from_u64(user.0).ok_or(FavoritesError::NumericalError)?;
+    db.run(move |c| {
+        use crate::db::schema::favorites;
+
+        let position = favorites::table
+            .filter(favorites::user_id.eq(&uid))
+            .select(diesel::dsl::max(favorites::position))
+            .first::<Option<i32>>(c)?
+            .map(|max| max + 1)
+            .unwrap_or(0);
+
+        diesel::insert_into(favorites::table)
+            .values((
+                favorites::user_id.eq(&uid),
+                favorites::sound_id.eq(sound_id),
+                favorites::position.eq(position),
+            ))
+            .on_conflict((favorites::user_id, favorites::sound_id))
+            .do_nothing()
+            .execute(c)
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[delete("/user/favorites/<sound_id>")]
+async fn remove_favorite(sound_id: i32, user: UserId, db: DbConn) -> Result<(), FavoritesError> {
+    let uid = BigDecimal::from_u64(user.0).ok_or(FavoritesError::NumericalError)?;
+    db.run(move |c| {
+        use crate::db::schema::favorites;
+
+        diesel::delete(favorites::table.find((uid, sound_id))).execute(c)
+    })
+    .await?;
+
+    Ok(())
+}
@@ -1,5 +1,6 @@
 use crate::api::auth::UserId;
 use crate::api::events::EventBus;
+use crate::api::rate_limiter::RateLimiter;
 use crate::db;
 use crate::discord::client::Client;
 use crate::CacheHttp;
@@ -17,17 +18,24 @@ use serde_with::serde_as;
 use serde_with::skip_serializing_none;
 use serde_with::DisplayFromStr;
 use std::env::var;
-use std::path::Path;
 use std::path::PathBuf;
 use std::sync::LazyLock;
 use utils::CachedFile;
 
+mod archive;
+pub mod audit_log;
 mod auth;
+mod categories;
 mod commands;
+mod consent;
 mod events;
+mod favorites;
+pub mod quotas;
+mod rate_limiter;
 mod recorder;
 mod settings;
 mod sounds;
+mod stats;
 mod utils;
 
 /// 64 bit integers can not be accurately represented in javascript. They are therefore
@@ -36,6 +44,15 @@ mod utils;
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Hash)]
 struct Snowflake(#[serde_as(as = "DisplayFromStr")] pub u64);
 
+// Folder containing the built frontend
+static STATIC_FOLDER: LazyLock<PathBuf> = LazyLock::new(|| {
+    var("STATIC_DIR")
+        .ok()
+        .filter(|folder| !folder.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("static"))
+});
+
 // Settings for frontend
 static LEGAL_URL: LazyLock<Option<String>> = LazyLock::new(|| var("LEGAL_URL").ok());
 // Discord data found in env
@@ -45,12 +62,71 @@ static DISCORD_CLIENT_SECRET: LazyLock<String> =
     LazyLock::new(|| var("DISCORD_CLIENT_SECRET").expect("Expected DISCORD_CLIENT_SECRET as env"));
 
 pub async fn run(cache_http: CacheHttp, client: Client) -> Result<Rocket<Ignite>, RocketError> {
+    // During development, the frontend is usually served separately
+    if !STATIC_FOLDER.join("index.html").is_file() {
+        warn!(
+            folder = ?*STATIC_FOLDER,
+            "STATIC_DIR does not contain index.html. The frontend will not be served."
+        );
+    }
+
     rocket::build()
         .attach(db::DbConn::fairing())
         .attach(AdHoc::on_ignite(
             "Database migrations",
             db::run_db_migrations,
         ))
+        .attach(AdHoc::on_liftoff("Trash purge", |rocket| {
+            Box::pin(async move {
+                if let Some(pool) = db::DbConn::pool(rocket) {
+                    tokio::spawn(sounds::purge_trash_periodically(pool.clone()));
+                }
+            })
+        }))
+        .attach(AdHoc::on_liftoff("Discord commands", |rocket| {
+            Box::pin(async move {
+                if let (Some(pool), Some(client)) =
+                    (db::DbConn::pool(rocket), rocket.state::<Client>())
+                {
+                    client.set_db_pool(pool.clone());
+                }
+            })
+        }))
+        .attach(AdHoc::on_liftoff("Recording consent", |rocket| {
+            Box::pin(async move {
+                if let (Some(pool), Some(client)) =
+                    (db::DbConn::pool(rocket), rocket.state::<Client>())
+                {
+                    tokio::spawn(consent::load_recording_consent(
+                        pool.clone(),
+                        client.clone(),
+                    ));
+                }
+            })
+        }))
+        .attach(AdHoc::on_liftoff("Recorder settings", |rocket| {
+            Box::pin(async move {
+                if let (Some(pool), Some(client)) =
+                    (db::DbConn::pool(rocket), rocket.state::<Client>())
+                {
+                    settings::load_recorder_settings(pool.clone(), client.clone()).await;
+                }
+            })
+        }))
+        .attach(AdHoc::on_liftoff("Recording retention", |rocket| {
+            Box::pin(async move {
+                if let Some(pool) = db::DbConn::pool(rocket) {
+                    tokio::spawn(recorder::purge_recordings_periodically(pool.clone()));
+                }
+            })
+        }))
+        .attach(AdHoc::on_liftoff("Sound file hashes", |rocket| {
+            Box::pin(async move {
+                if let Some(pool) = db::DbConn::pool(rocket) {
+                    tokio::spawn(sounds::hash_stored_files(pool.clone()));
+                }
+            })
+        }))
         .mount("/", routes![frontend, info])
         .mount("/api", auth::get_routes())
         .mount("/api/guilds", commands::get_routes())
@@ -58,20 +134,27 @@ pub async fn run(cache_http: CacheHttp, client: Client) -> Result<Rocket<Ignite>
         .mount("/api", recorder::get_routes())
         .mount("/api", settings::get_routes())
         .mount("/api", events::get_routes())
+        .mount("/api", audit_log::get_routes())
+        .mount("/api", stats::get_routes())
+        .mount("/api", categories::get_routes())
+        .mount("/api", favorites::get_routes())
+        .mount("/api", consent::get_routes())
+        .mount("/api", archive::get_routes())
         .manage(cache_http)
         .manage(client)
         .manage(auth::get_oauth_client())
         // Channel for server sent events
         .manage(EventBus::new())
+        .manage(RateLimiter::new())
         .launch()
         .await
 }
 
 #[get("/<path..>", rank = 100)]
 async fn frontend(path: PathBuf) -> Option<CachedFile> {
-    let mut file = Path::new("static").join(path);
+    let mut file = STATIC_FOLDER.join(path);
     if !file.is_file() {
-        file = Path::new("static").join("index.html");
+        file = STATIC_FOLDER.join("index.html");
     }
     CachedFile::open(file).await.ok()
 }
@@ -0,0 +1,279 @@
+use std::env::var;
+use std::sync::LazyLock;
+
+use bigdecimal::BigDecimal;
+use bigdecimal::FromPrimitive;
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use diesel::sql_types::BigInt;
+use diesel::sql_types::Numeric;
+use rocket::http::Status;
+use serde::Serialize;
+use serenity::model::id::GuildId;
+use thiserror::Error;
+
+use crate::db::models;
+use crate::db::DbConn;
+use crate::file_handling;
+use crate::storage::STORAGE;
+
+const BYTES_PER_MB: u64 = 1024 * 1024;
+
+fn parse_env<T: std::str::FromStr>(name: &str) -> Option<T> {
+    var(name).ok().and_then(|content| content.parse::<T>().ok())
+}
+
+/// Storage limits too large to be represented in bytes are treated as unlimited
+fn mb_to_bytes(mb: u64) -> Option<u64> {
+    mb.checked_mul(BYTES_PER_MB)
+}
+
+// Quotas that apply to all guilds without an override. Unset means unlimited.
+static MAX_SOUND_STORAGE: LazyLock<Option<u64>> =
+    LazyLock::new(|| parse_env::<u64>("QUOTA_SOUND_STORAGE_MB").and_then(mb_to_bytes));
+static MAX_SOUNDS: LazyLock<Option<i64>> = LazyLock::new(|| parse_env("QUOTA_SOUNDS"));
+static MAX_CLIP_LENGTH: LazyLock<Option<f32>> =
+    LazyLock::new(|| parse_env("QUOTA_CLIP_LENGTH_SECONDS"));
+static MAX_RECORDING_STORAGE: LazyLock<Option<u64>> =
+    LazyLock::new(|| parse_env::<u64>("QUOTA_RECORDING_STORAGE_MB").and_then(mb_to_bytes));
+
+/// Sums up the sizes of the distinct files of the guild's sounds, trashed ones and previous versions
+/// included. Files whose size is not known yet are not counted.
+const SOUND_STORAGE_QUERY: &str = "SELECT COALESCE(SUM(file_size), 0)::BIGINT AS bytes FROM (
+    SELECT soundfiles.file_name, soundfiles.file_size FROM soundfiles
+    INNER JOIN sounds ON sounds.id = soundfiles.sound_id WHERE sounds.guild_id = $1
+    UNION
+    SELECT soundfileversions.file_name, soundfileversions.file_size FROM soundfileversions
+    INNER JOIN sounds ON sounds.id = soundfileversions.sound_id WHERE sounds.guild_id = $1
+) AS files";
+
+#[derive(Debug, Error)]
+pub enum QuotaError {
+    #[error("Quota exceeded: {0}")]
+    Exceeded(String),
+
+    #[error("Database error: {0}")]
+    DieselError(#[from] DieselError),
+
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("Number handling error")]
+    NumericalError,
+}
+
+impl QuotaError {
+    pub fn status_code(&self) -> Status {
+        match self {
+            Self::Exceeded(_) => Status::Forbidden,
+            Self::DieselError(_) => Status::InternalServerError,
+            Self::IoError(_) => Status::InternalServerError,
+            Self::NumericalError => Status::InternalServerError,
+        }
+    }
+}
+
+/// Limits of a guild. Storage is measured in bytes and clip length in seconds. `None` means unlimited.
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct GuildQuota {
+    pub max_sound_storage: Option<u64>,
+    pub max_sounds: Option<i64>,
+    pub max_clip_length: Option<f32>,
+    pub max_recording_storage: Option<u64>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct GuildUsage {
+    pub sound_storage: u64,
+    pub sounds: i64,
+    pub recording_storage: u64,
+}
+
+/// The quota of the guild. Overrides set by the bot operator take precedence over the defaults.
+pub async fn get_quota(db: &DbConn, guild_id: GuildId) -> Result<GuildQuota, QuotaError> {
+    let gid = BigDecimal::from_u64(guild_id.get()).ok_or(QuotaError::NumericalError)?;
+    let quota_override = db
+        .run(move |c| {
+            use crate::db::schema::guildquotas;
+
+            guildquotas::table
+                .find(gid)
+                .first::<models::GuildQuota>(c)
+                .optional()
+        })
+        .await?;
+
+    let storage = |mb: Option<i32>| {
+        mb.and_then(|mb| u64::try_from(mb).ok())
+            .and_then(mb_to_bytes)
+    };
+    Ok(match quota_override {
+        Some(quota) => GuildQuota {
+            max_sound_storage: storage(quota.max_sound_storage_mb).or(*MAX_SOUND_STORAGE),
+            max_sounds: quota.max_sounds.map(i64::from).or(*MAX_SOUNDS),
+            max_clip_length: quota.max_clip_length.or(*MAX_CLIP_LENGTH),
+            max_recording_storage: storage(quota.max_recording_storage_mb)
+                .or(*MAX_RECORDING_STORAGE),
+        },
+        None => GuildQuota {
+            max_sound_storage: *MAX_SOUND_STORAGE,
+            max_sounds: *MAX_SOUNDS,
+            max_clip_length: *MAX_CLIP_LENGTH,
+            max_recording_storage: *MAX_RECORDING_STORAGE,
+        },
+    })
+}
+
+#[derive(QueryableByName)]
+struct StorageUsage {
+    #[diesel(sql_type = BigInt)]
+    bytes: i64,
+}
+
+/// Current usage of the guild. Sounds in the trash and previous versions count towards the storage, as
+/// their files are kept. Files shared with other guilds count for each of them.
+pub async fn get_usage(db: &DbConn, guild_id: GuildId) -> Result<GuildUsage, QuotaError> {
+    Ok(GuildUsage {
+        sound_storage: get_sound_storage(db, guild_id).await?,
+        sounds: get_sound_count(db, guild_id).await?,
+        recording_storage: get_recording_storage(guild_id).await?,
+    })
+}
+
+async fn get_sound_storage(db: &DbConn, guild_id: GuildId) -> Result<u64, QuotaError> {
+    let gid = BigDecimal::from_u64(guild_id.get()).ok_or(QuotaError::NumericalError)?;
+    let usage = db
+        .run(move |c| {
+            diesel::sql_query(SOUND_STORAGE_QUERY)
+                .bind::<Numeric, _>(gid)
+                .get_result::<StorageUsage>(c)
+        })
+        .await?;
+
+    u64::try_from(usage.bytes).map_err(|_| QuotaError::NumericalError)
+}
+
+async fn get_sound_count(db: &DbConn, guild_id: GuildId) -> Result<i64, QuotaError> {
+    let gid = BigDecimal::from_u64(guild_id.get()).ok_or(QuotaError::NumericalError)?;
+    let sounds = db
+        .run(move |c| {
+            use crate::db::schema::sounds;
+
+            sounds::table
+                .filter(sounds::guild_id.eq(gid))
+                .filter(sounds::deleted_at.is_null())
+                .count()
+                .get_result::<i64>(c)
+        })
+        .await?;
+
+    Ok(sounds)
+}
+
+/// Recordings are not tracked in the database, but only the folder of the guild has to be listed
+async fn get_recording_storage(guild_id: GuildId) -> Result<u64, QuotaError> {
+    Ok(STORAGE
+        .list(&file_handling::recording_folder_key(guild_id.get(), None))
+        .await?
+        .into_iter()
+        .map(|object| object.size)
+        .sum())
+}
+
+/// Checks that the guild may have `additional` more sounds
+pub async fn check_sound_count(
+    db: &DbConn,
+    guild_id: GuildId,
+    additional: i64,
+) -> Result<(), QuotaError> {
+    let quota = get_quota(db, guild_id).await?;
+    if let Some(max_sounds) = quota.max_sounds {
+        let sounds = get_sound_count(db, guild_id).await?;
+        if sounds.saturating_add(additional) > max_sounds {
+            return Err(QuotaError::Exceeded(format!(
+                "the guild may have at most {} sounds",
+                max_sounds
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that a file of the given size fits into the sound storage of the guild
+pub async fn check_sound_storage(
+    db: &DbConn,
+    guild_id: GuildId,
+    additional: u64,
+) -> Result<(), QuotaError> {
+    let quota = get_quota(db, guild_id).await?;
+    if let Some(max_sound_storage) = quota.max_sound_storage {
+        let sound_storage = get_sound_storage(db, guild_id).await?;
+        if sound_storage.saturating_add(additional) > max_sound_storage {
+            return Err(QuotaError::Exceeded(format!(
+                "the sounds of the guild may take up at most {} MiB",
+                max_sound_storage / BYTES_PER_MB
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn check_clip_length(quota: &GuildQuota, length: f32) -> Result<(), QuotaError> {
+    match quota.max_clip_length {
+        Some(max_clip_length) if length > max_clip_length => Err(QuotaError::Exceeded(format!(
+            "sounds may be at most {} seconds long",
+            max_clip_length
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that the guild has not used up its recording storage
+pub async fn check_recording_storage(db: &DbConn, guild_id: GuildId) -> Result<(), QuotaError> {
+    let quota = get_quota(db, guild_id).await?;
+    if let Some(max_recording_storage) = quota.max_recording_storage {
+        let recording_storage = get_recording_storage(guild_id).await?;
+        if recording_storage >= max_recording_storage {
+            return Err(QuotaError::Exceeded(format!(
+                "the recordings of the guild may take up at most {} MiB. Delete some to record again.",
+                max_recording_storage / BYTES_PER_MB
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quota(max_clip_length: Option<f32>) -> GuildQuota {
+        GuildQuota {
+            max_sound_storage: None,
+            max_sounds: None,
+            max_clip_length,
+            max_recording_storage: None,
+        }
+    }
+
+    #[test]
+    fn huge_storage_limits_are_unlimited() {
+        assert_eq!(mb_to_bytes(2), Some(2 * 1024 * 1024));
+        assert_eq!(mb_to_bytes(u64::MAX), None);
+    }
+
+    #[test]
+    fn clip_length_is_limited_inclusively() {
+        assert!(check_clip_length(&quota(None), 1000.0).is_ok());
+        assert!(check_clip_length(&quota(Some(10.0)), 10.0).is_ok());
+        assert!(matches!(
+            check_clip_length(&quota(Some(10.0)), 10.5),
+            Err(QuotaError::Exceeded(_))
+        ));
+    }
+}
@@ -0,0 +1,269 @@
+use serenity::model::id::GuildId;
+use serenity::model::id::UserId;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Rate limits are counted over a sliding window of this length
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// The longest cooldown that can be configured for a sound
+pub const MAX_COOLDOWN_SECONDS: f32 = 24.0 * 60.0 * 60.0;
+
+/// The highest number of plays per minute that can be configured as a limit
+pub const MAX_PLAYS_PER_MINUTE: i32 = 1000;
+
+/// The limits that apply to a single playback. `None` means unlimited.
+#[derive(Debug, Clone, Copy)]
+pub struct PlaybackLimits {
+    pub user_plays_per_minute: Option<u32>,
+    pub guild_plays_per_minute: Option<u32>,
+    pub sound_cooldown: Option<Duration>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Recent plays per guild, oldest first
+    plays: HashMap<GuildId, VecDeque<(UserId, Instant)>>,
+    /// Point in time at which a sound started cooling down in a guild and until which it does
+    cooldowns: HashMap<(GuildId, i32), (Instant, Instant)>,
+}
+
+/// Keeps track of recent playbacks in memory to enforce rate limits and cooldowns
+pub struct RateLimiter {
+    state: Mutex<State>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self {
+            state: Default::default(),
+        }
+    }
+
+    /// Checks whether the user may play the sound and registers the play if so. Checking and registering
+    /// happens under one lock, so concurrent requests cannot both pass the limits. If `enforce` is false the
+    /// play is registered without checking the limits.
+    ///
+    /// Returns the point in time of the registered play, which can be passed to `release` if the playback
+    /// fails, or the time after which the play can be retried.
+    pub fn acquire(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        sound_id: i32,
+        limits: &PlaybackLimits,
+        enforce: bool,
+    ) -> Result<Instant, Duration> {
+        self.acquire_at(guild_id, user_id, sound_id, limits, enforce, Instant::now())
+    }
+
+    fn acquire_at(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        sound_id: i32,
+        limits: &PlaybackLimits,
+        enforce: bool,
+        now: Instant,
+    ) -> Result<Instant, Duration> {
+        let mut state = self.state.lock().unwrap();
+
+        state.cooldowns.retain(|_, (_, until)| *until > now);
+        let guild_plays = state.plays.entry(guild_id).or_default();
+        while let Some(true) = guild_plays
+            .front()
+            .map(|(_, time)| now.saturating_duration_since(*time) >= WINDOW)
+        {
+            guild_plays.pop_front();
+        }
+
+        if enforce {
+            if let Some((_, until)) = state.cooldowns.get(&(guild_id, sound_id)) {
+                return Err(until.saturating_duration_since(now));
+            }
+
+            let guild_plays = state.plays.entry(guild_id).or_default();
+            // The play that has to leave the window before another one is allowed determines the waiting time
+            let retry_after =
+                |times: Vec<Instant>, limit: u32| match times.len().checked_sub(limit as usize) {
+                    Some(index) => Err(times
+                        .get(index)
+                        .map(|time| WINDOW.saturating_sub(now.saturating_duration_since(*time)))
+                        .unwrap_or(WINDOW)),
+                    None => Ok(()),
+                };
+
+            if let Some(limit) = limits.guild_plays_per_minute {
+                retry_after(guild_plays.iter().map(|(_, time)| *time).collect(), limit)?;
+            }
+            if let Some(limit) = limits.user_plays_per_minute {
+                retry_after(
+                    guild_plays
+                        .iter()
+                        .filter(|(uid, _)| *uid == user_id)
+                        .map(|(_, time)| *time)
+                        .collect(),
+                    limit,
+                )?;
+            }
+        }
+
+        state
+            .plays
+            .entry(guild_id)
+            .or_default()
+            .push_back((user_id, now));
+        if let Some(until) = limits
+            .sound_cooldown
+            .and_then(|cooldown| now.checked_add(cooldown))
+        {
+            state.cooldowns.insert((guild_id, sound_id), (now, until));
+        }
+
+        Ok(now)
+    }
+
+    /// Removes a play registered by `acquire` again, e.g. because the playback failed
+    pub fn release(&self, guild_id: GuildId, user_id: UserId, sound_id: i32, at: Instant) {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(guild_plays) = state.plays.get_mut(&guild_id) {
+            if let Some(index) = guild_plays
+                .iter()
+                .position(|(uid, time)| *uid == user_id && *time == at)
+            {
+                guild_plays.remove(index);
+            }
+        }
+        if let Some((since, _)) = state.cooldowns.get(&(guild_id, sound_id)) {
+            if *since == at {
+                state.cooldowns.remove(&(guild_id, sound_id));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD: GuildId = GuildId::new(1);
+    const ALICE: UserId = UserId::new(2);
+    const BOB: UserId = UserId::new(3);
+
+    fn limits(user: Option<u32>, guild: Option<u32>, cooldown: Option<u64>) -> PlaybackLimits {
+        PlaybackLimits {
+            user_plays_per_minute: user,
+            guild_plays_per_minute: guild,
+            sound_cooldown: cooldown.map(Duration::from_secs),
+        }
+    }
+
+    #[test]
+    fn cooldown_blocks_the_sound_until_it_expires() {
+        let limiter = RateLimiter::new();
+        let limits = limits(None, None, Some(10));
+        let now = Instant::now();
+
+        assert!(limiter
+            .acquire_at(GUILD, ALICE, 1, &limits, true, now)
+            .is_ok());
+        assert_eq!(
+            limiter.acquire_at(GUILD, BOB, 1, &limits, true, now + Duration::from_secs(4)),
+            Err(Duration::from_secs(6))
+        );
+        assert!(limiter
+            .acquire_at(GUILD, BOB, 2, &limits, true, now)
+            .is_ok());
+        assert!(limiter
+            .acquire_at(GUILD, BOB, 1, &limits, true, now + Duration::from_secs(10))
+            .is_ok());
+    }
+
+    #[test]
+    fn user_limit_only_counts_the_users_plays() {
+        let limiter = RateLimiter::new();
+        let limits = limits(Some(2), None, None);
+        let now = Instant::now();
+
+        for seconds in [0, 20] {
+            let at = now + Duration::from_secs(seconds);
+            assert!(limiter
+                .acquire_at(GUILD, ALICE, 1, &limits, true, at)
+                .is_ok());
+        }
+        let at = now + Duration::from_secs(30);
+        assert_eq!(
+            limiter.acquire_at(GUILD, ALICE, 1, &limits, true, at),
+            Err(Duration::from_secs(30))
+        );
+        assert!(limiter.acquire_at(GUILD, BOB, 1, &limits, true, at).is_ok());
+    }
+
+    #[test]
+    fn guild_limit_counts_all_plays_and_expires_with_the_window() {
+        let limiter = RateLimiter::new();
+        let limits = limits(None, Some(2), None);
+        let now = Instant::now();
+
+        assert!(limiter
+            .acquire_at(GUILD, ALICE, 1, &limits, true, now)
+            .is_ok());
+        assert!(limiter
+            .acquire_at(GUILD, BOB, 1, &limits, true, now)
+            .is_ok());
+        assert!(limiter
+            .acquire_at(GUILD, BOB, 1, &limits, true, now)
+            .is_err());
+        assert!(limiter
+            .acquire_at(GUILD, BOB, 1, &limits, true, now + WINDOW)
+            .is_ok());
+    }
+
+    #[test]
+    fn unenforced_plays_count_towards_the_limits() {
+        let limiter = RateLimiter::new();
+        let limits = limits(None, Some(1), None);
+        let now = Instant::now();
+
+        assert!(limiter
+            .acquire_at(GUILD, ALICE, 1, &limits, false, now)
+            .is_ok());
+        assert!(limiter
+            .acquire_at(GUILD, ALICE, 1, &limits, false, now)
+            .is_ok());
+        assert!(limiter
+            .acquire_at(GUILD, BOB, 1, &limits, true, now)
+            .is_err());
+    }
+
+    #[test]
+    fn released_plays_do_not_count() {
+        let limiter = RateLimiter::new();
+        let limits = limits(Some(1), None, Some(10));
+        let now = Instant::now();
+
+        let at = limiter
+            .acquire_at(GUILD, ALICE, 1, &limits, true, now)
+            .unwrap();
+        limiter.release(GUILD, ALICE, 1, at);
+        assert!(limiter
+            .acquire_at(GUILD, ALICE, 1, &limits, true, now)
+            .is_ok());
+    }
+
+    #[test]
+    fn huge_cooldowns_do_not_panic() {
+        let limiter = RateLimiter::new();
+        let limits = PlaybackLimits {
+            user_plays_per_minute: None,
+            guild_plays_per_minute: None,
+            sound_cooldown: Some(Duration::MAX),
+        };
+
+        assert!(limiter.acquire(GUILD, ALICE, 1, &limits, true).is_ok());
+    }
+}
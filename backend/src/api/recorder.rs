@@ -1,51 +1,71 @@
+use crate::api::audit_log;
+use crate::api::audit_log::AuditAction;
+use crate::api::audit_log::AuditEntry;
+use crate::api::auth::OperatorUserId;
 use crate::api::auth::UserId;
 use crate::api::utils::CachedFile;
 use crate::api::Snowflake;
+use crate::db::models;
 use crate::db::DbConn;
-use crate::discord::management::check_guild_user;
+use crate::discord::client::Client;
+use crate::discord::management::check_guild_capability;
 use crate::discord::management::get_guilds_for_user;
+use crate::discord::management::Capability;
 use crate::discord::management::PermissionError;
+use crate::discord::recorder::RecorderMetrics;
 use crate::file_handling;
-use crate::file_handling::MIXES_FOLDER;
-use crate::file_handling::RECORDINGS_FOLDER;
+use crate::file_handling::MIX_LIFETIME;
+use crate::storage::LocalFile;
+use crate::storage::STORAGE;
 use crate::CacheHttp;
 use crate::BASE_URL;
+use bigdecimal::ToPrimitive;
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
 use rand::random;
+use rocket::fs::NamedFile;
+use rocket::http::Header;
 use rocket::http::Status;
 use rocket::response::{self, Responder, Response};
 use rocket::serde::json::Json;
 use rocket::Request;
 use rocket::Route;
 use rocket::State;
+use rocket_sync_db_pools::ConnectionPool;
 use serde::Deserialize;
 use serde::Serialize;
 use serenity::model::id::GuildId;
-use std::convert::TryFrom;
+use std::collections::BTreeMap;
 use std::ffi::OsString;
+use std::io::Write;
 use std::path::Path;
 use std::process::Stdio;
+use std::time::Duration;
 use std::time::SystemTime;
 use thiserror::Error;
 use tokio::fs;
 use tokio::io;
 use tokio::process::Command;
 use tokio::time::sleep;
-use tokio::time::Duration;
 use tracing::Instrument;
 use tracing::Level;
 
 pub fn get_routes() -> Vec<Route> {
     routes![
         get_recordings,
+        get_recorder_metrics,
         mix_recording,
         delete_recording,
+        pin_recording,
+        unpin_recording,
         get_recording,
+        download_recording,
         get_mix
     ]
 }
 
 #[derive(Debug, Error)]
-enum RecorderError {
+pub enum RecorderError {
     #[error("Internal error: {0}")]
     InternalError(String),
 
@@ -58,40 +78,34 @@ enum RecorderError {
     #[error("Not found: {0}")]
     NotFound(String),
 
-    #[error("Not a member: you must be a member of the guild to perform this task")]
-    NotAMember(#[from] PermissionError),
-
-    #[error("Failed to encode file name: {0:?}")]
-    FileNameEncoding(OsString),
+    #[error("Insufficient permission: you do not have the permission to perform this action")]
+    InsufficientPermission(#[from] PermissionError),
 
     #[error("Error handling recordings: {0}")]
     FileHandling(#[from] file_handling::FileError),
 
     #[error("Discord API error: {0}")]
     SerenityError(#[from] serenity::Error),
+
+    #[error("Database error: {0}")]
+    DieselError(#[from] DieselError),
 }
 
 impl RecorderError {
-    fn status_code(&self) -> Status {
+    pub fn status_code(&self) -> Status {
         match self {
             Self::InternalError(_) => Status::InternalServerError,
             Self::IoError(_) => Status::InternalServerError,
             Self::RequestError(_) => Status::BadRequest,
             Self::NotFound(_) => Status::NotFound,
-            Self::NotAMember(_) => Status::Unauthorized,
-            Self::FileNameEncoding(_) => Status::InternalServerError,
+            Self::InsufficientPermission(_) => Status::Forbidden,
             Self::FileHandling(_) => Status::InternalServerError,
             Self::SerenityError(_) => Status::InternalServerError,
+            Self::DieselError(_) => Status::InternalServerError,
         }
     }
 }
 
-impl From<OsString> for RecorderError {
-    fn from(err: OsString) -> Self {
-        Self::FileNameEncoding(err)
-    }
-}
-
 impl<'r> Responder<'r, 'static> for RecorderError {
     fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
         let status = self.status_code();
@@ -109,46 +123,43 @@ struct Recording {
     guild_id: Snowflake,
     timestamp: u64,
     length: f32,
+    triggered_by: Option<Snowflake>,
+    pinned: bool,
     users: Vec<RecordingUser>,
 }
 
 #[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
 struct RecordingUser {
     /// Externally, we use the file nameas id. Is a unique id together with the guild_id and timestamp.
     id: String,
     username: String,
+    /// Unknown for old recordings
+    user_id: Option<Snowflake>,
+    start_offset: f32,
+    length: f32,
 }
 
-impl TryFrom<file_handling::Recording> for Recording {
-    type Error = RecorderError;
-
-    fn try_from(r: file_handling::Recording) -> std::result::Result<Self, Self::Error> {
-        let users: Result<Vec<RecordingUser>, _> = r
-            .users
-            .into_iter()
-            .map(|user| {
-                user.file_name
-                    .clone()
-                    .into_string()
-                    .map(|file_name| RecordingUser {
-                        id: file_name,
-                        username: user.name,
-                    })
-            })
-            .collect();
-
-        Ok(Self {
+impl From<file_handling::RecordingManifest> for Recording {
+    fn from(r: file_handling::RecordingManifest) -> Self {
+        Self {
             guild_id: Snowflake(r.guild_id),
-            timestamp: r
-                .timestamp
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .map_err(|_| {
-                    RecorderError::InternalError(String::from("Failed to handle timestamps"))
-                })?
-                .as_secs(),
+            timestamp: r.timestamp,
             length: r.length,
-            users: users?,
-        })
+            triggered_by: r.triggered_by.map(Snowflake),
+            pinned: r.pinned,
+            users: r
+                .tracks
+                .into_iter()
+                .map(|track| RecordingUser {
+                    id: track.file_name,
+                    username: track.name,
+                    user_id: track.user_id.map(Snowflake),
+                    start_offset: track.start_offset,
+                    length: track.length,
+                })
+                .collect(),
+        }
     }
 }
 
@@ -161,11 +172,23 @@ async fn get_recordings(
 ) -> Result<Json<Vec<Recording>>, RecorderError> {
     let guilds = get_guilds_for_user(cache_http.inner(), &db, user.into()).await?;
     let mut results = vec![];
-    for (guild, _) in guilds.iter() {
+    for (guild, _) in guilds
+        .iter()
+        .filter(|(_, perm)| perm.has_capability(Capability::DownloadRecordings))
+    {
         results.append(&mut file_handling::get_recordings_for_guild(guild.id.get()).await?);
     }
-    let results: Result<Vec<_>, _> = results.into_iter().map(Recording::try_from).collect();
-    Ok(Json(results?))
+    Ok(Json(results.into_iter().map(Recording::from).collect()))
+}
+
+/// Memory and disk usage of the buffers of the recorder, e.g. for monitoring. They cover all guilds, so only
+/// operators of the bot may see them.
+#[get("/recorder/metrics")]
+async fn get_recorder_metrics(
+    client: &State<Client>,
+    _user: OperatorUserId,
+) -> Json<RecorderMetrics> {
+    Json(client.recorder.metrics())
 }
 
 #[derive(Deserialize, Debug)]
@@ -200,63 +223,119 @@ async fn mix_recording(
     user: UserId,
 ) -> Result<Json<MixingResult>, RecorderError> {
     let guild_id = GuildId::new(guild_id);
-    check_guild_user(cache_http.inner(), &db, user.into(), guild_id).await?;
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.into(),
+        guild_id,
+        Capability::DownloadRecordings,
+    )
+    .await?;
 
-    let params = params.0;
-    if params.user_ids.is_empty() {
+    let file_name = format!("{}.mp3", random::<u32>());
+    let out_file = file_handling::temp_path("mp3");
+    mix_tracks(
+        guild_id,
+        timestamp,
+        &params.user_ids,
+        params.start,
+        params.end,
+        &out_file,
+    )
+    .await?;
+
+    // The mix is stored, so that every instance can serve the download
+    let mix_key = file_handling::mix_key(guild_id.get(), &file_name);
+    let result = STORAGE.put_file(&mix_key, &out_file).await;
+    fs::remove_file(&out_file).await?;
+    result?;
+
+    // Automatically delete the mix once it timed out
+    let span = span!(Level::INFO, "mix_gc");
+    tokio::spawn(
+        async move {
+            sleep(MIX_LIFETIME).await;
+            let result = STORAGE.delete(&mix_key).await;
+            debug!(?mix_key, ?result, "Removing timed out mix");
+        }
+        .instrument(span),
+    );
+
+    Ok(Json(MixingResult {
+        download_url: format!(
+            "{}/api/guilds/{}/mixes/{}",
+            BASE_URL.clone(),
+            guild_id,
+            file_name
+        ),
+    }))
+}
+
+/// Mixes the tracks of the given users into an mp3 file at `out_file`. `start` and `end` are relative to the
+/// start of the recording, as the tracks are aligned at their end.
+pub async fn mix_tracks(
+    guild_id: GuildId,
+    timestamp: u64,
+    user_ids: &[String],
+    start: f32,
+    end: f32,
+    out_file: &Path,
+) -> Result<(), RecorderError> {
+    if user_ids.is_empty() {
         return Err(RecorderError::RequestError(String::from(
             "At least one user must be specified",
         )));
     }
-    if params.start >= params.end {
+    if start >= end {
         return Err(RecorderError::RequestError(String::from(
             "End must lie after Start",
         )));
     }
-    let folder = (*RECORDINGS_FOLDER)
-        .join(guild_id.get().to_string())
-        .join(timestamp.to_string());
-    if !folder.exists() {
+    let folder = file_handling::recording_folder_key(guild_id.get(), Some(timestamp));
+    if STORAGE.list(&folder).await?.is_empty() {
         return Err(RecorderError::NotFound(format!(
             "Recording {} not found",
             timestamp
         )));
     }
 
-    let files: Vec<_> = params
-        .user_ids
-        .iter()
-        .map(|user| folder.join(sanitize_filename::sanitize(user)))
-        .collect();
+    let mut files = Vec::new();
+    for user in user_ids {
+        files.push(
+            STORAGE
+                .local_path(&file_handling::recording_key(
+                    guild_id.get(),
+                    timestamp,
+                    user,
+                ))
+                .await?,
+        );
+    }
 
     let filter = format!(
         "amix=inputs={}:duration=longest, atrim={}:{}",
         files.len(),
-        params.start,
-        params.end
+        start,
+        end
     );
     let static_args = vec!["-ac", "2", "-filter_complex", &filter];
 
     let mut dynamic_args = Vec::new();
-    for file in files {
+    for file in &files {
         dynamic_args.push(OsString::from("-i"));
-        dynamic_args.push(file.into_os_string());
+        dynamic_args.push(file.as_os_str().to_os_string());
     }
 
-    let file_name = format!("{}.mp3", random::<u32>());
-    let out_dir = (*MIXES_FOLDER).join(guild_id.get().to_string());
-    fs::create_dir_all(&out_dir).await?;
-    let out_file = out_dir.join(&file_name);
-
     let ffmpeg_out = Command::new("ffmpeg")
         .kill_on_drop(true)
         .args(&static_args)
         .args(&dynamic_args)
-        .arg(&out_file)
+        .arg(out_file)
         .stdin(Stdio::null())
         .output()
         .await?;
     if !ffmpeg_out.status.success() {
+        fs::remove_file(out_file).await.ok();
         let output = String::from_utf8(ffmpeg_out.stderr);
         error!(?output, "Failed to mix file with ffmpeg");
         return Err(RecorderError::InternalError(String::from(
@@ -264,25 +343,7 @@ async fn mix_recording(
         )));
     }
 
-    // Automatically delete the mix after 5 minutes
-    let span = span!(Level::INFO, "mix_gc");
-    tokio::spawn(
-        async move {
-            sleep(Duration::from_secs(5 * 60)).await;
-            let result = fs::remove_file(Path::new(&out_file)).await;
-            debug!(?out_file, ?result, "Removing timed out mix");
-        }
-        .instrument(span),
-    );
-
-    Ok(Json(MixingResult {
-        download_url: format!(
-            "{}/api/guilds/{}/mixes/{}",
-            BASE_URL.clone(),
-            guild_id,
-            file_name
-        ),
-    }))
+    Ok(())
 }
 
 #[delete("/guilds/<guild_id>/recordings/<timestamp>")]
@@ -294,15 +355,95 @@ async fn delete_recording(
     user: UserId,
 ) -> Result<(), RecorderError> {
     let guild_id = GuildId::new(guild_id);
-    check_guild_user(cache_http.inner(), &db, user.into(), guild_id).await?;
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.clone().into(),
+        guild_id,
+        Capability::Record,
+    )
+    .await?;
 
-    let folder = (*RECORDINGS_FOLDER)
-        .join(guild_id.to_string())
-        .join(timestamp.to_string());
-    if !folder.exists() {
+    let folder = file_handling::recording_folder_key(guild_id.get(), Some(timestamp));
+    if STORAGE.list(&folder).await?.is_empty() {
         return Err(RecorderError::NotFound(String::from("Recording not found")));
     }
-    fs::remove_dir_all(folder).await?;
+    STORAGE.delete_folder(&folder).await?;
+
+    audit_log::log(
+        &db,
+        AuditEntry::new(guild_id, user.into(), AuditAction::RecordingDeleted).target(timestamp),
+    )
+    .await;
+
+    Ok(())
+}
+
+#[put("/guilds/<guild_id>/recordings/<timestamp>/pin")]
+async fn pin_recording(
+    guild_id: u64,
+    timestamp: u64,
+    cache_http: &State<CacheHttp>,
+    db: DbConn,
+    user: UserId,
+) -> Result<(), RecorderError> {
+    set_pinned(guild_id, timestamp, true, cache_http.inner(), db, user).await
+}
+
+#[delete("/guilds/<guild_id>/recordings/<timestamp>/pin")]
+async fn unpin_recording(
+    guild_id: u64,
+    timestamp: u64,
+    cache_http: &State<CacheHttp>,
+    db: DbConn,
+    user: UserId,
+) -> Result<(), RecorderError> {
+    set_pinned(guild_id, timestamp, false, cache_http.inner(), db, user).await
+}
+
+async fn set_pinned(
+    guild_id: u64,
+    timestamp: u64,
+    pinned: bool,
+    cache_http: &CacheHttp,
+    db: DbConn,
+    user: UserId,
+) -> Result<(), RecorderError> {
+    let guild_id = GuildId::new(guild_id);
+    check_guild_capability(
+        cache_http,
+        &db,
+        user.clone().into(),
+        guild_id,
+        Capability::Record,
+    )
+    .await?;
+
+    // Manifests of old recordings are written when they are listed, which happens before they can be pinned
+    let mut manifest = match file_handling::read_recording_manifest(guild_id.get(), timestamp).await
+    {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Err(RecorderError::NotFound(String::from("Recording not found")))
+        }
+        result => result?,
+    };
+    if manifest.pinned == pinned {
+        return Ok(());
+    }
+
+    manifest.pinned = pinned;
+    file_handling::save_recording_manifest(&manifest).await?;
+
+    let action = if pinned {
+        AuditAction::RecordingPinned
+    } else {
+        AuditAction::RecordingUnpinned
+    };
+    audit_log::log(
+        &db,
+        AuditEntry::new(guild_id, user.into(), action).target(timestamp),
+    )
+    .await;
 
     Ok(())
 }
@@ -317,18 +458,188 @@ async fn get_recording(
     user: UserId,
 ) -> Option<CachedFile> {
     let guild_id = GuildId::new(guild_id);
-    check_guild_user(cache_http.inner(), &db, user.into(), guild_id)
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.into(),
+        guild_id,
+        Capability::DownloadRecordings,
+    )
+    .await
+    .ok()?;
+
+    let path = STORAGE
+        .local_path(&file_handling::recording_key(
+            guild_id.get(),
+            timestamp,
+            &filename,
+        ))
         .await
         .ok()?;
+    CachedFile::open(path).await.ok()
+}
 
-    CachedFile::open(
-        (*RECORDINGS_FOLDER)
-            .join(guild_id.get().to_string())
-            .join(timestamp.to_string())
-            .join(sanitize_filename::sanitize(filename)),
+/// How all tracks of a recording are downloaded at once
+#[derive(FromFormField, Debug, Clone, Copy, PartialEq, Eq)]
+enum DownloadLayout {
+    /// The files of the tracks as they are stored, along with the manifest describing them
+    Zip,
+    /// A single WAV file with a pair of channels per track, in the order of the manifest
+    Multichannel,
+}
+
+#[derive(Responder)]
+struct RecordingDownload {
+    file: NamedFile,
+    disposition: Header<'static>,
+}
+
+/// Downloads all tracks of the recording in one file, e.g. for editing them in a DAW
+#[get("/guilds/<guild_id>/recordings/<timestamp>/download?<layout>")]
+async fn download_recording(
+    guild_id: u64,
+    timestamp: u64,
+    layout: DownloadLayout,
+    cache_http: &State<CacheHttp>,
+    db: DbConn,
+    user: UserId,
+) -> Result<RecordingDownload, RecorderError> {
+    let guild_id = GuildId::new(guild_id);
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.into(),
+        guild_id,
+        Capability::DownloadRecordings,
     )
-    .await
-    .ok()
+    .await?;
+
+    let manifest = match file_handling::read_recording_manifest(guild_id.get(), timestamp).await {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Err(RecorderError::NotFound(String::from("Recording not found")))
+        }
+        result => result?,
+    };
+    let mut files = Vec::new();
+    for track in &manifest.tracks {
+        files.push((
+            track.file_name.clone(),
+            STORAGE
+                .local_path(&file_handling::recording_key(
+                    guild_id.get(),
+                    timestamp,
+                    &track.file_name,
+                ))
+                .await?,
+        ));
+    }
+    if files.is_empty() {
+        return Err(RecorderError::NotFound(String::from(
+            "Recording has no tracks",
+        )));
+    }
+
+    let out_file = match layout {
+        DownloadLayout::Zip => {
+            let out_file = file_handling::temp_path("zip");
+            let manifest = serde_json::to_vec_pretty(&manifest).map_err(io::Error::other)?;
+            let path = out_file.clone();
+            tokio::task::spawn_blocking(move || write_zip(&path, &manifest, files))
+                .await
+                .map_err(io::Error::other)??;
+            out_file
+        }
+        DownloadLayout::Multichannel => {
+            // Tracks are aligned at the end, like for mixing. Only tracks of old recordings differ in length.
+            let mut filter = String::new();
+            for (i, track) in manifest.tracks.iter().enumerate() {
+                let delay = ((manifest.length - track.length).max(0.0) * 1000.0).round();
+                filter.push_str(&format!(
+                    "[{}:a]adelay={}:all=1,apad=whole_dur={}[a{}];",
+                    i, delay, manifest.length, i
+                ));
+            }
+            for i in 0..files.len() {
+                filter.push_str(&format!("[a{}]", i));
+            }
+            if files.len() > 1 {
+                filter.push_str(&format!("amerge=inputs={}[out]", files.len()));
+            } else {
+                filter.push_str("anull[out]");
+            }
+
+            let mut input_args = Vec::new();
+            for (_, file) in &files {
+                input_args.push(OsString::from("-i"));
+                input_args.push(file.as_os_str().to_os_string());
+            }
+
+            let out_file = file_handling::temp_path("wav");
+            let ffmpeg_out = Command::new("ffmpeg")
+                .kill_on_drop(true)
+                .args(&input_args)
+                .args(["-filter_complex", &filter, "-map", "[out]"])
+                .args(["-t", &manifest.length.to_string(), "-c:a", "pcm_s16le"])
+                .arg(&out_file)
+                .stdin(Stdio::null())
+                .output()
+                .await?;
+            if !ffmpeg_out.status.success() {
+                fs::remove_file(&out_file).await.ok();
+                let output = String::from_utf8(ffmpeg_out.stderr);
+                error!(?output, "Failed to merge tracks with ffmpeg");
+                return Err(RecorderError::InternalError(String::from(
+                    "Failed to merge the tracks of the recording",
+                )));
+            }
+            out_file
+        }
+    };
+
+    // The open file stays readable after it has been removed
+    let file = NamedFile::open(&out_file).await;
+    fs::remove_file(&out_file).await?;
+    let extension = out_file
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    Ok(RecordingDownload {
+        file: file?,
+        disposition: Header::new(
+            "Content-Disposition",
+            format!(
+                "attachment; filename=\"recording-{}-{}.{}\"",
+                guild_id, timestamp, extension
+            ),
+        ),
+    })
+}
+
+/// Writes the manifest and the files into a zip archive. Compressing audio gains little, so the files are only
+/// stored.
+fn write_zip(
+    path: &Path,
+    manifest: &[u8],
+    files: Vec<(String, LocalFile)>,
+) -> Result<(), std::io::Error> {
+    let options =
+        zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    let mut writer = zip::ZipWriter::new(std::fs::File::create(path)?);
+
+    writer
+        .start_file(file_handling::RECORDING_MANIFEST, options)
+        .map_err(std::io::Error::other)?;
+    writer.write_all(manifest)?;
+
+    for (name, file_path) in files {
+        writer
+            .start_file(name, options)
+            .map_err(std::io::Error::other)?;
+        std::io::copy(&mut std::fs::File::open(file_path)?, &mut writer)?;
+    }
+
+    writer.finish().map_err(std::io::Error::other)?.sync_all()
 }
 
 #[get("/guilds/<guild_id>/mixes/<filename>")]
@@ -340,15 +651,213 @@ async fn get_mix(
     user: UserId,
 ) -> Option<CachedFile> {
     let guild_id = GuildId::new(guild_id);
-    check_guild_user(cache_http.inner(), &db, user.into(), guild_id)
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.into(),
+        guild_id,
+        Capability::DownloadRecordings,
+    )
+    .await
+    .ok()?;
+
+    let path = STORAGE
+        .local_path(&file_handling::mix_key(guild_id.get(), &filename))
         .await
         .ok()?;
+    CachedFile::open(path).await.ok()
+}
 
-    CachedFile::open(
-        (*MIXES_FOLDER)
-            .join(guild_id.get().to_string())
-            .join(sanitize_filename::sanitize(filename)),
-    )
-    .await
-    .ok()
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Limits of the recordings of a guild. Pinned recordings are exempt and do not count towards them.
+#[derive(Debug)]
+struct RetentionPolicy {
+    guild_id: u64,
+    max_age: Option<Duration>,
+    max_count: Option<usize>,
+    max_storage: Option<u64>,
+}
+
+/// Periodically removes recordings that exceed the retention policy of their guild
+pub async fn purge_recordings_periodically(pool: ConnectionPool<DbConn, PgConnection>) {
+    let mut interval = tokio::time::interval(RETENTION_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = purge_recordings(&pool).await {
+            error!(?err, "Failed to purge recordings");
+        }
+    }
+}
+
+#[instrument(skip(pool), err)]
+async fn purge_recordings(
+    pool: &ConnectionPool<DbConn, PgConnection>,
+) -> Result<(), RecorderError> {
+    let conn = DbConn::from_pool(pool).await.ok_or_else(|| {
+        RecorderError::InternalError(String::from("No database connection available"))
+    })?;
+
+    let policies = conn
+        .run(|c| {
+            use crate::db::schema::guildsettings;
+
+            guildsettings::table
+                .filter(
+                    guildsettings::recording_max_age_days
+                        .is_not_null()
+                        .or(guildsettings::recording_max_count.is_not_null())
+                        .or(guildsettings::recording_max_storage_mb.is_not_null()),
+                )
+                .load::<models::GuildSettings>(c)
+        })
+        .await?
+        .into_iter()
+        .filter_map(|settings| {
+            let to_u64 = |value: Option<i32>| value.and_then(|value| u64::try_from(value).ok());
+            Some(RetentionPolicy {
+                guild_id: settings.id.to_u64()?,
+                max_age: to_u64(settings.recording_max_age_days)
+                    .map(|days| Duration::from_secs(days * 60 * 60 * 24)),
+                max_count: to_u64(settings.recording_max_count).map(|count| count as usize),
+                max_storage: to_u64(settings.recording_max_storage_mb)
+                    .and_then(|mb| mb.checked_mul(1024 * 1024)),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for policy in policies {
+        for timestamp in expired_recordings(&policy).await? {
+            STORAGE
+                .delete_folder(&file_handling::recording_folder_key(
+                    policy.guild_id,
+                    Some(timestamp),
+                ))
+                .await?;
+            info!(
+                guild_id = policy.guild_id,
+                timestamp, "Purged recording exceeding the retention policy"
+            );
+            audit_log::log(
+                &conn,
+                AuditEntry::system(GuildId::new(policy.guild_id), AuditAction::RecordingPurged)
+                    .target(timestamp),
+            )
+            .await;
+        }
+    }
+
+    Ok(())
+}
+
+/// Timestamps of the recordings that exceed the policy. The newest recordings are kept.
+async fn expired_recordings(policy: &RetentionPolicy) -> Result<Vec<u64>, RecorderError> {
+    let recordings = file_handling::get_recordings_for_guild(policy.guild_id).await?;
+    let sizes = file_handling::get_recording_sizes(policy.guild_id).await?;
+    let created_before = policy.max_age.and_then(|max_age| {
+        SystemTime::now()
+            .checked_sub(max_age)?
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .ok()
+            .map(|since_epoch| since_epoch.as_secs())
+    });
+
+    Ok(select_expired(policy, &recordings, &sizes, created_before))
+}
+
+/// Goes through the recordings newest first and selects those that do not fit into the policy anymore.
+/// `recordings` is ordered oldest first and `sizes` maps their timestamps to the storage they use.
+fn select_expired(
+    policy: &RetentionPolicy,
+    recordings: &[file_handling::RecordingManifest],
+    sizes: &BTreeMap<u64, u64>,
+    created_before: Option<u64>,
+) -> Vec<u64> {
+    let mut expired = Vec::new();
+    let mut kept_count = 0;
+    let mut kept_storage = 0;
+    for recording in recordings
+        .iter()
+        .rev()
+        .filter(|recording| !recording.pinned)
+    {
+        let size = sizes.get(&recording.timestamp).copied().unwrap_or(0);
+        let too_old = created_before.is_some_and(|before| recording.timestamp < before);
+        let too_many = policy.max_count.is_some_and(|max| kept_count >= max);
+        let too_large = policy
+            .max_storage
+            .is_some_and(|max| kept_storage + size > max);
+
+        if too_old || too_many || too_large {
+            expired.push(recording.timestamp);
+        } else {
+            kept_count += 1;
+            kept_storage += size;
+        }
+    }
+
+    expired
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recording(timestamp: u64, pinned: bool) -> file_handling::RecordingManifest {
+        let mut manifest =
+            file_handling::RecordingManifest::new(1, timestamp, 60.0, None, Vec::new());
+        manifest.pinned = pinned;
+        manifest
+    }
+
+    fn policy(max_count: Option<usize>, max_storage: Option<u64>) -> RetentionPolicy {
+        RetentionPolicy {
+            guild_id: 1,
+            max_age: None,
+            max_count,
+            max_storage,
+        }
+    }
+
+    #[test]
+    fn oldest_recordings_exceeding_the_count_are_expired() {
+        let recordings = [
+            recording(1, false),
+            recording(2, false),
+            recording(3, false),
+        ];
+
+        assert_eq!(
+            select_expired(&policy(Some(2), None), &recordings, &BTreeMap::new(), None),
+            vec![1]
+        );
+    }
+
+    #[test]
+    fn pinned_recordings_are_kept_and_not_counted() {
+        let recordings = [recording(1, true), recording(2, false), recording(3, false)];
+        let sizes = BTreeMap::from([(1, 100), (2, 10), (3, 10)]);
+
+        assert!(select_expired(&policy(Some(2), Some(20)), &recordings, &sizes, None).is_empty());
+    }
+
+    #[test]
+    fn recordings_exceeding_the_storage_or_age_are_expired() {
+        let recordings = [
+            recording(1, false),
+            recording(2, false),
+            recording(3, false),
+        ];
+        let sizes = BTreeMap::from([(1, 5), (2, 20), (3, 10)]);
+
+        assert_eq!(
+            select_expired(&policy(None, Some(25)), &recordings, &sizes, None),
+            vec![2]
+        );
+        assert_eq!(
+            select_expired(&policy(None, None), &recordings, &sizes, Some(3)),
+            vec![2, 1]
+        );
+    }
 }
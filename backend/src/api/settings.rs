@@ -12,23 +12,43 @@ use rocket::serde::json::Json;
 use rocket::Request;
 use rocket::Route;
 use rocket::State;
+use rocket_sync_db_pools::ConnectionPool;
 use serde::Deserialize;
 use serde::Serialize;
+use serde_json::json;
+use serde_json::Value as JsonValue;
 use serenity::model::id::GuildId;
 use thiserror::Error;
 
+use crate::api::audit_log;
+use crate::api::audit_log::AuditAction;
+use crate::api::audit_log::AuditEntry;
+use crate::api::categories::ensure_category;
+use crate::api::quotas;
+use crate::api::quotas::GuildQuota;
+use crate::api::quotas::GuildUsage;
+use crate::api::quotas::QuotaError;
+use crate::api::rate_limiter::MAX_PLAYS_PER_MINUTE;
 use crate::api::Snowflake;
 use crate::api::UserId;
 use crate::db::models;
 use crate::db::DbConn;
+use crate::discord::client::Client;
+use crate::discord::management::parse_capabilities;
+use crate::discord::management::Capability;
 use crate::discord::management::PermissionError;
-use crate::discord::management::{check_guild_admin, check_guild_moderator, get_guilds_for_user};
+use crate::discord::management::UserPermission;
+use crate::discord::management::{check_guild_capability, get_guilds_for_user};
+use crate::discord::recorder::RecordingFormat;
+use crate::discord::recorder::RECORDING_LENGTH;
 use crate::CacheHttp;
 
 pub fn get_routes() -> Vec<Route> {
     routes![
         get_all_random_infixes,
         set_random_infixes,
+        get_category_restrictions,
+        set_category_restrictions,
         get_guild_settings,
         set_guild_settings
     ]
@@ -47,6 +67,15 @@ enum SettingsError {
 
     #[error("Insufficient permission: you do not have the permission to perform this action")]
     InsufficientPermission(#[from] PermissionError),
+
+    #[error("{0}")]
+    QuotaError(#[from] QuotaError),
+
+    #[error("Invalid setting: {0}")]
+    InvalidSetting(String),
+
+    #[error("Internal error: {0}")]
+    InternalError(String),
 }
 
 impl From<serenity::Error> for SettingsError {
@@ -62,6 +91,9 @@ impl SettingsError {
             Self::DieselError(_) => Status::InternalServerError,
             Self::SerenityError(_) => Status::InternalServerError,
             Self::InsufficientPermission(_) => Status::Forbidden,
+            Self::QuotaError(err) => err.status_code(),
+            Self::InvalidSetting(_) => Status::BadRequest,
+            Self::InternalError(_) => Status::InternalServerError,
         }
     }
 }
@@ -127,7 +159,7 @@ async fn get_all_random_infixes(
     Ok(Json(infixes))
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 struct RandomInfixParameter {
     infix: String,
@@ -146,44 +178,294 @@ async fn set_random_infixes(
     cache_http: &State<CacheHttp>,
     params: Json<Vec<RandomInfixParameter>>,
 ) -> Result<(), SettingsError> {
-    check_guild_moderator(cache_http.inner(), &db, user.into(), GuildId::new(guild_id)).await?;
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.clone().into(),
+        GuildId::new(guild_id),
+        Capability::ManageSounds,
+    )
+    .await?;
 
+    let params = params.into_inner();
     let gid = BigDecimal::from_u64(guild_id).ok_or_else(|| SettingsError::NumericalError)?;
     let random_infixes = params
-        .into_inner()
-        .into_iter()
+        .iter()
         .map(|infix| models::RandomInfix {
             guild_id: gid.clone(),
+            infix: infix.infix.clone(),
+            display_name: infix.display_name.clone(),
+        })
+        .collect::<Vec<_>>();
+
+    let previous_infixes = db
+        .run(move |c| {
+            use crate::db::schema::randominfixes::dsl::*;
+
+            let previous_infixes = randominfixes
+                .filter(guild_id.eq(&gid))
+                .load::<models::RandomInfix>(c)?;
+
+            // Delete all infixes and reinsert them
+            diesel::delete(randominfixes.filter(guild_id.eq(&gid))).execute(c)?;
+            diesel::insert_into(randominfixes)
+                .values(&random_infixes)
+                .execute(c)?;
+
+            Ok::<_, DieselError>(previous_infixes)
+        })
+        .await?
+        .into_iter()
+        .map(|infix| RandomInfixParameter {
             infix: infix.infix,
             display_name: infix.display_name,
         })
         .collect::<Vec<_>>();
 
-    db.run(move |c| {
-        use crate::db::schema::randominfixes::dsl::*;
+    audit_log::log(
+        &db,
+        AuditEntry::new(
+            GuildId::new(guild_id),
+            user.into(),
+            AuditAction::RandomInfixesUpdated,
+        )
+        .before(previous_infixes)
+        .after(params),
+    )
+    .await;
 
-        // Delete all infixes and reinsert them
-        diesel::delete(randominfixes.filter(guild_id.eq(&gid)))
-            .execute(c)
-            .and_then(|_| {
-                diesel::insert_into(randominfixes)
-                    .values(&random_infixes)
-                    .execute(c)
-            })
-    })
+    Ok(())
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CategoryRestriction {
+    category: String,
+    allowed_role_ids: Vec<Snowflake>,
+    allowed_channel_ids: Vec<Snowflake>,
+}
+
+impl TryFrom<models::CategoryRestriction> for CategoryRestriction {
+    type Error = SettingsError;
+
+    fn try_from(restriction: models::CategoryRestriction) -> Result<Self, Self::Error> {
+        let to_snowflakes = |ids: Vec<BigDecimal>| {
+            ids.into_iter()
+                .map(|id| {
+                    id.to_u64()
+                        .map(Snowflake)
+                        .ok_or_else(|| SettingsError::NumericalError)
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(Self {
+            category: restriction.category,
+            allowed_role_ids: to_snowflakes(restriction.allowed_role_ids)?,
+            allowed_channel_ids: to_snowflakes(restriction.allowed_channel_ids)?,
+        })
+    }
+}
+
+#[get("/guilds/<guild_id>/category-restrictions")]
+async fn get_category_restrictions(
+    guild_id: u64,
+    user: UserId,
+    db: DbConn,
+    cache_http: &State<CacheHttp>,
+) -> Result<Json<Vec<CategoryRestriction>>, SettingsError> {
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.into(),
+        GuildId::new(guild_id),
+        Capability::ManageSounds,
+    )
+    .await?;
+
+    let gid = BigDecimal::from_u64(guild_id).ok_or_else(|| SettingsError::NumericalError)?;
+    let restrictions = db
+        .run(move |c| {
+            use crate::db::schema::categoryrestrictions;
+
+            categoryrestrictions::table
+                .filter(categoryrestrictions::guild_id.eq(gid))
+                .load::<models::CategoryRestriction>(c)
+        })
+        .await?
+        .into_iter()
+        .map(CategoryRestriction::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Json(restrictions))
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CategoryRestrictionParameter {
+    category: String,
+    #[serde(default)]
+    allowed_role_ids: Vec<Snowflake>,
+    #[serde(default)]
+    allowed_channel_ids: Vec<Snowflake>,
+}
+
+/// Replaces all category restrictions of the guild
+#[put(
+    "/guilds/<guild_id>/category-restrictions",
+    format = "json",
+    data = "<params>"
+)]
+async fn set_category_restrictions(
+    guild_id: u64,
+    user: UserId,
+    db: DbConn,
+    cache_http: &State<CacheHttp>,
+    params: Json<Vec<CategoryRestrictionParameter>>,
+) -> Result<(), SettingsError> {
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.clone().into(),
+        GuildId::new(guild_id),
+        Capability::ManageSounds,
+    )
     .await?;
 
+    let params = params.into_inner();
+    let gid = BigDecimal::from_u64(guild_id).ok_or_else(|| SettingsError::NumericalError)?;
+    let from_snowflakes = |ids: &[Snowflake]| {
+        ids.iter()
+            .map(|id| BigDecimal::from_u64(id.0).ok_or_else(|| SettingsError::NumericalError))
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let restrictions = params
+        .iter()
+        .map(|restriction| {
+            Ok(models::CategoryRestriction {
+                guild_id: gid.clone(),
+                category: restriction.category.clone(),
+                allowed_role_ids: from_snowflakes(&restriction.allowed_role_ids)?,
+                allowed_channel_ids: from_snowflakes(&restriction.allowed_channel_ids)?,
+            })
+        })
+        .collect::<Result<Vec<_>, SettingsError>>()?;
+
+    let previous_restrictions = db
+        .run(move |c| {
+            use crate::db::schema::categoryrestrictions;
+
+            let previous_restrictions = categoryrestrictions::table
+                .filter(categoryrestrictions::guild_id.eq(&gid))
+                .load::<models::CategoryRestriction>(c)?;
+
+            for restriction in restrictions.iter() {
+                ensure_category(c, &gid, &restriction.category)?;
+            }
+
+            // Delete all restrictions and reinsert them
+            diesel::delete(
+                categoryrestrictions::table.filter(categoryrestrictions::guild_id.eq(&gid)),
+            )
+            .execute(c)?;
+            diesel::insert_into(categoryrestrictions::table)
+                .values(&restrictions)
+                .execute(c)?;
+
+            Ok::<_, DieselError>(previous_restrictions)
+        })
+        .await?
+        .into_iter()
+        .map(CategoryRestriction::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    audit_log::log(
+        &db,
+        AuditEntry::new(
+            GuildId::new(guild_id),
+            user.into(),
+            AuditAction::CategoryRestrictionsUpdated,
+        )
+        .before(previous_restrictions)
+        .after(params),
+    )
+    .await;
+
+    Ok(())
+}
+
+/// Loads the buffer lengths and formats of the guilds into the recorder, which cannot access the database itself
+pub async fn load_recorder_settings(pool: ConnectionPool<DbConn, PgConnection>, client: Client) {
+    if let Err(err) = load_settings(&pool, &client).await {
+        error!(?err, "Failed to load recorder settings");
+    }
+}
+
+#[instrument(skip(pool, client), err)]
+async fn load_settings(
+    pool: &ConnectionPool<DbConn, PgConnection>,
+    client: &Client,
+) -> Result<(), SettingsError> {
+    let conn = pool.get().await.ok_or_else(|| {
+        SettingsError::InternalError(String::from("No database connection available"))
+    })?;
+
+    let settings = conn
+        .run(|c| {
+            use crate::db::schema::guildsettings;
+
+            guildsettings::table
+                .select((
+                    guildsettings::id,
+                    guildsettings::recording_length,
+                    guildsettings::recording_format,
+                ))
+                .load::<(BigDecimal, Option<i32>, String)>(c)
+        })
+        .await?;
+
+    let mut lengths = HashMap::new();
+    let mut formats = HashMap::new();
+    for (guild_id, length, format) in settings
+        .into_iter()
+        .filter_map(|(gid, length, format)| Some((gid.to_u64()?, length, format)))
+    {
+        if let Some(length) = length.and_then(|length| u64::try_from(length).ok()) {
+            lengths.insert(guild_id, length);
+        }
+        if let Some(format) = RecordingFormat::from_db_str(&format) {
+            formats.insert(guild_id, format);
+        }
+    }
+    client.recorder.set_buffer_lengths(lengths).await;
+    client.recorder.set_formats(formats).await;
+
     Ok(())
 }
 
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 struct GuildSettings {
-    user_role_id: Option<Snowflake>,
-    moderator_role_id: Option<Snowflake>,
+    user_role_ids: Vec<Snowflake>,
+    moderator_role_ids: Vec<Snowflake>,
+    user_capabilities: Vec<Capability>,
+    moderator_capabilities: Vec<Capability>,
     target_max_volume: f32,
     target_mean_volume: f32,
+    user_plays_per_minute: Option<i32>,
+    guild_plays_per_minute: Option<i32>,
+    recording_max_age_days: Option<i32>,
+    recording_max_count: Option<i32>,
+    recording_max_storage_mb: Option<i32>,
+    recording_opt_in_required: bool,
+    /// Buffer length in seconds. `None` uses the maximum.
+    recording_length: Option<i32>,
+    max_recording_length: u64,
+    recording_format: RecordingFormat,
     roles: HashMap<Snowflake, String>,
+    /// Limits set by the bot operator. They cannot be changed through the settings.
+    quota: GuildQuota,
+    usage: GuildUsage,
 }
 
 #[get("/guilds/<guild_id>/settings")]
@@ -194,41 +476,55 @@ async fn get_guild_settings(
     cache_http: &State<CacheHttp>,
 ) -> Result<Json<GuildSettings>, SettingsError> {
     let guild_id = GuildId::new(guild_id);
-    check_guild_admin(cache_http.inner(), user.into(), guild_id).await?;
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.into(),
+        guild_id,
+        Capability::ManageSettings,
+    )
+    .await?;
 
     let gid = BigDecimal::from_u64(guild_id.get()).ok_or_else(|| SettingsError::NumericalError)?;
-    let guild_settings = db
+    let (guild_settings, guild_roles) = db
         .run(move |c| {
-            use crate::db::schema::guildsettings::dsl::*;
+            use crate::db::schema::guildroles;
+            use crate::db::schema::guildsettings;
 
             // Ensure that an entry for the guild is always present
-            diesel::insert_into(guildsettings)
-                .values(id.eq(gid.clone()))
-                .on_conflict(id)
+            diesel::insert_into(guildsettings::table)
+                .values(guildsettings::id.eq(gid.clone()))
+                .on_conflict(guildsettings::id)
                 .do_nothing()
-                .execute(c)
-                .and_then(|_| guildsettings.find(gid).first::<models::GuildSettings>(c))
+                .execute(c)?;
+
+            let guild_settings = guildsettings::table
+                .find(gid.clone())
+                .first::<models::GuildSettings>(c)?;
+            let guild_roles = guildroles::table
+                .filter(guildroles::guild_id.eq(gid))
+                .load::<models::GuildRole>(c)?;
+
+            Ok::<_, DieselError>((guild_settings, guild_roles))
         })
         .await?;
 
-    let user_role_id = guild_settings
-        .user_role_id
-        .map(|role_id| {
-            role_id
-                .to_u64()
-                .ok_or_else(|| SettingsError::NumericalError)
-        })
-        .map_or(Ok(None), |r| r.map(Some))?
-        .map(Snowflake);
-    let moderator_role_id = guild_settings
-        .moderator_role_id
-        .map(|role_id| {
-            role_id
+    let mut user_role_ids = vec![];
+    let mut moderator_role_ids = vec![];
+    for guild_role in guild_roles {
+        let role_id = Snowflake(
+            guild_role
+                .role_id
                 .to_u64()
-                .ok_or_else(|| SettingsError::NumericalError)
-        })
-        .map_or(Ok(None), |r| r.map(Some))?
-        .map(Snowflake);
+                .ok_or_else(|| SettingsError::NumericalError)?,
+        );
+
+        if guild_role.permission_level == UserPermission::Moderator.as_str() {
+            moderator_role_ids.push(role_id);
+        } else {
+            user_role_ids.push(role_id);
+        }
+    }
 
     let roles = guild_id
         .to_partial_guild(&cache_http.inner().http)
@@ -237,25 +533,55 @@ async fn get_guild_settings(
         .into_iter()
         .map(|(role_id, role)| (Snowflake(role_id.get()), role.name))
         .collect::<HashMap<_, _>>();
+    let quota = quotas::get_quota(&db, guild_id).await?;
+    let usage = quotas::get_usage(&db, guild_id).await?;
 
     Ok(Json(GuildSettings {
-        user_role_id,
-        moderator_role_id,
+        user_role_ids,
+        moderator_role_ids,
+        user_capabilities: parse_capabilities(&guild_settings.user_capabilities),
+        moderator_capabilities: parse_capabilities(&guild_settings.moderator_capabilities),
         target_max_volume: guild_settings.target_max_volume,
         target_mean_volume: guild_settings.target_mean_volume,
+        user_plays_per_minute: guild_settings.user_plays_per_minute,
+        guild_plays_per_minute: guild_settings.guild_plays_per_minute,
+        recording_max_age_days: guild_settings.recording_max_age_days,
+        recording_max_count: guild_settings.recording_max_count,
+        recording_max_storage_mb: guild_settings.recording_max_storage_mb,
+        recording_opt_in_required: guild_settings.recording_opt_in_required,
+        recording_length: guild_settings.recording_length,
+        max_recording_length: *RECORDING_LENGTH,
+        recording_format: RecordingFormat::from_db_str(&guild_settings.recording_format)
+            .unwrap_or_default(),
         roles,
+        quota,
+        usage,
     }))
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct GuildSettingsParameter {
-    #[serde(default, with = "::serde_with::rust::double_option")]
-    user_role_id: Option<Option<Snowflake>>,
-    #[serde(default, with = "::serde_with::rust::double_option")]
-    moderator_role_id: Option<Option<Snowflake>>,
+    user_role_ids: Option<Vec<Snowflake>>,
+    moderator_role_ids: Option<Vec<Snowflake>>,
+    user_capabilities: Option<Vec<Capability>>,
+    moderator_capabilities: Option<Vec<Capability>>,
     target_max_volume: Option<f32>,
     target_mean_volume: Option<f32>,
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    user_plays_per_minute: Option<Option<i32>>,
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    guild_plays_per_minute: Option<Option<i32>>,
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    recording_max_age_days: Option<Option<i32>>,
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    recording_max_count: Option<Option<i32>>,
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    recording_max_storage_mb: Option<Option<i32>>,
+    recording_opt_in_required: Option<bool>,
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    recording_length: Option<Option<i32>>,
+    recording_format: Option<RecordingFormat>,
 }
 
 #[put("/guilds/<guild_id>/settings", format = "json", data = "<params>")]
@@ -264,56 +590,274 @@ async fn set_guild_settings(
     user: UserId,
     db: DbConn,
     cache_http: &State<CacheHttp>,
+    client: &State<Client>,
     params: Json<GuildSettingsParameter>,
 ) -> Result<(), SettingsError> {
     let guild_id = GuildId::new(guild_id);
-    check_guild_admin(cache_http.inner(), user.into(), guild_id).await?;
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.clone().into(),
+        guild_id,
+        Capability::ManageSettings,
+    )
+    .await?;
 
     let gid = BigDecimal::from_u64(guild_id.get()).ok_or_else(|| SettingsError::NumericalError)?;
     let params = params.into_inner();
+    let recording_opt_in_required = params.recording_opt_in_required;
+    let recording_length = params.recording_length;
+    let recording_format = params.recording_format;
+    if let Some(Some(length)) = recording_length {
+        if length < 1 || length as u64 > *RECORDING_LENGTH {
+            return Err(SettingsError::InvalidSetting(format!(
+                "the recording length must be between 1 and {} seconds",
+                *RECORDING_LENGTH
+            )));
+        }
+    }
 
-    // We assume that the data is already present in the database at that point (queried at least once)
-    db.run(move |c| -> Result<(), SettingsError> {
-        use crate::db::schema::guildsettings;
-        // Performing separate update queries feeld kinda hacky. However, I cannot be bothered to fight Diesel.
-
-        if let Some(user_role_id) = params.user_role_id {
-            let role_id = user_role_id
-                .map(|rid| BigDecimal::from_u64(rid.0).ok_or_else(|| SettingsError::NumericalError))
-                .map_or(Ok(None), |r| r.map(Some))?;
+    for (name, limit) in [
+        ("maximum age of recordings", params.recording_max_age_days),
+        ("maximum number of recordings", params.recording_max_count),
+        (
+            "maximum storage of recordings",
+            params.recording_max_storage_mb,
+        ),
+    ] {
+        if let Some(Some(limit)) = limit {
+            if limit < 1 {
+                return Err(SettingsError::InvalidSetting(format!(
+                    "the {name} must be at least 1"
+                )));
+            }
+        }
+    }
 
-            diesel::update(guildsettings::table)
-                .filter(guildsettings::id.eq(gid.clone()))
-                .set(guildsettings::user_role_id.eq(role_id))
-                .execute(c)?;
+    for limit in [params.user_plays_per_minute, params.guild_plays_per_minute] {
+        if let Some(Some(limit)) = limit {
+            if !(1..=MAX_PLAYS_PER_MINUTE).contains(&limit) {
+                return Err(SettingsError::InvalidSetting(format!(
+                    "the plays per minute must be between 1 and {MAX_PLAYS_PER_MINUTE}"
+                )));
+            }
         }
+    }
 
-        if let Some(moderator_role_id) = params.moderator_role_id {
-            let role_id = moderator_role_id
-                .map(|rid| BigDecimal::from_u64(rid.0).ok_or_else(|| SettingsError::NumericalError))
-                .map_or(Ok(None), |r| r.map(Some))?;
+    let (before, after) = db
+        .run(move |c| -> Result<(JsonValue, JsonValue), SettingsError> {
+            use crate::db::schema::guildroles;
+            use crate::db::schema::guildsettings;
 
-            diesel::update(guildsettings::table)
-                .filter(guildsettings::id.eq(gid.clone()))
-                .set(guildsettings::moderator_role_id.eq(role_id))
-                .execute(c)?;
-        }
+            let before = settings_snapshot(c, &gid)?;
 
-        if let Some(target_max_volume) = params.target_max_volume {
-            diesel::update(guildsettings::table)
-                .filter(guildsettings::id.eq(gid.clone()))
-                .set(guildsettings::target_max_volume.eq(target_max_volume))
+            // The settings have not necessarily been queried before, so the entry may still be missing
+            diesel::insert_into(guildsettings::table)
+                .values(guildsettings::id.eq(gid.clone()))
+                .on_conflict(guildsettings::id)
+                .do_nothing()
                 .execute(c)?;
-        }
 
-        if let Some(target_mean_volume) = params.target_mean_volume {
-            diesel::update(guildsettings::table)
-                .filter(guildsettings::id.eq(gid))
-                .set(guildsettings::target_mean_volume.eq(target_mean_volume))
-                .execute(c)?;
-        }
+            // Performing separate update queries feeld kinda hacky. However, I cannot be bothered to fight Diesel.
+
+            for (permission, role_ids) in [
+                (UserPermission::User, params.user_role_ids),
+                (UserPermission::Moderator, params.moderator_role_ids),
+            ] {
+                if let Some(role_ids) = role_ids {
+                    let guild_roles = role_ids
+                        .into_iter()
+                        .map(|rid| {
+                            Ok(models::GuildRole {
+                                guild_id: gid.clone(),
+                                role_id: BigDecimal::from_u64(rid.0)
+                                    .ok_or_else(|| SettingsError::NumericalError)?,
+                                permission_level: permission.as_str().to_string(),
+                            })
+                        })
+                        .collect::<Result<Vec<_>, SettingsError>>()?;
+
+                    // Replace all roles of this level. A role can only be assigned to one level, so
+                    // it is moved over if it was assigned to the other one before.
+                    diesel::delete(
+                        guildroles::table
+                            .filter(guildroles::guild_id.eq(gid.clone()))
+                            .filter(guildroles::permission_level.eq(permission.as_str())),
+                    )
+                    .execute(c)?;
+                    diesel::insert_into(guildroles::table)
+                        .values(&guild_roles)
+                        .on_conflict((guildroles::guild_id, guildroles::role_id))
+                        .do_update()
+                        .set(guildroles::permission_level.eq(permission.as_str()))
+                        .execute(c)?;
+                }
+            }
+
+            if let Some(user_capabilities) = params.user_capabilities {
+                let user_capabilities = user_capabilities
+                    .iter()
+                    .map(|capability| capability.as_str().to_string())
+                    .collect::<Vec<_>>();
+
+                diesel::update(guildsettings::table)
+                    .filter(guildsettings::id.eq(gid.clone()))
+                    .set(guildsettings::user_capabilities.eq(user_capabilities))
+                    .execute(c)?;
+            }
+
+            if let Some(moderator_capabilities) = params.moderator_capabilities {
+                let moderator_capabilities = moderator_capabilities
+                    .iter()
+                    .map(|capability| capability.as_str().to_string())
+                    .collect::<Vec<_>>();
+
+                diesel::update(guildsettings::table)
+                    .filter(guildsettings::id.eq(gid.clone()))
+                    .set(guildsettings::moderator_capabilities.eq(moderator_capabilities))
+                    .execute(c)?;
+            }
+
+            if let Some(target_max_volume) = params.target_max_volume {
+                diesel::update(guildsettings::table)
+                    .filter(guildsettings::id.eq(gid.clone()))
+                    .set(guildsettings::target_max_volume.eq(target_max_volume))
+                    .execute(c)?;
+            }
+
+            if let Some(user_plays_per_minute) = params.user_plays_per_minute {
+                diesel::update(guildsettings::table)
+                    .filter(guildsettings::id.eq(gid.clone()))
+                    .set(guildsettings::user_plays_per_minute.eq(user_plays_per_minute))
+                    .execute(c)?;
+            }
+
+            if let Some(guild_plays_per_minute) = params.guild_plays_per_minute {
+                diesel::update(guildsettings::table)
+                    .filter(guildsettings::id.eq(gid.clone()))
+                    .set(guildsettings::guild_plays_per_minute.eq(guild_plays_per_minute))
+                    .execute(c)?;
+            }
+
+            if let Some(recording_max_age_days) = params.recording_max_age_days {
+                diesel::update(guildsettings::table)
+                    .filter(guildsettings::id.eq(gid.clone()))
+                    .set(guildsettings::recording_max_age_days.eq(recording_max_age_days))
+                    .execute(c)?;
+            }
+
+            if let Some(recording_max_count) = params.recording_max_count {
+                diesel::update(guildsettings::table)
+                    .filter(guildsettings::id.eq(gid.clone()))
+                    .set(guildsettings::recording_max_count.eq(recording_max_count))
+                    .execute(c)?;
+            }
+
+            if let Some(recording_max_storage_mb) = params.recording_max_storage_mb {
+                diesel::update(guildsettings::table)
+                    .filter(guildsettings::id.eq(gid.clone()))
+                    .set(guildsettings::recording_max_storage_mb.eq(recording_max_storage_mb))
+                    .execute(c)?;
+            }
+
+            if let Some(recording_opt_in_required) = params.recording_opt_in_required {
+                diesel::update(guildsettings::table)
+                    .filter(guildsettings::id.eq(gid.clone()))
+                    .set(guildsettings::recording_opt_in_required.eq(recording_opt_in_required))
+                    .execute(c)?;
+            }
+
+            if let Some(recording_length) = params.recording_length {
+                diesel::update(guildsettings::table)
+                    .filter(guildsettings::id.eq(gid.clone()))
+                    .set(guildsettings::recording_length.eq(recording_length))
+                    .execute(c)?;
+            }
+
+            if let Some(recording_format) = params.recording_format {
+                diesel::update(guildsettings::table)
+                    .filter(guildsettings::id.eq(gid.clone()))
+                    .set(guildsettings::recording_format.eq(recording_format.as_str()))
+                    .execute(c)?;
+            }
+
+            if let Some(target_mean_volume) = params.target_mean_volume {
+                diesel::update(guildsettings::table)
+                    .filter(guildsettings::id.eq(gid.clone()))
+                    .set(guildsettings::target_mean_volume.eq(target_mean_volume))
+                    .execute(c)?;
+            }
+
+            let after = settings_snapshot(c, &gid)?;
+            Ok((before, after))
+        })
+        .await?;
+
+    // The recorder cannot read the settings from the database
+    if let Some(required) = recording_opt_in_required {
+        client
+            .recorder
+            .set_opt_in_required(guild_id.get(), required)
+            .await;
+    }
+    if let Some(length) = recording_length {
+        client
+            .recorder
+            .set_buffer_length(guild_id.get(), length.map(|length| length as u64))
+            .await;
+    }
+    if let Some(format) = recording_format {
+        client.recorder.set_format(guild_id.get(), format).await;
+    }
+
+    audit_log::log(
+        &db,
+        AuditEntry::new(guild_id, user.into(), AuditAction::SettingsUpdated)
+            .before(before)
+            .after(after),
+    )
+    .await;
 
-        Ok(())
-    })
-    .await
+    Ok(())
+}
+
+/// Stored settings and roles of a guild as recorded in the audit log
+/// Returns null if there are no settings stored for the guild yet
+fn settings_snapshot(c: &mut PgConnection, gid: &BigDecimal) -> Result<JsonValue, DieselError> {
+    use crate::db::schema::guildroles;
+    use crate::db::schema::guildsettings;
+
+    let guild_settings = match guildsettings::table
+        .find(gid)
+        .first::<models::GuildSettings>(c)
+        .optional()?
+    {
+        Some(guild_settings) => guild_settings,
+        None => return Ok(JsonValue::Null),
+    };
+    let guild_roles = guildroles::table
+        .filter(guildroles::guild_id.eq(gid))
+        .load::<models::GuildRole>(c)?;
+
+    Ok(json!({
+        "roles": guild_roles
+            .iter()
+            .map(|role| json!({
+                "roleId": role.role_id.to_string(),
+                "permissionLevel": role.permission_level,
+            }))
+            .collect::<Vec<_>>(),
+        "userCapabilities": guild_settings.user_capabilities,
+        "moderatorCapabilities": guild_settings.moderator_capabilities,
+        "targetMaxVolume": guild_settings.target_max_volume,
+        "targetMeanVolume": guild_settings.target_mean_volume,
+        "userPlaysPerMinute": guild_settings.user_plays_per_minute,
+        "guildPlaysPerMinute": guild_settings.guild_plays_per_minute,
+        "recordingMaxAgeDays": guild_settings.recording_max_age_days,
+        "recordingMaxCount": guild_settings.recording_max_count,
+        "recordingMaxStorageMb": guild_settings.recording_max_storage_mb,
+        "recordingOptInRequired": guild_settings.recording_opt_in_required,
+        "recordingLength": guild_settings.recording_length,
+        "recordingFormat": guild_settings.recording_format,
+    }))
 }
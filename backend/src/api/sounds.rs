@@ -1,12 +1,17 @@
+use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::env::var;
 use std::num::TryFromIntError;
-use std::path::PathBuf;
+use std::path::Path;
+use std::sync::LazyLock;
+use std::time::Duration;
 use std::time::SystemTime;
 
 use bigdecimal::BigDecimal;
 use bigdecimal::FromPrimitive;
 use bigdecimal::ToPrimitive;
 use diesel::prelude::*;
+use diesel::result::DatabaseErrorKind;
 use diesel::result::Error as DieselError;
 use rocket::fs::NamedFile;
 use rocket::fs::TempFile;
@@ -17,25 +22,42 @@ use rocket::serde::json::Json;
 use rocket::Request;
 use rocket::Route;
 use rocket::State;
+use rocket_sync_db_pools::ConnectionPool;
 use serde::Deserialize;
 use serde::Serialize;
+use serde_json::json;
 use serde_with::serde_as;
 use serde_with::TimestampSeconds;
 use serenity::model::id::GuildId;
+use serenity::model::id::UserId as SerenityUserId;
 use thiserror::Error;
 use tokio::fs;
 
+use crate::api::audit_log;
+use crate::api::audit_log::AuditAction;
+use crate::api::audit_log::AuditEntry;
 use crate::api::auth::TokenUserId;
 use crate::api::auth::UserId;
+use crate::api::categories;
+use crate::api::categories::ensure_category;
+use crate::api::quotas;
+use crate::api::quotas::GuildQuota;
+use crate::api::quotas::QuotaError;
+use crate::api::rate_limiter::MAX_COOLDOWN_SECONDS;
+use crate::api::recorder;
+use crate::api::recorder::RecorderError;
 use crate::api::Snowflake;
 use crate::audio_utils;
 use crate::db::models;
 use crate::db::DbConn;
-use crate::discord::management::check_guild_moderator;
-use crate::discord::management::check_guild_user;
+use crate::discord::management::check_guild_capability;
 use crate::discord::management::get_guilds_for_user;
+use crate::discord::management::get_permission_level;
+use crate::discord::management::Capability;
 use crate::discord::management::PermissionError;
+use crate::discord::management::PlayRestriction;
 use crate::file_handling;
+use crate::storage::STORAGE;
 use crate::CacheHttp;
 
 pub fn get_routes() -> Vec<Route> {
@@ -45,12 +67,19 @@ pub fn get_routes() -> Vec<Route> {
         create_sound,
         update_sound,
         delete_sound,
-        upload_sound
+        list_trash,
+        restore_sound,
+        upload_sound,
+        copy_sound,
+        create_sound_from_recording,
+        list_versions,
+        revert_sound,
+        delete_version
     ]
 }
 
 #[derive(Debug, Error)]
-enum SoundsError {
+pub enum SoundsError {
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
 
@@ -72,11 +101,23 @@ enum SoundsError {
     #[error("Invalid sound file: {0}")]
     InvalidSoundfile(String),
 
+    #[error("Invalid parameter: {0}")]
+    InvalidParameter(String),
+
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
     #[error("Number conversion error: {0}")]
     NumberConversion(#[from] TryFromIntError),
 
     #[error("Number handling error")]
     BigDecimalError,
+
+    #[error("{0}")]
+    QuotaError(#[from] QuotaError),
+
+    #[error("{0}")]
+    RecorderError(Box<RecorderError>),
 }
 
 impl From<serenity::Error> for SoundsError {
@@ -85,12 +126,26 @@ impl From<serenity::Error> for SoundsError {
     }
 }
 
+impl From<RecorderError> for SoundsError {
+    fn from(err: RecorderError) -> Self {
+        Self::RecorderError(Box::new(err))
+    }
+}
+
 impl From<DieselError> for SoundsError {
     fn from(err: DieselError) -> Self {
-        if err == DieselError::NotFound {
-            Self::NotFound(String::from("A sound with the given id does not exist"))
-        } else {
-            Self::DieselError(err)
+        match err {
+            DieselError::NotFound => {
+                Self::NotFound(String::from("A sound with the given id does not exist"))
+            }
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, ref info)
+                if info.constraint_name() == Some(UNIQUE_NAME_INDEX) =>
+            {
+                Self::Conflict(String::from(
+                    "a sound with this name already exists in the guild",
+                ))
+            }
+            err => Self::DieselError(err),
         }
     }
 }
@@ -105,8 +160,12 @@ impl SoundsError {
             Self::InsufficientPermission(_) => Status::Forbidden,
             Self::NotFound(_) => Status::NotFound,
             Self::InvalidSoundfile(_) => Status::BadRequest,
+            Self::InvalidParameter(_) => Status::BadRequest,
+            Self::Conflict(_) => Status::Conflict,
             Self::NumberConversion(_) => Status::InternalServerError,
             Self::BigDecimalError => Status::InternalServerError,
+            Self::QuotaError(err) => err.status_code(),
+            Self::RecorderError(err) => err.status_code(),
         }
     }
 }
@@ -133,6 +192,16 @@ struct Sound {
     #[serde_as(as = "TimestampSeconds<String>")]
     created_at: SystemTime,
     volume_adjustment: Option<f32>,
+    allowed_role_ids: Vec<Snowflake>,
+    allowed_channel_ids: Vec<Snowflake>,
+    cooldown: Option<f32>,
+    play_count: i32,
+    #[serde_as(as = "Option<TimestampSeconds<String>>")]
+    deleted_at: Option<SystemTime>,
+    tags: Vec<String>,
+    aliases: Vec<String>,
+    /// Whether the requesting user marked the sound as favorite. Only set in the sound list.
+    favorite: bool,
     sound_file: Option<Soundfile>,
 }
 
@@ -163,6 +232,14 @@ impl TryFrom<(models::Sound, Option<models::Soundfile>)> for Sound {
             category: s.category,
             created_at: s.created_at,
             volume_adjustment: s.volume_adjustment,
+            allowed_role_ids: to_snowflakes(&s.allowed_role_ids)?,
+            allowed_channel_ids: to_snowflakes(&s.allowed_channel_ids)?,
+            cooldown: s.cooldown,
+            play_count: s.play_count,
+            deleted_at: s.deleted_at,
+            tags: vec![],
+            aliases: vec![],
+            favorite: false,
             sound_file: f.map(|f| Soundfile {
                 max_volume: f.max_volume,
                 mean_volume: f.mean_volume,
@@ -173,38 +250,516 @@ impl TryFrom<(models::Sound, Option<models::Soundfile>)> for Sound {
     }
 }
 
-#[get("/")]
+fn to_snowflakes(ids: &[BigDecimal]) -> Result<Vec<Snowflake>, SoundsError> {
+    ids.iter()
+        .map(|id| {
+            id.to_u64()
+                .map(Snowflake)
+                .ok_or_else(|| SoundsError::BigDecimalError)
+        })
+        .collect()
+}
+
+fn from_snowflakes(ids: Vec<Snowflake>) -> Result<Vec<BigDecimal>, SoundsError> {
+    ids.into_iter()
+        .map(|id| BigDecimal::from_u64(id.0).ok_or_else(|| SoundsError::BigDecimalError))
+        .collect()
+}
+
+/// Labels like tags or aliases by sound id
+pub type LabelsBySound = HashMap<i32, Vec<String>>;
+
+/// Tags and aliases of the given sounds by sound id, each sorted alphabetically
+pub fn load_tags_and_aliases(
+    c: &mut PgConnection,
+    sound_ids: &[i32],
+) -> Result<(LabelsBySound, LabelsBySound), DieselError> {
+    use crate::db::schema::soundaliases;
+    use crate::db::schema::soundtags;
+    use crate::db::schema::tags;
+
+    let mut sound_tags = HashMap::<i32, Vec<String>>::new();
+    for (sound_id, tag) in soundtags::table
+        .inner_join(tags::table)
+        .filter(soundtags::sound_id.eq_any(sound_ids))
+        .select((soundtags::sound_id, tags::name))
+        .order(tags::name.asc())
+        .load::<(i32, String)>(c)?
+    {
+        sound_tags.entry(sound_id).or_default().push(tag);
+    }
+
+    let mut sound_aliases = HashMap::<i32, Vec<String>>::new();
+    for (sound_id, alias) in soundaliases::table
+        .filter(soundaliases::sound_id.eq_any(sound_ids))
+        .order(soundaliases::alias.asc())
+        .load::<(i32, String)>(c)?
+    {
+        sound_aliases.entry(sound_id).or_default().push(alias);
+    }
+
+    Ok((sound_tags, sound_aliases))
+}
+
+/// Converts the sound and adds its tags and aliases
+fn load_sound(c: &mut PgConnection, sound: models::Sound) -> Result<Sound, SoundsError> {
+    let sound_id = sound.id;
+    let (mut tags, mut aliases) = load_tags_and_aliases(c, &[sound_id])?;
+
+    let mut sound = Sound::try_from((sound, None))?;
+    sound.tags = tags.remove(&sound_id).unwrap_or_default();
+    sound.aliases = aliases.remove(&sound_id).unwrap_or_default();
+    Ok(sound)
+}
+
+/// Maximum number of sounds returned per page
+const MAX_PAGE_SIZE: i64 = 500;
+
+/// Names, tags and aliases of sounds may not be longer than this, in characters
+const MAX_NAME_LENGTH: usize = 64;
+const MAX_LABEL_LENGTH: usize = 64;
+
+/// The index keeping the names of the sounds of a guild unique, trashed sounds excluded
+const UNIQUE_NAME_INDEX: &str = "sounds_guild_id_name_key";
+
+/// Checks that the name of a sound is neither empty nor too long
+fn validate_name(name: &str) -> Result<(), SoundsError> {
+    if name.trim().is_empty() {
+        Err(SoundsError::InvalidParameter(String::from(
+            "the name of a sound must not be empty",
+        )))
+    } else if name.chars().count() > MAX_NAME_LENGTH {
+        Err(SoundsError::InvalidParameter(format!(
+            "the name of a sound must not be longer than {} characters",
+            MAX_NAME_LENGTH
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Order of the sound list. Names and lengths are sorted ascending, creation dates and play counts
+/// descending. Sounds without a file come last when sorting by length. `Favorites` follows the personal
+/// order of the favorites of the user, followed by the other sounds.
+#[derive(FromFormField, Debug, Clone, Copy, PartialEq, Eq)]
+enum SoundSort {
+    Name,
+    Created,
+    Plays,
+    Length,
+    Favorites,
+}
+
+/// Position of a sound in the list. It is passed to clients as `<sort key>:<sound id>`.
+#[derive(Debug, Clone)]
+enum SoundCursor {
+    Name(String, i32),
+    Created(SystemTime, i32),
+    Plays(i32, i32),
+    Length(Option<f32>, i32),
+    Favorites(Option<i32>, i32),
+}
+
+impl SoundCursor {
+    fn of(
+        sort: SoundSort,
+        sound: &models::Sound,
+        soundfile: Option<&models::Soundfile>,
+        position: Option<i32>,
+    ) -> Self {
+        match sort {
+            SoundSort::Name => Self::Name(sound.name.clone(), sound.id),
+            SoundSort::Created => Self::Created(sound.created_at, sound.id),
+            SoundSort::Plays => Self::Plays(sound.play_count, sound.id),
+            SoundSort::Length => Self::Length(soundfile.map(|f| f.length), sound.id),
+            SoundSort::Favorites => Self::Favorites(position, sound.id),
+        }
+    }
+
+    fn parse(sort: SoundSort, value: &str) -> Option<Self> {
+        let (key, id) = value.rsplit_once(':')?;
+        let id = id.parse::<i32>().ok()?;
+
+        Some(match sort {
+            SoundSort::Name => Self::Name(key.to_string(), id),
+            SoundSort::Created => Self::Created(
+                SystemTime::UNIX_EPOCH + Duration::from_micros(key.parse::<u64>().ok()?),
+                id,
+            ),
+            SoundSort::Plays => Self::Plays(key.parse::<i32>().ok()?, id),
+            SoundSort::Length if key.is_empty() => Self::Length(None, id),
+            SoundSort::Length => Self::Length(Some(key.parse::<f32>().ok()?), id),
+            SoundSort::Favorites if key.is_empty() => Self::Favorites(None, id),
+            SoundSort::Favorites => Self::Favorites(Some(key.parse::<i32>().ok()?), id),
+        })
+    }
+
+    fn encode(&self) -> String {
+        match self {
+            Self::Name(name, id) => format!("{}:{}", name, id),
+            Self::Created(created_at, id) => format!(
+                "{}:{}",
+                created_at
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map(|duration| duration.as_micros())
+                    .unwrap_or(0),
+                id
+            ),
+            Self::Plays(play_count, id) => format!("{}:{}", play_count, id),
+            Self::Length(length, id) => format!(
+                "{}:{}",
+                length.map(|length| length.to_string()).unwrap_or_default(),
+                id
+            ),
+            Self::Favorites(position, id) => format!(
+                "{}:{}",
+                position
+                    .map(|position| position.to_string())
+                    .unwrap_or_default(),
+                id
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SoundFilter {
+    guild_ids: Vec<BigDecimal>,
+    category: Option<String>,
+    search: Option<String>,
+    tag: Option<String>,
+    name: Option<String>,
+    /// The user whose favorites are marked and sorted
+    user_id: BigDecimal,
+    /// Only include the favorites of the user
+    favorites_only: bool,
+}
+
+/// Escapes the wildcards of a LIKE pattern
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// A sound with its file and its position in the favorites of the user, if it is one
+type SoundRow = (models::Sound, Option<models::Soundfile>, Option<i32>);
+
+/// Loads the sounds matching the filter in the given order, starting after the cursor
+fn load_sounds(
+    c: &mut PgConnection,
+    filter: &SoundFilter,
+    sort: SoundSort,
+    cursor: Option<&SoundCursor>,
+    limit: Option<i64>,
+) -> Result<Vec<SoundRow>, DieselError> {
+    use crate::db::schema::favorites;
+    use crate::db::schema::soundaliases;
+    use crate::db::schema::soundfiles;
+    use crate::db::schema::sounds;
+    use crate::db::schema::soundtags;
+    use crate::db::schema::tags;
+
+    let mut query = sounds::table
+        .left_join(soundfiles::table)
+        .left_join(
+            favorites::table.on(favorites::sound_id
+                .eq(sounds::id)
+                .and(favorites::user_id.eq(filter.user_id.clone()))),
+        )
+        .select((
+            sounds::all_columns,
+            soundfiles::all_columns.nullable(),
+            favorites::position.nullable(),
+        ))
+        .filter(sounds::guild_id.eq_any(filter.guild_ids.clone()))
+        .filter(sounds::deleted_at.is_null())
+        .into_boxed();
+
+    if let Some(category) = &filter.category {
+        query = query.filter(sounds::category.eq(category.clone()));
+    }
+    if let Some(search) = &filter.search {
+        let pattern = format!("%{}%", escape_like(search));
+        query = query.filter(
+            sounds::name
+                .ilike(pattern.clone())
+                .or(sounds::category.ilike(pattern.clone()))
+                .or(sounds::id.eq_any(
+                    soundtags::table
+                        .inner_join(tags::table)
+                        .filter(tags::name.ilike(pattern.clone()))
+                        .select(soundtags::sound_id),
+                ))
+                .or(sounds::id.eq_any(
+                    soundaliases::table
+                        .filter(soundaliases::alias.ilike(pattern))
+                        .select(soundaliases::sound_id),
+                )),
+        );
+    }
+    if let Some(tag) = &filter.tag {
+        query = query.filter(
+            sounds::id.eq_any(
+                soundtags::table
+                    .inner_join(tags::table)
+                    .filter(tags::name.eq(tag.to_lowercase()))
+                    .select(soundtags::sound_id),
+            ),
+        );
+    }
+    if let Some(name) = &filter.name {
+        let pattern = escape_like(name);
+        query = query.filter(
+            sounds::name.ilike(pattern.clone()).or(sounds::id.eq_any(
+                soundaliases::table
+                    .filter(soundaliases::alias.ilike(pattern))
+                    .select(soundaliases::sound_id),
+            )),
+        );
+    }
+
+    if filter.favorites_only {
+        query = query.filter(favorites::position.nullable().is_not_null());
+    }
+
+    query = match cursor {
+        Some(SoundCursor::Name(name, id)) => query.filter(
+            sounds::name
+                .gt(name.clone())
+                .or(sounds::name.eq(name.clone()).and(sounds::id.gt(*id))),
+        ),
+        Some(SoundCursor::Created(created_at, id)) => query.filter(
+            sounds::created_at
+                .lt(*created_at)
+                .or(sounds::created_at.eq(*created_at).and(sounds::id.lt(*id))),
+        ),
+        Some(SoundCursor::Plays(play_count, id)) => query.filter(
+            sounds::play_count
+                .lt(*play_count)
+                .or(sounds::play_count.eq(*play_count).and(sounds::id.lt(*id))),
+        ),
+        Some(SoundCursor::Length(Some(length), id)) => query.filter(
+            soundfiles::length
+                .gt(*length)
+                .or(soundfiles::length.eq(*length).and(sounds::id.gt(*id)))
+                .or(soundfiles::length.is_null()),
+        ),
+        Some(SoundCursor::Length(None, id)) => {
+            query.filter(soundfiles::length.is_null().and(sounds::id.gt(*id)))
+        }
+        Some(SoundCursor::Favorites(Some(position), id)) => query.filter(
+            favorites::position
+                .nullable()
+                .gt(*position)
+                .or(favorites::position
+                    .nullable()
+                    .eq(*position)
+                    .and(sounds::id.gt(*id)))
+                .or(favorites::position.nullable().is_null()),
+        ),
+        Some(SoundCursor::Favorites(None, id)) => query.filter(
+            favorites::position
+                .nullable()
+                .is_null()
+                .and(sounds::id.gt(*id)),
+        ),
+        None => query,
+    };
+
+    query = match sort {
+        SoundSort::Name => query.order((sounds::name.asc(), sounds::id.asc())),
+        SoundSort::Created => query.order((sounds::created_at.desc(), sounds::id.desc())),
+        SoundSort::Plays => query.order((sounds::play_count.desc(), sounds::id.desc())),
+        // Postgres sorts null values last in ascending order
+        SoundSort::Length => query.order((soundfiles::length.asc(), sounds::id.asc())),
+        SoundSort::Favorites => {
+            query.order((favorites::position.nullable().asc(), sounds::id.asc()))
+        }
+    };
+
+    if let Some(limit) = limit {
+        query = query.limit(limit);
+    }
+
+    query.load::<SoundRow>(c)
+}
+
+/// A page of the sound list. If there are more sounds, the cursor for the next page is passed in
+/// the `X-Next-Cursor` header.
+struct SoundPage {
+    sounds: Vec<Sound>,
+    next_cursor: Option<String>,
+}
+
+impl<'r> Responder<'r, 'static> for SoundPage {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Json(self.sounds).respond_to(req)?;
+        if let Some(next_cursor) = self.next_cursor {
+            response.set_raw_header("X-Next-Cursor", next_cursor);
+        }
+
+        Ok(response)
+    }
+}
+
+/// Lists the sounds of the guilds of the user, optionally only of the guild `guild_id`.
+///
+/// Sounds can be filtered by `category`, by `tag` and by `name`, which matches the name of the sound or one of
+/// its aliases. `search` matches parts of the name, category, tags or aliases. All filters except the category
+/// are case-insensitive. With `favorites`, only the favorites of the user are listed, in their personal order
+/// unless another `sort` is given. If `limit` is given, sounds are returned in pages. The next page is
+/// requested by passing the cursor received with the previous one.
+#[allow(clippy::too_many_arguments)]
+#[get("/?<guild_id>&<category>&<search>&<tag>&<name>&<favorites>&<sort>&<cursor>&<limit>")]
 async fn list_sounds(
+    guild_id: Option<u64>,
+    category: Option<String>,
+    search: Option<String>,
+    tag: Option<String>,
+    name: Option<String>,
+    favorites: Option<bool>,
+    sort: Option<SoundSort>,
+    cursor: Option<String>,
+    limit: Option<i64>,
     cache_http: &State<CacheHttp>,
     db: DbConn,
     user: TokenUserId,
-) -> Result<Json<Vec<Sound>>, SoundsError> {
-    let guild_ids = get_guilds_for_user(cache_http.inner(), &db, user.into())
-        .await?
-        .into_iter()
-        .map(|(guildinfo, _)| {
-            BigDecimal::from_u64(guildinfo.id.get()).ok_or_else(|| SoundsError::BigDecimalError)
-        })
+) -> Result<SoundPage, SoundsError> {
+    let guilds = match guild_id {
+        Some(guild_id) => HashMap::from([(
+            guild_id,
+            get_permission_level(cache_http.inner(), &db, user.into(), GuildId::new(guild_id))
+                .await?,
+        )]),
+        None => get_guilds_for_user(cache_http.inner(), &db, user.into())
+            .await?
+            .into_iter()
+            .map(|(guildinfo, permission)| (guildinfo.id.get(), permission))
+            .collect::<HashMap<_, _>>(),
+    };
+    let guild_ids = guilds
+        .keys()
+        .map(|guild_id| BigDecimal::from_u64(*guild_id).ok_or_else(|| SoundsError::BigDecimalError))
         .collect::<Result<Vec<_>, _>>()?;
 
-    let sounds = db
+    let favorites_only = favorites.unwrap_or(false);
+    let sort = sort.unwrap_or(if favorites_only {
+        SoundSort::Favorites
+    } else {
+        SoundSort::Name
+    });
+    let mut cursor = cursor
+        .map(|cursor| {
+            SoundCursor::parse(sort, &cursor)
+                .ok_or_else(|| SoundsError::InvalidParameter(String::from("Invalid cursor")))
+        })
+        .transpose()?;
+    let limit = limit.map(|limit| limit.clamp(1, MAX_PAGE_SIZE));
+    let uid = BigDecimal::from_u64(SerenityUserId::from(user).get())
+        .ok_or_else(|| SoundsError::BigDecimalError)?;
+    let filter = SoundFilter {
+        guild_ids: guild_ids.clone(),
+        category,
+        search: search.map(|search| search.trim().to_string()),
+        tag: tag.map(|tag| tag.trim().to_string()),
+        name: name.map(|name| name.trim().to_string()),
+        user_id: uid,
+        favorites_only,
+    };
+
+    let category_restrictions = db
         .run(move |c| {
-            use crate::db::schema::soundfiles;
-            use crate::db::schema::sounds;
+            use crate::db::schema::categoryrestrictions;
 
-            sounds::table
-                .filter(sounds::guild_id.eq_any(guild_ids))
-                .left_join(soundfiles::table)
-                .load::<(models::Sound, Option<models::Soundfile>)>(c)
+            categoryrestrictions::table
+                .filter(categoryrestrictions::guild_id.eq_any(&guild_ids))
+                .load::<models::CategoryRestriction>(c)
         })
-        .await?;
+        .await?
+        .into_iter()
+        .map(|restriction| {
+            let guild_id = restriction
+                .guild_id
+                .to_u64()
+                .ok_or_else(|| SoundsError::BigDecimalError)?;
+            let play_restriction = PlayRestriction::from_db(
+                &restriction.allowed_role_ids,
+                &restriction.allowed_channel_ids,
+            )
+            .map_err(|_| SoundsError::BigDecimalError)?;
 
-    Ok(Json(
-        sounds
-            .into_iter()
-            .map(Sound::try_from)
-            .collect::<Result<Vec<_>, _>>()?,
-    ))
+            Ok(((guild_id, restriction.category), play_restriction))
+        })
+        .collect::<Result<HashMap<_, _>, SoundsError>>()?;
+
+    // Users only see the sounds their roles allow them to play. Whoever manages sounds sees all of them.
+    // As hidden sounds are filtered here, further batches are loaded until the page is full.
+    let mut visible_sounds = vec![];
+    let next_cursor = 'batches: loop {
+        let (sounds, mut tags, mut aliases) = {
+            let filter = filter.clone();
+            let cursor = cursor.clone();
+            db.run(move |c| {
+                let sounds = load_sounds(c, &filter, sort, cursor.as_ref(), limit)?;
+                let sound_ids = sounds
+                    .iter()
+                    .map(|(sound, _, _)| sound.id)
+                    .collect::<Vec<_>>();
+                let (tags, aliases) = load_tags_and_aliases(c, &sound_ids)?;
+
+                Ok::<_, DieselError>((sounds, tags, aliases))
+            })
+            .await?
+        };
+        let batch_size = sounds.len() as i64;
+
+        for (sound, soundfile, position) in sounds {
+            cursor = Some(SoundCursor::of(sort, &sound, soundfile.as_ref(), position));
+
+            let guild_id = sound
+                .guild_id
+                .to_u64()
+                .ok_or_else(|| SoundsError::BigDecimalError)?;
+            let permission = guilds
+                .get(&guild_id)
+                .ok_or_else(|| SoundsError::InternalError(String::from("Unexpected guild")))?;
+
+            let visible = permission.has_capability(Capability::ManageSounds)
+                || (PlayRestriction::from_db(&sound.allowed_role_ids, &sound.allowed_channel_ids)
+                    .map_err(|_| SoundsError::BigDecimalError)?
+                    .allows_member(permission)
+                    && category_restrictions
+                        .get(&(guild_id, sound.category.clone()))
+                        .map(|restriction| restriction.allows_member(permission))
+                        .unwrap_or(true));
+            if !visible {
+                continue;
+            }
+
+            let sound_id = sound.id;
+            let mut sound = Sound::try_from((sound, soundfile))?;
+            sound.tags = tags.remove(&sound_id).unwrap_or_default();
+            sound.aliases = aliases.remove(&sound_id).unwrap_or_default();
+            sound.favorite = position.is_some();
+            visible_sounds.push(sound);
+
+            if limit.is_some_and(|limit| visible_sounds.len() as i64 >= limit) {
+                break 'batches cursor.as_ref().map(SoundCursor::encode);
+            }
+        }
+
+        match limit {
+            Some(limit) if batch_size == limit => continue,
+            _ => break None,
+        }
+    };
+
+    Ok(SoundPage {
+        sounds: visible_sounds,
+        next_cursor,
+    })
 }
 
 #[get("/<sound_id>")]
@@ -222,6 +777,7 @@ async fn get_sound(
             soundfiles::table
                 .find(sound_id)
                 .inner_join(sounds::table)
+                .filter(sounds::deleted_at.is_null())
                 .select((soundfiles::file_name, sounds::guild_id))
                 .first::<(String, BigDecimal)>(c)
         })
@@ -233,10 +789,22 @@ async fn get_sound(
             .to_u64()
             .ok_or_else(|| SoundsError::BigDecimalError)?,
     );
-    check_guild_user(cache_http.inner(), &db, user.into(), guild_id).await?;
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.into(),
+        guild_id,
+        Capability::Play,
+    )
+    .await?;
 
     // We perform no caching as this request is authenticated
-    Ok(NamedFile::open(file_handling::get_full_sound_path(&filename)).await?)
+    Ok(NamedFile::open(
+        STORAGE
+            .local_path(&file_handling::sound_key(&filename))
+            .await?,
+    )
+    .await?)
 }
 
 #[derive(Deserialize, Debug)]
@@ -256,14 +824,17 @@ async fn create_sound(
     params: Json<CreateSoundParameter>,
 ) -> Result<Json<Sound>, SoundsError> {
     let params = params.into_inner();
+    validate_name(&params.name)?;
 
-    check_guild_moderator(
+    check_guild_capability(
         cache_http.inner(),
         &db,
         user.clone().into(),
         GuildId::new(params.guild_id.0),
+        Capability::ManageSounds,
     )
     .await?;
+    quotas::check_sound_count(&db, GuildId::new(params.guild_id.0), 1).await?;
 
     let uid = BigDecimal::from_u64(user.0).ok_or_else(|| SoundsError::BigDecimalError)?;
     let gid =
@@ -272,20 +843,36 @@ async fn create_sound(
         .run(move |c| {
             use crate::db::schema::sounds;
 
-            diesel::insert_into(sounds::table)
-                .values((
-                    sounds::guild_id.eq(gid),
-                    sounds::name.eq(params.name),
-                    sounds::category.eq(params.category),
-                    sounds::volume_adjustment.eq(params.volume_adjustment),
-                    sounds::created_by_user_id.eq(Some(uid.clone())),
-                    sounds::last_edited_by_user_id.eq(Some(uid)),
-                ))
-                .get_result::<models::Sound>(c)
+            c.transaction(|c| {
+                ensure_category(c, &gid, &params.category)?;
+                diesel::insert_into(sounds::table)
+                    .values((
+                        sounds::guild_id.eq(gid),
+                        sounds::name.eq(params.name),
+                        sounds::category.eq(params.category),
+                        sounds::volume_adjustment.eq(params.volume_adjustment),
+                        sounds::created_by_user_id.eq(Some(uid.clone())),
+                        sounds::last_edited_by_user_id.eq(Some(uid)),
+                    ))
+                    .get_result::<models::Sound>(c)
+            })
         })
         .await?;
+    let sound = Sound::try_from((sound, None))?;
+
+    audit_log::log(
+        &db,
+        AuditEntry::new(
+            GuildId::new(sound.guild_id.0),
+            user.into(),
+            AuditAction::SoundCreated,
+        )
+        .target(sound.id.0)
+        .after(&sound),
+    )
+    .await;
 
-    Ok(Json(Sound::try_from((sound, None))?))
+    Ok(Json(sound))
 }
 
 #[derive(Deserialize, Debug)]
@@ -295,15 +882,37 @@ struct UpdateSoundParameter {
     category: Option<String>,
     #[serde(default, with = "::serde_with::rust::double_option")]
     volume_adjustment: Option<Option<f32>>,
+    allowed_role_ids: Option<Vec<Snowflake>>,
+    allowed_channel_ids: Option<Vec<Snowflake>>,
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    cooldown: Option<Option<f32>>,
+    tags: Option<Vec<String>>,
+    aliases: Option<Vec<String>>,
 }
 
-impl From<UpdateSoundParameter> for models::SoundChangeset {
-    fn from(s: UpdateSoundParameter) -> Self {
-        Self {
+impl TryFrom<UpdateSoundParameter> for models::SoundChangeset {
+    type Error = SoundsError;
+
+    fn try_from(s: UpdateSoundParameter) -> Result<Self, Self::Error> {
+        if let Some(name) = &s.name {
+            validate_name(name)?;
+        }
+        if let Some(Some(cooldown)) = s.cooldown {
+            if !(0.0..=MAX_COOLDOWN_SECONDS).contains(&cooldown) {
+                return Err(SoundsError::InvalidParameter(format!(
+                    "the cooldown must be between 0 and {MAX_COOLDOWN_SECONDS} seconds"
+                )));
+            }
+        }
+
+        Ok(Self {
             name: s.name,
             category: s.category,
             volume_adjustment: s.volume_adjustment,
-        }
+            allowed_role_ids: s.allowed_role_ids.map(from_snowflakes).transpose()?,
+            allowed_channel_ids: s.allowed_channel_ids.map(from_snowflakes).transpose()?,
+            cooldown: s.cooldown,
+        })
     }
 }
 
@@ -315,46 +924,189 @@ async fn update_sound(
     user: UserId,
     params: Json<UpdateSoundParameter>,
 ) -> Result<(), SoundsError> {
-    let guild_id = db
+    let before = db
         .run(move |c| {
             use crate::db::schema::sounds;
 
-            sounds::table
+            let sound = sounds::table
                 .find(sound_id)
-                .select(sounds::guild_id)
-                .first::<BigDecimal>(c)
+                .filter(sounds::deleted_at.is_null())
+                .first::<models::Sound>(c)?;
+            load_sound(c, sound)
         })
         .await?;
-    let guild_id = guild_id
-        .to_u64()
-        .ok_or_else(|| SoundsError::BigDecimalError)?;
+    let guild_id = before.guild_id.0;
 
-    check_guild_moderator(
+    check_guild_capability(
         cache_http.inner(),
         &db,
         user.clone().into(),
         GuildId::new(guild_id),
+        Capability::ManageSounds,
     )
     .await?;
 
     let uid = BigDecimal::from_u64(user.0).ok_or_else(|| SoundsError::BigDecimalError)?;
-    let params = params.into_inner();
-    db.run(move |c| {
-        use crate::db::schema::sounds;
+    let gid = BigDecimal::from_u64(guild_id).ok_or_else(|| SoundsError::BigDecimalError)?;
+    let mut params = params.into_inner();
+    let tags = params.tags.take();
+    let aliases = params.aliases.take();
+    for (kind, labels) in [("tags", &tags), ("aliases", &aliases)] {
+        if let Some(labels) = labels {
+            validate_labels(kind, labels).map_err(SoundsError::InvalidParameter)?;
+        }
+    }
+    let changeset = models::SoundChangeset::try_from(params)?;
+    let after = db
+        .run(move |c| {
+            use crate::db::schema::sounds;
 
-        diesel::update(sounds::table.filter(sounds::id.eq(sound_id)))
-            .set((
-                &models::SoundChangeset::from(params),
-                sounds::last_edited_at.eq(SystemTime::now()),
-                sounds::last_edited_by_user_id.eq(Some(uid)),
-            ))
-            .execute(c)
-    })
-    .await?;
+            c.transaction(|c| {
+                if let Some(category) = &changeset.category {
+                    ensure_category(c, &gid, category)?;
+                }
+                let sound = diesel::update(sounds::table.filter(sounds::id.eq(sound_id)))
+                    .set((
+                        &changeset,
+                        sounds::last_edited_at.eq(SystemTime::now()),
+                        sounds::last_edited_by_user_id.eq(Some(uid)),
+                    ))
+                    .get_result::<models::Sound>(c)?;
+                if let Some(tags) = tags {
+                    set_tags(c, &gid, sound_id, tags)?;
+                }
+                if let Some(aliases) = aliases {
+                    set_aliases(c, sound_id, aliases)?;
+                }
+
+                load_sound(c, sound)
+            })
+        })
+        .await?;
+
+    audit_log::log(
+        &db,
+        AuditEntry::new(
+            GuildId::new(guild_id),
+            user.into(),
+            AuditAction::SoundUpdated,
+        )
+        .target(sound_id)
+        .before(before)
+        .after(after),
+    )
+    .await;
+
+    Ok(())
+}
+
+/// Checks up front that the tags or aliases fit into their columns, so that a bad label is reported as such
+pub fn validate_labels(kind: &str, labels: &[String]) -> Result<(), String> {
+    match labels
+        .iter()
+        .find(|label| label.trim().chars().count() > MAX_LABEL_LENGTH)
+    {
+        Some(label) => Err(format!(
+            "{} must not be longer than {} characters: {}",
+            kind,
+            MAX_LABEL_LENGTH,
+            label.trim()
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Removes surrounding whitespace, empty entries and duplicates
+fn normalize_labels(labels: Vec<String>) -> Vec<String> {
+    let mut labels = labels
+        .into_iter()
+        .map(|label| label.trim().to_string())
+        .filter(|label| !label.is_empty())
+        .collect::<Vec<_>>();
+    labels.sort();
+    labels.dedup();
+    labels
+}
+
+/// Replaces the tags of the sound. Tags are stored in lowercase per guild and are removed once no sound
+/// uses them anymore.
+pub fn set_tags(
+    c: &mut PgConnection,
+    guild_id: &BigDecimal,
+    sound_id: i32,
+    tags: Vec<String>,
+) -> Result<(), DieselError> {
+    use crate::db::schema::soundtags;
+    use crate::db::schema::tags;
+
+    let tags = normalize_labels(tags.into_iter().map(|tag| tag.to_lowercase()).collect());
+
+    diesel::insert_into(tags::table)
+        .values(
+            tags.iter()
+                .map(|tag| (tags::guild_id.eq(guild_id), tags::name.eq(tag)))
+                .collect::<Vec<_>>(),
+        )
+        .on_conflict((tags::guild_id, tags::name))
+        .do_nothing()
+        .execute(c)?;
+    let tag_ids = tags::table
+        .filter(tags::guild_id.eq(guild_id))
+        .filter(tags::name.eq_any(&tags))
+        .select(tags::id)
+        .load::<i32>(c)?;
+
+    diesel::delete(soundtags::table.filter(soundtags::sound_id.eq(sound_id))).execute(c)?;
+    diesel::insert_into(soundtags::table)
+        .values(
+            tag_ids
+                .into_iter()
+                .map(|tag_id| {
+                    (
+                        soundtags::sound_id.eq(sound_id),
+                        soundtags::tag_id.eq(tag_id),
+                    )
+                })
+                .collect::<Vec<_>>(),
+        )
+        .execute(c)?;
+
+    diesel::delete(
+        tags::table
+            .filter(tags::guild_id.eq(guild_id))
+            .filter(tags::id.ne_all(soundtags::table.select(soundtags::tag_id))),
+    )
+    .execute(c)?;
+
+    Ok(())
+}
+
+pub fn set_aliases(
+    c: &mut PgConnection,
+    sound_id: i32,
+    aliases: Vec<String>,
+) -> Result<(), DieselError> {
+    use crate::db::schema::soundaliases;
+
+    diesel::delete(soundaliases::table.filter(soundaliases::sound_id.eq(sound_id))).execute(c)?;
+    diesel::insert_into(soundaliases::table)
+        .values(
+            normalize_labels(aliases)
+                .into_iter()
+                .map(|alias| {
+                    (
+                        soundaliases::sound_id.eq(sound_id),
+                        soundaliases::alias.eq(alias),
+                    )
+                })
+                .collect::<Vec<_>>(),
+        )
+        .execute(c)?;
 
     Ok(())
 }
 
+/// Moves the sound to the trash. It is purged after the retention period unless it is restored.
 #[delete("/<sound_id>")]
 async fn delete_sound(
     sound_id: i32,
@@ -362,31 +1114,48 @@ async fn delete_sound(
     db: DbConn,
     user: UserId,
 ) -> Result<(), SoundsError> {
-    let (guild_id, file_name) = fetch_guild_and_file(sound_id, &db).await?;
-    check_guild_moderator(cache_http.inner(), &db, user.into(), GuildId::new(guild_id)).await?;
-
-    if let Some(file_name) = file_name {
-        if let Err(err) = fs::remove_file(file_handling::get_full_sound_path(&file_name)).await {
-            if err.kind() != std::io::ErrorKind::NotFound {
-                return Err(SoundsError::InternalError(String::from(
-                    "Failed to delete the corresponding sound file",
-                )));
-            }
-        }
-    }
+    let (guild_id, _) = fetch_guild_and_file(sound_id, &db).await?;
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.clone().into(),
+        GuildId::new(guild_id),
+        Capability::ManageSounds,
+    )
+    .await?;
 
-    let affected_rows = db
+    let uid = BigDecimal::from_u64(user.0).ok_or_else(|| SoundsError::BigDecimalError)?;
+    let deleted_sound = db
         .run(move |c| {
-            use crate::db::schema::soundfiles;
             use crate::db::schema::sounds;
 
-            diesel::delete(soundfiles::table.filter(soundfiles::sound_id.eq(sound_id)))
-                .execute(c)?;
-            diesel::delete(sounds::table.filter(sounds::id.eq(sound_id))).execute(c)
+            diesel::update(
+                sounds::table
+                    .filter(sounds::id.eq(sound_id))
+                    .filter(sounds::deleted_at.is_null()),
+            )
+            .set((
+                sounds::deleted_at.eq(Some(SystemTime::now())),
+                sounds::deleted_by_user_id.eq(Some(uid)),
+            ))
+            .get_result::<models::Sound>(c)
+            .optional()
         })
         .await?;
 
-    if affected_rows > 0 {
+    if let Some(deleted_sound) = deleted_sound {
+        audit_log::log(
+            &db,
+            AuditEntry::new(
+                GuildId::new(guild_id),
+                user.into(),
+                AuditAction::SoundDeleted,
+            )
+            .target(sound_id)
+            .before(Sound::try_from((deleted_sound, None))?),
+        )
+        .await;
+
         Ok(())
     } else {
         Err(SoundsError::NotFound(String::from(
@@ -395,97 +1164,1214 @@ async fn delete_sound(
     }
 }
 
-#[post("/<sound_id>", format = "audio/mpeg", data = "<file>")]
-async fn upload_sound(
-    sound_id: i32,
-    file: TempFile<'_>,
+/// Lists the sounds of the guild that are in the trash, most recently deleted first
+#[get("/trash?<guild_id>")]
+async fn list_trash(
+    guild_id: u64,
     cache_http: &State<CacheHttp>,
     db: DbConn,
     user: UserId,
-) -> Result<Json<Soundfile>, SoundsError> {
-    let (guild_id, file_name) = fetch_guild_and_file(sound_id, &db).await?;
-    check_guild_moderator(
+) -> Result<Json<Vec<Sound>>, SoundsError> {
+    check_guild_capability(
         cache_http.inner(),
         &db,
-        user.clone().into(),
+        user.into(),
         GuildId::new(guild_id),
+        Capability::ManageSounds,
     )
     .await?;
 
-    let uid = BigDecimal::from_u64(user.0).ok_or_else(|| SoundsError::BigDecimalError)?;
-    let file_name = file_name.unwrap_or(format!("{}_{}.mp3", guild_id, sound_id));
-    let file_path = file_handling::get_full_sound_path(&file_name);
-
-    let save_res = save_sound_file(sound_id, uid, file_name, &file_path, file, &db).await;
-
-    if save_res.is_err() {
-        // Clean up everything
-        let delete_res = fs::remove_file(&file_path).await;
-        let db_res = db
-            .run(move |c| {
-                use crate::db::schema::soundfiles;
-                diesel::delete(soundfiles::table.filter(soundfiles::sound_id.eq(sound_id)))
-                    .execute(c)
-            })
-            .await;
+    let gid = BigDecimal::from_u64(guild_id).ok_or_else(|| SoundsError::BigDecimalError)?;
+    let sounds = db
+        .run(move |c| {
+            use crate::db::schema::soundfiles;
+            use crate::db::schema::sounds;
 
-        delete_res?;
-        db_res?;
-    }
+            sounds::table
+                .filter(sounds::guild_id.eq(gid))
+                .filter(sounds::deleted_at.is_not_null())
+                .left_join(soundfiles::table)
+                .order(sounds::deleted_at.desc())
+                .load::<(models::Sound, Option<models::Soundfile>)>(c)
+        })
+        .await?
+        .into_iter()
+        .map(Sound::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
 
-    save_res
+    Ok(Json(sounds))
 }
 
-async fn save_sound_file(
+/// Restores a sound from the trash. Fails with a conflict if another sound took its name in the meantime.
+#[post("/<sound_id>/restore")]
+async fn restore_sound(
     sound_id: i32,
-    user_id: BigDecimal,
-    file_name: String,
-    file_path: &PathBuf,
-    mut file: TempFile<'_>,
-    db: &DbConn,
-) -> Result<Json<Soundfile>, SoundsError> {
-    file.move_copy_to(file_path).await?;
+    cache_http: &State<CacheHttp>,
+    db: DbConn,
+    user: UserId,
+) -> Result<Json<Sound>, SoundsError> {
+    let (guild_id, soundfile) = db
+        .run(move |c| {
+            use crate::db::schema::soundfiles;
+            use crate::db::schema::sounds;
 
-    let volume = audio_utils::detect_volume(file_path).await;
-    let length = audio_utils::get_length(file_path).await;
-    if let (Some(volume), Some(length)) = (volume, length) {
-        let sound_info = models::Soundfile {
-            sound_id,
-            file_name,
-            max_volume: volume.max_volume,
-            mean_volume: volume.mean_volume,
-            length,
-            uploaded_by_user_id: Some(user_id),
-            uploaded_at: SystemTime::now(),
-        };
+            sounds::table
+                .find(sound_id)
+                .filter(sounds::deleted_at.is_not_null())
+                .left_join(soundfiles::table)
+                .select((sounds::guild_id, soundfiles::all_columns.nullable()))
+                .first::<(BigDecimal, Option<models::Soundfile>)>(c)
+        })
+        .await?;
+    let guild_id = guild_id
+        .to_u64()
+        .ok_or_else(|| SoundsError::BigDecimalError)?;
 
-        {
-            let sound_info = sound_info.clone();
-            db.run(move |c| {
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.clone().into(),
+        GuildId::new(guild_id),
+        Capability::ManageSounds,
+    )
+    .await?;
+    quotas::check_sound_count(&db, GuildId::new(guild_id), 1).await?;
+
+    let sound = db
+        .run(move |c| {
+            use crate::db::schema::sounds;
+
+            diesel::update(sounds::table.filter(sounds::id.eq(sound_id)))
+                .set((
+                    sounds::deleted_at.eq(None::<SystemTime>),
+                    sounds::deleted_by_user_id.eq(None::<BigDecimal>),
+                ))
+                .get_result::<models::Sound>(c)
+        })
+        .await?;
+    let sound = Sound::try_from((sound, soundfile))?;
+
+    audit_log::log(
+        &db,
+        AuditEntry::new(
+            GuildId::new(guild_id),
+            user.into(),
+            AuditAction::SoundRestored,
+        )
+        .target(sound_id)
+        .after(&sound),
+    )
+    .await;
+
+    Ok(Json(sound))
+}
+
+/// Sounds are purged from the trash after this period
+static TRASH_RETENTION: LazyLock<Duration> = LazyLock::new(|| {
+    let days = var("TRASH_RETENTION_DAYS")
+        .ok()
+        .and_then(|content| content.parse::<u64>().ok())
+        .unwrap_or(30);
+    Duration::from_secs(days * 60 * 60 * 24)
+});
+const TRASH_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Periodically removes sounds that have been in the trash for longer than the retention period
+pub async fn purge_trash_periodically(pool: ConnectionPool<DbConn, PgConnection>) {
+    let mut interval = tokio::time::interval(TRASH_PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = purge_trash(&pool).await {
+            error!(?err, "Failed to purge trash");
+        }
+    }
+}
+
+#[instrument(skip(pool), err)]
+async fn purge_trash(pool: &ConnectionPool<DbConn, PgConnection>) -> Result<(), SoundsError> {
+    let conn = pool.get().await.ok_or_else(|| {
+        SoundsError::InternalError(String::from("No database connection available"))
+    })?;
+    let deleted_before = SystemTime::now()
+        .checked_sub(*TRASH_RETENTION)
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+
+    let expired_sounds = conn
+        .run(move |c| {
+            use crate::db::schema::soundfiles;
+            use crate::db::schema::sounds;
+
+            sounds::table
+                .filter(sounds::deleted_at.lt(deleted_before))
+                .left_join(soundfiles::table)
+                .select((sounds::id, soundfiles::file_name.nullable()))
+                .load::<(i32, Option<String>)>(c)
+        })
+        .await?;
+
+    for (sound_id, file_name) in expired_sounds {
+        let unreferenced_files = conn
+            .run(move |c| {
                 use crate::db::schema::soundfiles;
+                use crate::db::schema::soundfileversions;
+                use crate::db::schema::sounds;
+
+                c.transaction(|c| {
+                    let mut file_names = soundfileversions::table
+                        .filter(soundfileversions::sound_id.eq(sound_id))
+                        .select(soundfileversions::file_name)
+                        .load::<String>(c)?;
+                    file_names.extend(file_name);
+
+                    diesel::delete(soundfiles::table.filter(soundfiles::sound_id.eq(sound_id)))
+                        .execute(c)?;
+                    diesel::delete(sounds::table.filter(sounds::id.eq(sound_id))).execute(c)?;
+
+                    // Other sounds with the same content share the file. Sounds that start using it lock
+                    // the rows referencing it, so the deletes above wait for them and they are counted.
+                    let mut unreferenced_files = vec![];
+                    for file_name in file_names {
+                        if reference_count(c, &file_name)? == 0 {
+                            unreferenced_files.push(file_name);
+                        }
+                    }
+                    Ok::<_, DieselError>(unreferenced_files)
+                })
+            })
+            .await?;
+
+        for file_name in unreferenced_files {
+            if let Err(err) = STORAGE.delete(&file_handling::sound_key(&file_name)).await {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    warn!(?err, sound_id, "Failed to delete a file of a purged sound");
+                }
+            }
+        }
+        info!(sound_id, "Purged sound from trash");
+    }
+
+    Ok(())
+}
+
+/// Computes the missing content hashes and sizes of files stored before they were introduced
+pub async fn hash_stored_files(pool: ConnectionPool<DbConn, PgConnection>) {
+    if let Err(err) = hash_files(&pool).await {
+        error!(?err, "Failed to hash stored files");
+    }
+}
+
+#[instrument(skip(pool), err)]
+async fn hash_files(pool: &ConnectionPool<DbConn, PgConnection>) -> Result<(), SoundsError> {
+    let conn = pool.get().await.ok_or_else(|| {
+        SoundsError::InternalError(String::from("No database connection available"))
+    })?;
+
+    let file_names = conn
+        .run(|c| {
+            use crate::db::schema::soundfiles;
+            use crate::db::schema::soundfileversions;
+
+            let mut file_names = soundfiles::table
+                .filter(
+                    soundfiles::content_hash
+                        .is_null()
+                        .or(soundfiles::file_size.is_null()),
+                )
+                .select(soundfiles::file_name)
+                .load::<String>(c)?;
+            file_names.extend(
+                soundfileversions::table
+                    .filter(
+                        soundfileversions::content_hash
+                            .is_null()
+                            .or(soundfileversions::file_size.is_null()),
+                    )
+                    .select(soundfileversions::file_name)
+                    .load::<String>(c)?,
+            );
+            file_names.sort();
+            file_names.dedup();
+            Ok::<_, DieselError>(file_names)
+        })
+        .await?;
+
+    for file_name in file_names {
+        let analysis = match STORAGE
+            .local_path(&file_handling::sound_key(&file_name))
+            .await
+        {
+            Ok(path) => match file_handling::hash_file(&path).await {
+                Ok(content_hash) => fs::metadata(&path)
+                    .await
+                    .map(|metadata| (content_hash, metadata.len())),
+                Err(err) => Err(err),
+            },
+            Err(err) => Err(err),
+        };
+        let (content_hash, file_size) = match analysis {
+            Ok((content_hash, file_size)) => (content_hash, i64::try_from(file_size)?),
+            Err(err) => {
+                warn!(?err, %file_name, "Failed to hash stored file");
+                continue;
+            }
+        };
+
+        conn.run(move |c| {
+            use crate::db::schema::soundfiles;
+            use crate::db::schema::soundfileversions;
+
+            diesel::update(soundfiles::table.filter(soundfiles::file_name.eq(&file_name)))
+                .set((
+                    soundfiles::content_hash.eq(&content_hash),
+                    soundfiles::file_size.eq(file_size),
+                ))
+                .execute(c)?;
+            diesel::update(
+                soundfileversions::table.filter(soundfileversions::file_name.eq(&file_name)),
+            )
+            .set((
+                soundfileversions::content_hash.eq(&content_hash),
+                soundfileversions::file_size.eq(file_size),
+            ))
+            .execute(c)
+        })
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Number of sound files and previous versions that use the file. Files with identical content are
+/// stored only once, so a file can only be removed once this drops to zero.
+fn reference_count(c: &mut PgConnection, file_name: &str) -> Result<i64, DieselError> {
+    use crate::db::schema::soundfiles;
+    use crate::db::schema::soundfileversions;
+
+    let soundfiles = soundfiles::table
+        .filter(soundfiles::file_name.eq(file_name))
+        .count()
+        .get_result::<i64>(c)?;
+    let versions = soundfileversions::table
+        .filter(soundfileversions::file_name.eq(file_name))
+        .count()
+        .get_result::<i64>(c)?;
+
+    Ok(soundfiles + versions)
+}
+
+/// A file in the sounds folder together with its analysis
+#[derive(Queryable, Debug)]
+pub struct StoredFile {
+    pub file_name: String,
+    pub max_volume: f32,
+    pub mean_volume: f32,
+    pub length: f32,
+}
+
+/// Finds a stored file with the given content, either a current file or a previous version of any sound
+pub fn find_stored_file(
+    c: &mut PgConnection,
+    content_hash: &str,
+) -> Result<Option<StoredFile>, DieselError> {
+    use crate::db::schema::soundfiles;
+    use crate::db::schema::soundfileversions;
+
+    let stored_file = soundfiles::table
+        .filter(soundfiles::content_hash.eq(content_hash))
+        .select((
+            soundfiles::file_name,
+            soundfiles::max_volume,
+            soundfiles::mean_volume,
+            soundfiles::length,
+        ))
+        .first::<StoredFile>(c)
+        .optional()?;
+    if stored_file.is_some() {
+        return Ok(stored_file);
+    }
+
+    soundfileversions::table
+        .filter(soundfileversions::content_hash.eq(content_hash))
+        .select((
+            soundfileversions::file_name,
+            soundfileversions::max_volume,
+            soundfileversions::mean_volume,
+            soundfileversions::length,
+        ))
+        .first::<StoredFile>(c)
+        .optional()
+}
 
-                diesel::insert_into(soundfiles::table)
-                    .values(&sound_info)
-                    .on_conflict(soundfiles::sound_id)
-                    .do_update()
-                    .set(&sound_info)
-                    .execute(c)
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct DuplicateSound {
+    id: Snowflake,
+    name: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct UploadResult {
+    #[serde(flatten)]
+    sound_file: Soundfile,
+    /// Other sounds of the guild with exactly the same file. Uploading them again is allowed, but
+    /// probably a mistake.
+    duplicates: Vec<DuplicateSound>,
+}
+
+#[post("/<sound_id>", format = "audio/mpeg", data = "<file>")]
+async fn upload_sound(
+    sound_id: i32,
+    file: TempFile<'_>,
+    cache_http: &State<CacheHttp>,
+    db: DbConn,
+    user: UserId,
+) -> Result<Json<UploadResult>, SoundsError> {
+    let (guild_id, file_name) = fetch_guild_and_file(sound_id, &db).await?;
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.clone().into(),
+        GuildId::new(guild_id),
+        Capability::ManageSounds,
+    )
+    .await?;
+    quotas::check_sound_storage(&db, GuildId::new(guild_id), file.len()).await?;
+    let quota = quotas::get_quota(&db, GuildId::new(guild_id)).await?;
+
+    let uid = BigDecimal::from_u64(user.0).ok_or_else(|| SoundsError::BigDecimalError)?;
+    let file_name = new_file_name(guild_id, sound_id, file_name.is_some());
+    let sound_info = save_sound_file(
+        sound_id,
+        uid,
+        file_name,
+        NewSoundfile::Upload(Box::new(file)),
+        &quota,
+        &db,
+    )
+    .await?;
+
+    let duplicates = {
+        let gid = BigDecimal::from_u64(guild_id).ok_or_else(|| SoundsError::BigDecimalError)?;
+        let content_hash = sound_info.content_hash.clone();
+        db.run(move |c| {
+            use crate::db::schema::soundfiles;
+            use crate::db::schema::sounds;
+
+            sounds::table
+                .inner_join(soundfiles::table)
+                .filter(sounds::guild_id.eq(gid))
+                .filter(sounds::deleted_at.is_null())
+                .filter(sounds::id.ne(sound_id))
+                .filter(soundfiles::content_hash.eq(content_hash))
+                .select((sounds::id, sounds::name))
+                .load::<(i32, String)>(c)
+        })
+        .await?
+        .into_iter()
+        .map(|(id, name)| {
+            Ok(DuplicateSound {
+                id: Snowflake(u64::try_from(id)?),
+                name,
             })
+        })
+        .collect::<Result<Vec<_>, SoundsError>>()?
+    };
+    if !duplicates.is_empty() {
+        info!(
+            sound_id,
+            ?duplicates,
+            "Uploaded file duplicates other sounds of the guild"
+        );
+    }
+
+    let sound_file = Soundfile {
+        max_volume: sound_info.max_volume,
+        mean_volume: sound_info.mean_volume,
+        length: sound_info.length,
+        uploaded_at: sound_info.uploaded_at,
+    };
+    audit_log::log(
+        &db,
+        AuditEntry::new(
+            GuildId::new(guild_id),
+            user.into(),
+            AuditAction::SoundUploaded,
+        )
+        .target(sound_id)
+        .after(&sound_file),
+    )
+    .await;
+
+    Ok(Json(UploadResult {
+        sound_file,
+        duplicates,
+    }))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CopySoundParameter {
+    guild_id: Snowflake,
+    name: Option<String>,
+    category: Option<String>,
+    /// Reuse the file and analysis of the original without reading the file again. Files with the same
+    /// content are shared either way.
+    #[serde(default)]
+    deduplicate: bool,
+}
+
+/// Copies the sound including its file to another guild. Role and channel restrictions are not copied as
+/// they only apply to the original guild.
+#[post("/<sound_id>/copy", format = "json", data = "<params>")]
+async fn copy_sound(
+    sound_id: i32,
+    cache_http: &State<CacheHttp>,
+    db: DbConn,
+    user: UserId,
+    params: Json<CopySoundParameter>,
+) -> Result<Json<Sound>, SoundsError> {
+    let params = params.into_inner();
+    if let Some(name) = &params.name {
+        validate_name(name)?;
+    }
+    let target_guild_id = params.guild_id.0;
+    let deduplicate = params.deduplicate;
+    let (source_guild_id, source_file_name) = fetch_guild_and_file(sound_id, &db).await?;
+    for guild_id in [source_guild_id, target_guild_id] {
+        check_guild_capability(
+            cache_http.inner(),
+            &db,
+            user.clone().into(),
+            GuildId::new(guild_id),
+            Capability::ManageSounds,
+        )
+        .await?;
+    }
+
+    let target_guild = GuildId::new(target_guild_id);
+    let quota = quotas::get_quota(&db, target_guild).await?;
+    quotas::check_sound_count(&db, target_guild, 1).await?;
+    if let Some(source_file_name) = &source_file_name {
+        let size = STORAGE
+            .size(&file_handling::sound_key(source_file_name))
             .await?;
+        quotas::check_sound_storage(&db, target_guild, size).await?;
+    }
+
+    let uid = BigDecimal::from_u64(user.0).ok_or_else(|| SoundsError::BigDecimalError)?;
+    let gid = BigDecimal::from_u64(target_guild_id).ok_or_else(|| SoundsError::BigDecimalError)?;
+    let (source_file, new_sound_id) = {
+        let uid = uid.clone();
+        db.run(move |c| {
+            use crate::db::schema::soundfiles;
+            use crate::db::schema::sounds;
+
+            c.transaction(|c| {
+                let source = sounds::table.find(sound_id).first::<models::Sound>(c)?;
+                let source_file = soundfiles::table
+                    .find(sound_id)
+                    .first::<models::Soundfile>(c)
+                    .optional()?;
+                let name = params.name.unwrap_or_else(|| source.name.clone());
+                let category = params.category.unwrap_or_else(|| source.category.clone());
+
+                ensure_category(c, &gid, &category)?;
+                let new_sound = diesel::insert_into(sounds::table)
+                    .values((
+                        sounds::guild_id.eq(&gid),
+                        sounds::name.eq(name),
+                        sounds::category.eq(category),
+                        sounds::volume_adjustment.eq(source.volume_adjustment),
+                        sounds::cooldown.eq(source.cooldown),
+                        sounds::created_by_user_id.eq(Some(uid.clone())),
+                        sounds::last_edited_by_user_id.eq(Some(uid)),
+                    ))
+                    .get_result::<models::Sound>(c)?;
+
+                let (tags, aliases) = load_tags_and_aliases(c, &[sound_id])?;
+                set_tags(
+                    c,
+                    &gid,
+                    new_sound.id,
+                    tags.into_values().flatten().collect(),
+                )?;
+                set_aliases(c, new_sound.id, aliases.into_values().flatten().collect())?;
+
+                Ok::<_, SoundsError>((source_file, new_sound.id))
+            })
+        })
+        .await?
+    };
+
+    match (source_file_name, source_file) {
+        (Some(_), Some(source_file)) if deduplicate => {
+            let soundfile = models::Soundfile {
+                sound_id: new_sound_id,
+                uploaded_by_user_id: Some(uid),
+                uploaded_at: SystemTime::now(),
+                ..source_file
+            };
+            let link_res = match quotas::check_clip_length(&quota, soundfile.length) {
+                Ok(()) => db
+                    .run(move |c| {
+                        c.transaction(|c| {
+                            if !lock_stored_file(c, &soundfile.file_name)? {
+                                return Err(DieselError::NotFound);
+                            }
+                            replace_soundfile(c, &soundfile)
+                        })
+                    })
+                    .await
+                    .map_err(SoundsError::from),
+                Err(err) => Err(err.into()),
+            };
+
+            if let Err(err) = link_res {
+                db.run(move |c| {
+                    use crate::db::schema::sounds;
+
+                    diesel::delete(sounds::table.find(new_sound_id)).execute(c)
+                })
+                .await?;
+                return Err(err);
+            }
         }
+        (Some(source_file_name), _) => {
+            let save_res = save_sound_file(
+                new_sound_id,
+                uid,
+                new_file_name(target_guild_id, new_sound_id, false),
+                NewSoundfile::Copy(file_handling::sound_key(&source_file_name)),
+                &quota,
+                &db,
+            )
+            .await;
 
-        Ok(Json(Soundfile {
-            max_volume: sound_info.max_volume,
-            mean_volume: sound_info.mean_volume,
-            length: sound_info.length,
-            uploaded_at: sound_info.uploaded_at,
+            if let Err(err) = save_res {
+                // Without its file, the copy is useless
+                db.run(move |c| {
+                    use crate::db::schema::sounds;
+
+                    diesel::delete(sounds::table.find(new_sound_id)).execute(c)
+                })
+                .await?;
+                return Err(err);
+            }
+        }
+        (None, _) => {}
+    }
+
+    let sound = db
+        .run(move |c| {
+            use crate::db::schema::soundfiles;
+            use crate::db::schema::sounds;
+
+            let (sound, soundfile) = sounds::table
+                .find(new_sound_id)
+                .left_join(soundfiles::table)
+                .first::<(models::Sound, Option<models::Soundfile>)>(c)?;
+            let (mut tags, mut aliases) = load_tags_and_aliases(c, &[new_sound_id])?;
+
+            let mut sound = Sound::try_from((sound, soundfile))?;
+            sound.tags = tags.remove(&new_sound_id).unwrap_or_default();
+            sound.aliases = aliases.remove(&new_sound_id).unwrap_or_default();
+            Ok::<_, SoundsError>(sound)
+        })
+        .await?;
+
+    audit_log::log(
+        &db,
+        AuditEntry::new(
+            GuildId::new(target_guild_id),
+            user.into(),
+            AuditAction::SoundCopied,
+        )
+        .target(new_sound_id)
+        .before(json!({
+            "guildId": Snowflake(source_guild_id),
+            "soundId": Snowflake(u64::try_from(sound_id)?),
         }))
+        .after(&sound),
+    )
+    .await;
+
+    Ok(Json(sound))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RecordingSoundParameter {
+    guild_id: Snowflake,
+    /// The recording and the part of it to use, as when mixing a recording for download
+    timestamp: u64,
+    start: f32,
+    end: f32,
+    user_ids: Vec<String>,
+    name: String,
+    category: String,
+}
+
+/// Creates a sound from a part of a recording by mixing the tracks of the selected users
+#[post("/from-recording", format = "json", data = "<params>")]
+async fn create_sound_from_recording(
+    cache_http: &State<CacheHttp>,
+    db: DbConn,
+    user: UserId,
+    params: Json<RecordingSoundParameter>,
+) -> Result<Json<Sound>, SoundsError> {
+    let mut params = params.into_inner();
+    // Checked up front, as mixing the recording takes a while
+    validate_name(&params.name)?;
+    params.category =
+        categories::normalize_name(&params.category).map_err(SoundsError::InvalidParameter)?;
+    let guild_id = GuildId::new(params.guild_id.0);
+    for capability in [Capability::DownloadRecordings, Capability::ManageSounds] {
+        check_guild_capability(
+            cache_http.inner(),
+            &db,
+            user.clone().into(),
+            guild_id,
+            capability,
+        )
+        .await?;
+    }
+
+    // Checked before the clip length, which is only meaningful for a valid range
+    let range_valid = params.start >= 0.0 && params.start < params.end;
+    if !range_valid {
+        return Err(SoundsError::InvalidParameter(String::from(
+            "End must lie after Start",
+        )));
+    }
+    let quota = quotas::get_quota(&db, guild_id).await?;
+    quotas::check_clip_length(&quota, params.end - params.start)?;
+    quotas::check_sound_count(&db, guild_id, 1).await?;
+
+    // The mix is analyzed locally before it is stored
+    let temp_path = file_handling::temp_path("mp3");
+    recorder::mix_tracks(
+        guild_id,
+        params.timestamp,
+        &params.user_ids,
+        params.start,
+        params.end,
+        &temp_path,
+    )
+    .await?;
+    let result = save_recording_sound(&params, &temp_path, &quota, &db, &user).await;
+    if let Err(err) = fs::remove_file(&temp_path).await {
+        warn!(?err, "Failed to remove temporary mix of recording");
+    }
+    let sound = result?;
+
+    audit_log::log(
+        &db,
+        AuditEntry::new(
+            guild_id,
+            user.into(),
+            AuditAction::SoundCreatedFromRecording,
+        )
+        .target(sound.id.0)
+        .after(json!({
+            "sound": &sound,
+            "source": {
+                "timestamp": params.timestamp,
+                "start": params.start,
+                "end": params.end,
+                "userIds": params.user_ids,
+            },
+        })),
+    )
+    .await;
+
+    Ok(Json(sound))
+}
+
+/// Creates the sound for a mixed recording part and stores the file. Without its file, the sound is removed
+/// again.
+async fn save_recording_sound(
+    params: &RecordingSoundParameter,
+    file_path: &Path,
+    quota: &GuildQuota,
+    db: &DbConn,
+    user: &UserId,
+) -> Result<Sound, SoundsError> {
+    let guild_id = params.guild_id.0;
+    let size = fs::metadata(file_path).await?.len();
+    quotas::check_sound_storage(db, GuildId::new(guild_id), size).await?;
+
+    let uid = BigDecimal::from_u64(user.0).ok_or_else(|| SoundsError::BigDecimalError)?;
+    let gid = BigDecimal::from_u64(guild_id).ok_or_else(|| SoundsError::BigDecimalError)?;
+    let sound_id = {
+        let uid = uid.clone();
+        let name = params.name.clone();
+        let category = params.category.clone();
+        db.run(move |c| {
+            use crate::db::schema::sounds;
+
+            c.transaction(|c| {
+                ensure_category(c, &gid, &category)?;
+                diesel::insert_into(sounds::table)
+                    .values((
+                        sounds::guild_id.eq(gid),
+                        sounds::name.eq(name),
+                        sounds::category.eq(category),
+                        sounds::created_by_user_id.eq(Some(uid.clone())),
+                        sounds::last_edited_by_user_id.eq(Some(uid)),
+                    ))
+                    .returning(sounds::id)
+                    .get_result::<i32>(c)
+            })
+        })
+        .await?
+    };
+
+    let file_name = new_file_name(guild_id, sound_id, false);
+    let store_res = store_sound_file(sound_id, uid, file_name, file_path, quota, db).await;
+    if let Err(err) = store_res {
+        db.run(move |c| {
+            use crate::db::schema::sounds;
+
+            diesel::delete(sounds::table.find(sound_id)).execute(c)
+        })
+        .await?;
+        return Err(err);
+    }
+
+    let sound = db
+        .run(move |c| {
+            use crate::db::schema::soundfiles;
+            use crate::db::schema::sounds;
+
+            sounds::table
+                .find(sound_id)
+                .left_join(soundfiles::table)
+                .first::<(models::Sound, Option<models::Soundfile>)>(c)
+        })
+        .await?;
+    Sound::try_from(sound)
+}
+
+/// Name for a new file of the sound. If the sound already has a file, it is kept as a previous version,
+/// so the new one needs a different name.
+pub fn new_file_name(guild_id: u64, sound_id: i32, has_file: bool) -> String {
+    if has_file {
+        format!(
+            "{}_{}_{}.mp3",
+            guild_id,
+            sound_id,
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|duration| duration.as_millis())
+                .unwrap_or(0)
+        )
     } else {
+        format!("{}_{}.mp3", guild_id, sound_id)
+    }
+}
+
+/// Where the new file of a sound comes from
+enum NewSoundfile<'r> {
+    Upload(Box<TempFile<'r>>),
+    /// A file in the storage that is copied, e.g. the file of another sound
+    Copy(String),
+}
+
+/// Stores the file and makes it the current one of the sound. If a file with the same content is already
+/// stored, that one is used instead. Files longer than the quota of the guild allows are rejected.
+async fn save_sound_file(
+    sound_id: i32,
+    user_id: BigDecimal,
+    file_name: String,
+    file: NewSoundfile<'_>,
+    quota: &GuildQuota,
+    db: &DbConn,
+) -> Result<models::Soundfile, SoundsError> {
+    // The file is analyzed locally before it is stored
+    let temp_path = file_handling::temp_path("mp3");
+    let result = match file {
+        NewSoundfile::Upload(mut file) => file.move_copy_to(&temp_path).await,
+        NewSoundfile::Copy(key) => match STORAGE.local_path(&key).await {
+            Ok(source) => fs::copy(source, &temp_path).await.map(|_| ()),
+            Err(err) => Err(err),
+        },
+    };
+    let result = match result {
+        Ok(()) => store_sound_file(sound_id, user_id, file_name, &temp_path, quota, db).await,
+        Err(err) => Err(err.into()),
+    };
+
+    if let Err(err) = fs::remove_file(&temp_path).await {
+        if err.kind() != std::io::ErrorKind::NotFound {
+            warn!(?err, sound_id, "Failed to remove temporary sound file");
+        }
+    }
+    result
+}
+
+/// Analyzes the file and makes it the current one of the sound. If a file with the same content is already
+/// stored, that one is used instead. Files longer than the quota of the guild allows are rejected.
+pub async fn store_sound_file(
+    sound_id: i32,
+    user_id: BigDecimal,
+    file_name: String,
+    file_path: &Path,
+    quota: &GuildQuota,
+    db: &DbConn,
+) -> Result<models::Soundfile, SoundsError> {
+    let content_hash = file_handling::hash_file(file_path).await?;
+    let file_size = i64::try_from(fs::metadata(file_path).await?.len())?;
+    let stored_file = {
+        let content_hash = content_hash.clone();
+        db.run(move |c| find_stored_file(c, &content_hash)).await?
+    };
+    let stored_file = match stored_file {
+        Some(stored_file)
+            if STORAGE
+                .exists(&file_handling::sound_key(&stored_file.file_name))
+                .await? =>
+        {
+            Some(stored_file)
+        }
+        _ => None,
+    };
+
+    if let Some(stored_file) = stored_file {
+        let sound_info = models::Soundfile {
+            sound_id,
+            file_name: stored_file.file_name,
+            max_volume: stored_file.max_volume,
+            mean_volume: stored_file.mean_volume,
+            length: stored_file.length,
+            uploaded_by_user_id: Some(user_id.clone()),
+            uploaded_at: SystemTime::now(),
+            content_hash: Some(content_hash.clone()),
+            file_size: Some(file_size),
+        };
+        quotas::check_clip_length(quota, sound_info.length)?;
+
+        let linked = {
+            let sound_info = sound_info.clone();
+            db.run(move |c| {
+                c.transaction(|c| {
+                    // A purge that removed the last reference in the meantime also removes the file
+                    if !lock_stored_file(c, &sound_info.file_name)? {
+                        return Ok(false);
+                    }
+                    replace_soundfile(c, &sound_info)?;
+                    Ok::<_, DieselError>(true)
+                })
+            })
+            .await?
+        };
+        if linked {
+            return Ok(sound_info);
+        }
+        info!(
+            sound_id,
+            "Stored file was purged while it was reused, storing it again"
+        );
+    }
+
+    let (volume, length) = match (
+        audio_utils::detect_volume(file_path).await,
+        audio_utils::get_length(file_path).await,
+    ) {
+        (Some(volume), Some(length)) => (volume, length),
         // The sound file might be invalid -> return error
-        Err(SoundsError::InvalidSoundfile(String::from(
-            "File could not be analyzed. Is it corrupted?",
-        )))
+        _ => {
+            return Err(SoundsError::InvalidSoundfile(String::from(
+                "File could not be analyzed. Is it corrupted?",
+            )))
+        }
+    };
+    let sound_info = models::Soundfile {
+        sound_id,
+        file_name: file_name.clone(),
+        max_volume: volume.max_volume,
+        mean_volume: volume.mean_volume,
+        length,
+        uploaded_by_user_id: Some(user_id),
+        uploaded_at: SystemTime::now(),
+        content_hash: Some(content_hash),
+        file_size: Some(file_size),
+    };
+    quotas::check_clip_length(quota, sound_info.length)?;
+
+    let key = file_handling::sound_key(&file_name);
+    STORAGE.put_file(&key, file_path).await?;
+    let result = {
+        let sound_info = sound_info.clone();
+        db.run(move |c| c.transaction(|c| replace_soundfile(c, &sound_info)))
+            .await
+    };
+    if let Err(err) = result {
+        // The database is only changed if saving succeeded
+        STORAGE.delete(&key).await.ok();
+        return Err(err.into());
     }
+
+    Ok(sound_info)
+}
+
+/// Locks the sound files and versions that use the file until the end of the transaction, so that a purge
+/// cannot remove the file while another sound starts using it. Returns false if no sound uses the file.
+fn lock_stored_file(c: &mut PgConnection, file_name: &str) -> Result<bool, DieselError> {
+    use crate::db::schema::soundfiles;
+    use crate::db::schema::soundfileversions;
+
+    let soundfiles = soundfiles::table
+        .filter(soundfiles::file_name.eq(file_name))
+        .select(soundfiles::sound_id)
+        .for_update()
+        .load::<i32>(c)?;
+    let versions = soundfileversions::table
+        .filter(soundfileversions::file_name.eq(file_name))
+        .select(soundfileversions::id)
+        .for_update()
+        .load::<i32>(c)?;
+
+    Ok(!soundfiles.is_empty() || !versions.is_empty())
+}
+
+/// Makes the given file the current one of its sound. The previous file is kept as a version.
+pub fn replace_soundfile(
+    c: &mut PgConnection,
+    soundfile: &models::Soundfile,
+) -> Result<(), DieselError> {
+    use crate::db::schema::soundfiles;
+    use crate::db::schema::soundfileversions;
+
+    let previous = soundfiles::table
+        .find(soundfile.sound_id)
+        .first::<models::Soundfile>(c)
+        .optional()?;
+    if let Some(previous) = previous {
+        diesel::insert_into(soundfileversions::table)
+            .values((
+                soundfileversions::sound_id.eq(previous.sound_id),
+                soundfileversions::file_name.eq(previous.file_name),
+                soundfileversions::max_volume.eq(previous.max_volume),
+                soundfileversions::mean_volume.eq(previous.mean_volume),
+                soundfileversions::length.eq(previous.length),
+                soundfileversions::uploaded_by_user_id.eq(previous.uploaded_by_user_id),
+                soundfileversions::uploaded_at.eq(previous.uploaded_at),
+                soundfileversions::content_hash.eq(previous.content_hash),
+                soundfileversions::file_size.eq(previous.file_size),
+            ))
+            .execute(c)?;
+    }
+
+    diesel::insert_into(soundfiles::table)
+        .values(soundfile)
+        .on_conflict(soundfiles::sound_id)
+        .do_update()
+        .set(soundfile)
+        .execute(c)?;
+
+    Ok(())
+}
+
+#[serde_as]
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SoundfileVersion {
+    id: Snowflake,
+    max_volume: f32,
+    mean_volume: f32,
+    length: f32,
+    #[serde_as(as = "TimestampSeconds<String>")]
+    uploaded_at: SystemTime,
+}
+
+/// Lists the previous files of the sound, newest first
+#[get("/<sound_id>/versions")]
+async fn list_versions(
+    sound_id: i32,
+    cache_http: &State<CacheHttp>,
+    db: DbConn,
+    user: UserId,
+) -> Result<Json<Vec<SoundfileVersion>>, SoundsError> {
+    let (guild_id, _) = fetch_guild_and_file(sound_id, &db).await?;
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.into(),
+        GuildId::new(guild_id),
+        Capability::ManageSounds,
+    )
+    .await?;
+
+    let versions = db
+        .run(move |c| {
+            use crate::db::schema::soundfileversions;
+
+            soundfileversions::table
+                .filter(soundfileversions::sound_id.eq(sound_id))
+                .order(soundfileversions::uploaded_at.desc())
+                .load::<models::SoundfileVersion>(c)
+        })
+        .await?
+        .into_iter()
+        .map(|version| {
+            Ok(SoundfileVersion {
+                id: Snowflake(u64::try_from(version.id)?),
+                max_volume: version.max_volume,
+                mean_volume: version.mean_volume,
+                length: version.length,
+                uploaded_at: version.uploaded_at,
+            })
+        })
+        .collect::<Result<Vec<_>, SoundsError>>()?;
+
+    Ok(Json(versions))
+}
+
+/// Makes a previous version the current file of the sound. The current file becomes a version itself.
+#[post("/<sound_id>/versions/<version_id>/revert")]
+async fn revert_sound(
+    sound_id: i32,
+    version_id: i32,
+    cache_http: &State<CacheHttp>,
+    db: DbConn,
+    user: UserId,
+) -> Result<Json<Soundfile>, SoundsError> {
+    let (guild_id, _) = fetch_guild_and_file(sound_id, &db).await?;
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.clone().into(),
+        GuildId::new(guild_id),
+        Capability::ManageSounds,
+    )
+    .await?;
+
+    let soundfile = db
+        .run(move |c| {
+            use crate::db::schema::soundfileversions;
+
+            c.transaction(|c| {
+                let version = soundfileversions::table
+                    .find(version_id)
+                    .filter(soundfileversions::sound_id.eq(sound_id))
+                    .first::<models::SoundfileVersion>(c)?;
+                diesel::delete(soundfileversions::table.find(version_id)).execute(c)?;
+
+                let soundfile = models::Soundfile {
+                    sound_id,
+                    file_name: version.file_name,
+                    max_volume: version.max_volume,
+                    mean_volume: version.mean_volume,
+                    length: version.length,
+                    uploaded_by_user_id: version.uploaded_by_user_id,
+                    uploaded_at: version.uploaded_at,
+                    content_hash: version.content_hash,
+                    file_size: version.file_size,
+                };
+                replace_soundfile(c, &soundfile)?;
+
+                Ok::<_, DieselError>(soundfile)
+            })
+        })
+        .await?;
+    let soundfile = Soundfile {
+        max_volume: soundfile.max_volume,
+        mean_volume: soundfile.mean_volume,
+        length: soundfile.length,
+        uploaded_at: soundfile.uploaded_at,
+    };
+
+    audit_log::log(
+        &db,
+        AuditEntry::new(
+            GuildId::new(guild_id),
+            user.into(),
+            AuditAction::SoundReverted,
+        )
+        .target(sound_id)
+        .after(&soundfile),
+    )
+    .await;
+
+    Ok(Json(soundfile))
+}
+
+/// Removes a previous version of the sound, e.g. to free up storage. The file is deleted once no other sound
+/// or version uses it anymore.
+#[delete("/<sound_id>/versions/<version_id>")]
+async fn delete_version(
+    sound_id: i32,
+    version_id: i32,
+    cache_http: &State<CacheHttp>,
+    db: DbConn,
+    user: UserId,
+) -> Result<(), SoundsError> {
+    let (guild_id, _) = fetch_guild_and_file(sound_id, &db).await?;
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.clone().into(),
+        GuildId::new(guild_id),
+        Capability::ManageSounds,
+    )
+    .await?;
+
+    let (version, unreferenced) = db
+        .run(move |c| {
+            use crate::db::schema::soundfileversions;
+
+            c.transaction(|c| {
+                let version = soundfileversions::table
+                    .find(version_id)
+                    .filter(soundfileversions::sound_id.eq(sound_id))
+                    .first::<models::SoundfileVersion>(c)
+                    .optional()?
+                    .ok_or_else(|| {
+                        SoundsError::NotFound(String::from(
+                            "A version with the given id does not exist",
+                        ))
+                    })?;
+                diesel::delete(soundfileversions::table.find(version_id)).execute(c)?;
+
+                // Sounds that start using the file lock the rows referencing it, as in the purge of the trash
+                let unreferenced = reference_count(c, &version.file_name)? == 0;
+                Ok::<_, SoundsError>((version, unreferenced))
+            })
+        })
+        .await?;
+
+    if unreferenced {
+        if let Err(err) = STORAGE
+            .delete(&file_handling::sound_key(&version.file_name))
+            .await
+        {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                warn!(?err, sound_id, "Failed to delete the file of a version");
+            }
+        }
+    }
+
+    audit_log::log(
+        &db,
+        AuditEntry::new(
+            GuildId::new(guild_id),
+            user.into(),
+            AuditAction::SoundVersionDeleted,
+        )
+        .target(sound_id)
+        .before(SoundfileVersion {
+            id: Snowflake(u64::try_from(version.id)?),
+            max_volume: version.max_volume,
+            mean_volume: version.mean_volume,
+            length: version.length,
+            uploaded_at: version.uploaded_at,
+        }),
+    )
+    .await;
+
+    Ok(())
 }
 
 async fn fetch_guild_and_file(
@@ -499,6 +2385,7 @@ async fn fetch_guild_and_file(
 
             sounds::table
                 .find(sound_id)
+                .filter(sounds::deleted_at.is_null())
                 .left_join(soundfiles::table)
                 .select((sounds::guild_id, soundfiles::file_name.nullable()))
                 .first::<(BigDecimal, Option<String>)>(c)
@@ -512,3 +2399,46 @@ async fn fetch_guild_and_file(
         file_name,
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_are_normalized() {
+        let labels = vec![
+            String::from(" meme "),
+            String::from(""),
+            String::from("meme"),
+            String::from("classic"),
+        ];
+
+        assert_eq!(normalize_labels(labels), vec!["classic", "meme"]);
+    }
+
+    #[test]
+    fn favorite_cursors_survive_encoding() {
+        for value in ["3:17", ":17"] {
+            let cursor = SoundCursor::parse(SoundSort::Favorites, value).unwrap();
+            assert_eq!(cursor.encode(), value);
+        }
+        assert!(SoundCursor::parse(SoundSort::Favorites, "x:17").is_none());
+    }
+
+    #[test]
+    fn names_must_not_be_empty_or_too_long() {
+        assert!(validate_name("Airhorn").is_ok());
+        assert!(validate_name(&"ä".repeat(MAX_NAME_LENGTH)).is_ok());
+        assert!(validate_name(&"ä".repeat(MAX_NAME_LENGTH + 1)).is_err());
+        assert!(validate_name("  ").is_err());
+    }
+
+    #[test]
+    fn overlong_labels_are_rejected() {
+        let fitting = format!("  {}  ", "ä".repeat(MAX_LABEL_LENGTH));
+        let overlong = "a".repeat(MAX_LABEL_LENGTH + 1);
+
+        assert!(validate_labels("tags", std::slice::from_ref(&fitting)).is_ok());
+        assert!(validate_labels("tags", &[fitting, overlong]).is_err());
+    }
+}
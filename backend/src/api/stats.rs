@@ -0,0 +1,345 @@
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use bigdecimal::BigDecimal;
+use bigdecimal::FromPrimitive;
+use bigdecimal::ToPrimitive;
+use diesel::dsl::count_star;
+use diesel::dsl::sql;
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use diesel::sql_types::Timestamp;
+use rocket::http::Status;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::Json;
+use rocket::Request;
+use rocket::Route;
+use rocket::State;
+use serde::Serialize;
+use serde_with::serde_as;
+use serde_with::TimestampSeconds;
+use serenity::model::id::GuildId;
+use serenity::model::id::UserId as SerenityUserId;
+use thiserror::Error;
+
+use crate::api::auth::UserId;
+use crate::api::Snowflake;
+use crate::db::DbConn;
+use crate::discord::management::check_guild_capability;
+use crate::discord::management::Capability;
+use crate::discord::management::PermissionError;
+use crate::CacheHttp;
+
+pub fn get_routes() -> Vec<Route> {
+    routes![get_stats]
+}
+
+/// Period covered by the statistics if the request does not specify a start
+const DEFAULT_RANGE: Duration = Duration::from_secs(60 * 60 * 24 * 30);
+/// Number of top sounds and users returned if the request does not specify a limit
+const DEFAULT_LIMIT: i64 = 10;
+const MAX_LIMIT: i64 = 100;
+
+/// Days are in UTC because timestamps are stored in UTC
+const PLAY_DAY: &str = "date_trunc('day', plays.played_at)";
+
+/// How a playback was triggered
+#[derive(Debug, Clone, Copy)]
+pub enum PlaySource {
+    /// The web interface, authenticated using a session
+    Web,
+    /// An auth token, e.g. from a script or a stream deck
+    Token,
+}
+
+impl PlaySource {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Web => "web",
+            Self::Token => "token",
+        }
+    }
+}
+
+/// Adds a successful playback to the play history. Failing to do so does not fail the playback.
+#[instrument(skip(db))]
+pub async fn record_play(
+    db: &DbConn,
+    guild_id: GuildId,
+    user_id: SerenityUserId,
+    sound_id: i32,
+    source: PlaySource,
+) {
+    let (gid, uid) = match (
+        BigDecimal::from_u64(guild_id.get()),
+        BigDecimal::from_u64(user_id.get()),
+    ) {
+        (Some(gid), Some(uid)) => (gid, uid),
+        _ => {
+            warn!("Failed to convert ids for play history");
+            return;
+        }
+    };
+
+    let result = db
+        .run(move |c| {
+            use crate::db::schema::plays;
+            use crate::db::schema::sounds;
+
+            c.transaction(|c| {
+                diesel::insert_into(plays::table)
+                    .values((
+                        plays::guild_id.eq(gid),
+                        plays::sound_id.eq(sound_id),
+                        plays::user_id.eq(uid),
+                        plays::source.eq(source.as_str()),
+                    ))
+                    .execute(c)?;
+                // Kept on the sound so that the sound list can be sorted by it
+                diesel::update(sounds::table.find(sound_id))
+                    .set(sounds::play_count.eq(sounds::play_count + 1))
+                    .execute(c)
+            })
+        })
+        .await;
+
+    if let Err(err) = result {
+        error!(?err, "Failed to record play");
+    }
+}
+
+#[derive(Debug, Error)]
+enum StatsError {
+    #[error("Number handling error")]
+    NumericalError,
+
+    #[error("Invalid parameter: {0}")]
+    InvalidParameter(String),
+
+    #[error("Database error: {0}")]
+    DieselError(#[from] DieselError),
+
+    #[error("Insufficient permission: you do not have the permission to perform this action")]
+    InsufficientPermission(#[from] PermissionError),
+}
+
+impl StatsError {
+    fn status_code(&self) -> Status {
+        match self {
+            Self::NumericalError => Status::InternalServerError,
+            Self::InvalidParameter(_) => Status::BadRequest,
+            Self::DieselError(_) => Status::InternalServerError,
+            Self::InsufficientPermission(_) => Status::Forbidden,
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for StatsError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.status_code();
+        let error_message = self.to_string();
+
+        Response::build_from(error_message.respond_to(req)?)
+            .status(status)
+            .ok()
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SoundPlays {
+    id: Snowflake,
+    name: String,
+    plays: i64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct UserPlays {
+    user_id: Snowflake,
+    plays: i64,
+}
+
+#[serde_as]
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct DayPlays {
+    #[serde_as(as = "TimestampSeconds<String>")]
+    day: SystemTime,
+    plays: i64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct UnplayedSound {
+    id: Snowflake,
+    name: String,
+    category: String,
+}
+
+#[serde_as]
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GuildStats {
+    #[serde_as(as = "TimestampSeconds<String>")]
+    from: SystemTime,
+    #[serde_as(as = "TimestampSeconds<String>")]
+    to: SystemTime,
+    total_plays: i64,
+    top_sounds: Vec<SoundPlays>,
+    top_users: Vec<UserPlays>,
+    plays_per_day: Vec<DayPlays>,
+    never_played: Vec<UnplayedSound>,
+}
+
+fn to_snowflake(id: i32) -> Result<Snowflake, StatsError> {
+    u64::try_from(id)
+        .map(Snowflake)
+        .map_err(|_| StatsError::NumericalError)
+}
+
+/// Statistics about the plays in the guild within `from` and `to` (unix timestamps in seconds). By default,
+/// the last 30 days are covered. Days are in UTC and days without any plays are omitted. Never played
+/// sounds are the sounds of this guild that have not been played anywhere in that time.
+#[get("/guilds/<guild_id>/stats?<from>&<to>&<limit>")]
+async fn get_stats(
+    guild_id: u64,
+    from: Option<u64>,
+    to: Option<u64>,
+    limit: Option<i64>,
+    user: UserId,
+    db: DbConn,
+    cache_http: &State<CacheHttp>,
+) -> Result<Json<GuildStats>, StatsError> {
+    check_guild_capability(
+        cache_http.inner(),
+        &db,
+        user.into(),
+        GuildId::new(guild_id),
+        Capability::ManageSounds,
+    )
+    .await?;
+
+    let gid = BigDecimal::from_u64(guild_id).ok_or(StatsError::NumericalError)?;
+    let to_time = |secs: u64| {
+        SystemTime::UNIX_EPOCH
+            .checked_add(Duration::from_secs(secs))
+            .ok_or_else(|| StatsError::InvalidParameter(format!("Invalid timestamp {secs}")))
+    };
+    let to = to.map(to_time).transpose()?.unwrap_or_else(SystemTime::now);
+    let from = from.map(to_time).transpose()?.unwrap_or_else(|| {
+        to.checked_sub(DEFAULT_RANGE)
+            .unwrap_or(SystemTime::UNIX_EPOCH)
+    });
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    let (top_sounds, top_users, plays_per_day, guild_sounds, played_sound_ids) = db
+        .run(move |c| {
+            use crate::db::schema::plays;
+            use crate::db::schema::sounds;
+
+            let top_sounds = plays::table
+                .inner_join(sounds::table)
+                .filter(plays::guild_id.eq(&gid))
+                .filter(plays::played_at.ge(from))
+                .filter(plays::played_at.lt(to))
+                .group_by((sounds::id, sounds::name))
+                .select((sounds::id, sounds::name, count_star()))
+                .order(count_star().desc())
+                .limit(limit)
+                .load::<(i32, String, i64)>(c)?;
+
+            let top_users = plays::table
+                .filter(plays::guild_id.eq(&gid))
+                .filter(plays::played_at.ge(from))
+                .filter(plays::played_at.lt(to))
+                .group_by(plays::user_id)
+                .select((plays::user_id, count_star()))
+                .order(count_star().desc())
+                .limit(limit)
+                .load::<(BigDecimal, i64)>(c)?;
+
+            let plays_per_day = plays::table
+                .filter(plays::guild_id.eq(&gid))
+                .filter(plays::played_at.ge(from))
+                .filter(plays::played_at.lt(to))
+                .group_by(sql::<Timestamp>(PLAY_DAY))
+                .select((sql::<Timestamp>(PLAY_DAY), count_star()))
+                .order(sql::<Timestamp>(PLAY_DAY).asc())
+                .load::<(SystemTime, i64)>(c)?;
+
+            let guild_sounds = sounds::table
+                .filter(sounds::guild_id.eq(&gid))
+                .filter(sounds::deleted_at.is_null())
+                .select((sounds::id, sounds::name, sounds::category))
+                .order((sounds::category.asc(), sounds::name.asc()))
+                .load::<(i32, String, String)>(c)?;
+
+            let played_sound_ids = plays::table
+                .filter(
+                    plays::sound_id.eq_any(
+                        sounds::table
+                            .filter(sounds::guild_id.eq(&gid))
+                            .select(sounds::id),
+                    ),
+                )
+                .filter(plays::played_at.ge(from))
+                .filter(plays::played_at.lt(to))
+                .select(plays::sound_id)
+                .distinct()
+                .load::<i32>(c)?
+                .into_iter()
+                .collect::<HashSet<_>>();
+
+            Ok::<_, DieselError>((
+                top_sounds,
+                top_users,
+                plays_per_day,
+                guild_sounds,
+                played_sound_ids,
+            ))
+        })
+        .await?;
+
+    Ok(Json(GuildStats {
+        from,
+        to,
+        total_plays: plays_per_day.iter().map(|(_, plays)| plays).sum(),
+        top_sounds: top_sounds
+            .into_iter()
+            .map(|(id, name, plays)| {
+                Ok(SoundPlays {
+                    id: to_snowflake(id)?,
+                    name,
+                    plays,
+                })
+            })
+            .collect::<Result<Vec<_>, StatsError>>()?,
+        top_users: top_users
+            .into_iter()
+            .map(|(uid, plays)| {
+                Ok(UserPlays {
+                    user_id: Snowflake(uid.to_u64().ok_or(StatsError::NumericalError)?),
+                    plays,
+                })
+            })
+            .collect::<Result<Vec<_>, StatsError>>()?,
+        plays_per_day: plays_per_day
+            .into_iter()
+            .map(|(day, plays)| DayPlays { day, plays })
+            .collect(),
+        never_played: guild_sounds
+            .into_iter()
+            .filter(|(id, _, _)| !played_sound_ids.contains(id))
+            .map(|(id, name, category)| {
+                Ok(UnplayedSound {
+                    id: to_snowflake(id)?,
+                    name,
+                    category,
+                })
+            })
+            .collect::<Result<Vec<_>, StatsError>>()?,
+    }))
+}
@@ -2,6 +2,7 @@ use diesel::PgConnection;
 use rocket::Build;
 use rocket::Rocket;
 use rocket_sync_db_pools::database;
+use rocket_sync_db_pools::ConnectionPool;
 
 pub mod models;
 pub mod schema;
@@ -9,6 +10,13 @@ pub mod schema;
 #[database("postgres_database")]
 pub struct DbConn(PgConnection);
 
+impl DbConn {
+    /// Gets a connection outside of a request, e.g. for Discord commands
+    pub async fn from_pool(pool: &ConnectionPool<DbConn, PgConnection>) -> Option<Self> {
+        pool.get().await.map(Self)
+    }
+}
+
 pub async fn run_db_migrations(rocket: Rocket<Build>) -> Rocket<Build> {
     use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 
@@ -1,15 +1,44 @@
 use crate::db::schema::*;
 use bigdecimal::BigDecimal;
+use serde_json::Value as JsonValue;
 use std::time::SystemTime;
 
 #[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
 #[diesel(table_name = guildsettings)]
 pub struct GuildSettings {
     pub id: BigDecimal,
-    pub user_role_id: Option<BigDecimal>,
-    pub moderator_role_id: Option<BigDecimal>,
     pub target_max_volume: f32,
     pub target_mean_volume: f32,
+    pub user_capabilities: Vec<String>,
+    pub moderator_capabilities: Vec<String>,
+    pub user_plays_per_minute: Option<i32>,
+    pub guild_plays_per_minute: Option<i32>,
+    pub recording_max_age_days: Option<i32>,
+    pub recording_max_count: Option<i32>,
+    pub recording_max_storage_mb: Option<i32>,
+    pub recording_opt_in_required: bool,
+    pub recording_length: Option<i32>,
+    pub recording_format: String,
+}
+
+#[derive(Queryable, Identifiable, Debug, Clone)]
+#[diesel(table_name = guildquotas)]
+#[diesel(primary_key(guild_id))]
+pub struct GuildQuota {
+    pub guild_id: BigDecimal,
+    pub max_sound_storage_mb: Option<i32>,
+    pub max_sounds: Option<i32>,
+    pub max_clip_length: Option<f32>,
+    pub max_recording_storage_mb: Option<i32>,
+}
+
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = guildroles)]
+#[diesel(primary_key(guild_id, role_id))]
+pub struct GuildRole {
+    pub guild_id: BigDecimal,
+    pub role_id: BigDecimal,
+    pub permission_level: String,
 }
 
 #[derive(Queryable, Insertable, AsChangeset, Identifiable, Debug)]
@@ -28,6 +57,38 @@ pub struct RandomInfix {
     pub display_name: String,
 }
 
+#[derive(Queryable, Identifiable, Debug, Clone)]
+#[diesel(table_name = categories)]
+pub struct Category {
+    pub id: i32,
+    pub guild_id: BigDecimal,
+    pub name: String,
+    pub display_order: i32,
+    pub color: Option<String>,
+    pub emoji: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(AsChangeset, Debug, Clone)]
+#[diesel(table_name = categories)]
+pub struct CategoryChangeset {
+    pub name: Option<String>,
+    pub display_order: Option<i32>,
+    pub color: Option<Option<String>>,
+    pub emoji: Option<Option<String>>,
+    pub description: Option<Option<String>>,
+}
+
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = categoryrestrictions)]
+#[diesel(primary_key(guild_id, category))]
+pub struct CategoryRestriction {
+    pub guild_id: BigDecimal,
+    pub category: String,
+    pub allowed_role_ids: Vec<BigDecimal>,
+    pub allowed_channel_ids: Vec<BigDecimal>,
+}
+
 #[derive(Queryable, Insertable, AsChangeset, Identifiable, Debug, Clone)]
 #[diesel(table_name = authtokens)]
 #[diesel(primary_key(user_id))]
@@ -49,6 +110,12 @@ pub struct Sound {
     pub last_edited_by_user_id: Option<BigDecimal>,
     pub last_edited_at: SystemTime,
     pub volume_adjustment: Option<f32>,
+    pub allowed_role_ids: Vec<BigDecimal>,
+    pub allowed_channel_ids: Vec<BigDecimal>,
+    pub cooldown: Option<f32>,
+    pub deleted_at: Option<SystemTime>,
+    pub deleted_by_user_id: Option<BigDecimal>,
+    pub play_count: i32,
 }
 
 #[derive(AsChangeset, Debug, Clone)]
@@ -57,6 +124,9 @@ pub struct SoundChangeset {
     pub name: Option<String>,
     pub category: Option<String>,
     pub volume_adjustment: Option<Option<f32>>,
+    pub allowed_role_ids: Option<Vec<BigDecimal>>,
+    pub allowed_channel_ids: Option<Vec<BigDecimal>>,
+    pub cooldown: Option<Option<f32>>,
 }
 
 #[derive(Queryable, Insertable, AsChangeset, Identifiable, Debug, Clone)]
@@ -70,4 +140,45 @@ pub struct Soundfile {
     pub length: f32,
     pub uploaded_by_user_id: Option<BigDecimal>,
     pub uploaded_at: SystemTime,
+    /// SHA-256 of the file, hex encoded. Files stored before hashes were introduced are hashed on startup.
+    pub content_hash: Option<String>,
+    /// In bytes. Files stored before sizes were introduced are measured on startup.
+    pub file_size: Option<i64>,
+}
+
+#[derive(Queryable, Identifiable, Debug, Clone)]
+#[diesel(table_name = soundfileversions)]
+pub struct SoundfileVersion {
+    pub id: i32,
+    pub sound_id: i32,
+    pub file_name: String,
+    pub max_volume: f32,
+    pub mean_volume: f32,
+    pub length: f32,
+    pub uploaded_by_user_id: Option<BigDecimal>,
+    pub uploaded_at: SystemTime,
+    pub content_hash: Option<String>,
+    pub file_size: Option<i64>,
+}
+
+#[derive(Queryable, Identifiable, Debug, Clone)]
+#[diesel(table_name = auditlog)]
+pub struct AuditLogEntry {
+    pub id: i32,
+    pub guild_id: BigDecimal,
+    pub user_id: Option<BigDecimal>,
+    pub action: String,
+    pub target: Option<String>,
+    pub before: Option<JsonValue>,
+    pub after: Option<JsonValue>,
+    pub created_at: SystemTime,
+}
+
+#[derive(Queryable, Insertable, Identifiable, Debug, Clone)]
+#[diesel(table_name = recordingconsents)]
+#[diesel(primary_key(user_id, guild_id))]
+pub struct RecordingConsent {
+    pub user_id: BigDecimal,
+    pub guild_id: BigDecimal,
+    pub consent: bool,
 }
@@ -1,3 +1,16 @@
+table! {
+    auditlog (id) {
+        id -> Int4,
+        guild_id -> Numeric,
+        user_id -> Nullable<Numeric>,
+        action -> Varchar,
+        target -> Nullable<Varchar>,
+        before -> Nullable<Jsonb>,
+        after -> Nullable<Jsonb>,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     authtokens (user_id) {
         user_id -> Numeric,
@@ -6,13 +19,80 @@ table! {
     }
 }
 
+table! {
+    categories (id) {
+        id -> Int4,
+        guild_id -> Numeric,
+        name -> Varchar,
+        display_order -> Int4,
+        color -> Nullable<Varchar>,
+        emoji -> Nullable<Varchar>,
+        description -> Nullable<Text>,
+    }
+}
+
+table! {
+    categoryrestrictions (guild_id, category) {
+        guild_id -> Numeric,
+        category -> Varchar,
+        allowed_role_ids -> Array<Numeric>,
+        allowed_channel_ids -> Array<Numeric>,
+    }
+}
+
 table! {
     guildsettings (id) {
         id -> Numeric,
-        user_role_id -> Nullable<Numeric>,
-        moderator_role_id -> Nullable<Numeric>,
         target_max_volume -> Float4,
         target_mean_volume -> Float4,
+        user_capabilities -> Array<Text>,
+        moderator_capabilities -> Array<Text>,
+        user_plays_per_minute -> Nullable<Int4>,
+        guild_plays_per_minute -> Nullable<Int4>,
+        recording_max_age_days -> Nullable<Int4>,
+        recording_max_count -> Nullable<Int4>,
+        recording_max_storage_mb -> Nullable<Int4>,
+        recording_opt_in_required -> Bool,
+        recording_length -> Nullable<Int4>,
+        recording_format -> Text,
+    }
+}
+
+table! {
+    favorites (user_id, sound_id) {
+        user_id -> Numeric,
+        sound_id -> Int4,
+        position -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    guildquotas (guild_id) {
+        guild_id -> Numeric,
+        max_sound_storage_mb -> Nullable<Int4>,
+        max_sounds -> Nullable<Int4>,
+        max_clip_length -> Nullable<Float4>,
+        max_recording_storage_mb -> Nullable<Int4>,
+    }
+}
+
+table! {
+    guildroles (guild_id, role_id) {
+        guild_id -> Numeric,
+        role_id -> Numeric,
+        permission_level -> Varchar,
+    }
+}
+
+table! {
+    plays (id) {
+        id -> Int4,
+        guild_id -> Numeric,
+        sound_id -> Int4,
+        user_id -> Numeric,
+        source -> Varchar,
+        played_at -> Timestamp,
     }
 }
 
@@ -24,6 +104,27 @@ table! {
     }
 }
 
+table! {
+    recordingconsents (user_id, guild_id) {
+        user_id -> Numeric,
+        guild_id -> Numeric,
+        consent -> Bool,
+    }
+}
+
+table! {
+    recordingoptouts (user_id) {
+        user_id -> Numeric,
+    }
+}
+
+table! {
+    soundaliases (sound_id, alias) {
+        sound_id -> Int4,
+        alias -> Varchar,
+    }
+}
+
 table! {
     soundfiles (sound_id) {
         sound_id -> Int4,
@@ -33,6 +134,23 @@ table! {
         length -> Float4,
         uploaded_by_user_id -> Nullable<Numeric>,
         uploaded_at -> Timestamp,
+        content_hash -> Nullable<Varchar>,
+        file_size -> Nullable<Int8>,
+    }
+}
+
+table! {
+    soundfileversions (id) {
+        id -> Int4,
+        sound_id -> Int4,
+        file_name -> Varchar,
+        max_volume -> Float4,
+        mean_volume -> Float4,
+        length -> Float4,
+        uploaded_by_user_id -> Nullable<Numeric>,
+        uploaded_at -> Timestamp,
+        content_hash -> Nullable<Varchar>,
+        file_size -> Nullable<Int8>,
     }
 }
 
@@ -47,6 +165,27 @@ table! {
         last_edited_by_user_id -> Nullable<Numeric>,
         last_edited_at -> Timestamp,
         volume_adjustment -> Nullable<Float4>,
+        allowed_role_ids -> Array<Numeric>,
+        allowed_channel_ids -> Array<Numeric>,
+        cooldown -> Nullable<Float4>,
+        deleted_at -> Nullable<Timestamp>,
+        deleted_by_user_id -> Nullable<Numeric>,
+        play_count -> Int4,
+    }
+}
+
+table! {
+    soundtags (sound_id, tag_id) {
+        sound_id -> Int4,
+        tag_id -> Int4,
+    }
+}
+
+table! {
+    tags (id) {
+        id -> Int4,
+        guild_id -> Numeric,
+        name -> Varchar,
     }
 }
 
@@ -58,14 +197,35 @@ table! {
 }
 
 joinable!(authtokens -> users (user_id));
+joinable!(favorites -> sounds (sound_id));
+joinable!(favorites -> users (user_id));
+joinable!(plays -> sounds (sound_id));
+joinable!(soundaliases -> sounds (sound_id));
 joinable!(soundfiles -> sounds (sound_id));
 joinable!(soundfiles -> users (uploaded_by_user_id));
+joinable!(soundfileversions -> sounds (sound_id));
+joinable!(soundfileversions -> users (uploaded_by_user_id));
+joinable!(soundtags -> sounds (sound_id));
+joinable!(soundtags -> tags (tag_id));
 
 allow_tables_to_appear_in_same_query!(
+    auditlog,
     authtokens,
+    categories,
+    categoryrestrictions,
+    favorites,
+    guildquotas,
+    guildroles,
     guildsettings,
+    plays,
     randominfixes,
+    recordingconsents,
+    recordingoptouts,
+    soundaliases,
     soundfiles,
+    soundfileversions,
     sounds,
+    soundtags,
+    tags,
     users,
 );
@@ -1,5 +1,10 @@
+use crate::db::DbConn;
 use crate::discord::recorder::Recorder;
 use crate::discord::CacheHttp;
+use crate::storage::LocalFile;
+use diesel::PgConnection;
+use rocket_sync_db_pools::ConnectionPool;
+use serenity::async_trait;
 use serenity::client::ClientBuilder;
 use serenity::client::Context;
 use serenity::model::id::ChannelId;
@@ -10,10 +15,15 @@ use songbird::driver::DecodeMode;
 use songbird::error::JoinError;
 use songbird::input::File;
 use songbird::Config as DriverConfig;
+use songbird::Event;
+use songbird::EventContext;
+use songbird::EventHandler as VoiceEventHandler;
 use songbird::SerenityInit;
 use songbird::Songbird;
-use std::path::Path;
+use songbird::TrackEvent;
 use std::sync::Arc;
+use std::sync::Mutex as StdMutex;
+use std::sync::OnceLock;
 use thiserror::Error;
 use tokio::sync::Mutex;
 
@@ -24,15 +34,23 @@ pub enum ClientError {
     #[error("User not found in a voice channel")]
     UserNotFound,
     #[error("Connection error: {0}")]
-    ConnectionError(#[from] JoinError),
+    ConnectionError(Box<JoinError>),
     #[error("Guild not found")]
     GuildNotFound,
 }
 
+impl From<JoinError> for ClientError {
+    fn from(err: JoinError) -> Self {
+        Self::ConnectionError(Box::new(err))
+    }
+}
+
 #[derive(Clone)]
 pub struct Client {
     songbird: Arc<Songbird>,
     pub recorder: Arc<Recorder>,
+    /// Set once the API has started
+    db_pool: Arc<OnceLock<ConnectionPool<DbConn, PgConnection>>>,
 }
 
 impl Default for Client {
@@ -50,25 +68,45 @@ impl Client {
         Self {
             songbird,
             recorder: Recorder::create(),
+            db_pool: Default::default(),
         }
     }
 
-    #[instrument(skip(self))]
+    pub fn set_db_pool(&self, pool: ConnectionPool<DbConn, PgConnection>) {
+        let _ = self.db_pool.set(pool);
+    }
+
+    /// Gets a database connection for Discord commands. Not available before the API has started.
+    pub async fn db(&self) -> Option<DbConn> {
+        DbConn::from_pool(self.db_pool.get()?).await
+    }
+
+    #[instrument(skip(self, cache_and_http))]
     pub async fn join_channel(
         &self,
         guild_id: GuildId,
         channel_id: ChannelId,
+        cache_and_http: &CacheHttp,
     ) -> Result<Arc<Mutex<songbird::Call>>, ClientError> {
+        let already_joined = self.current_channel(guild_id).await == Some(channel_id);
         let call_lock = self
             .songbird
             .join(guild_id, channel_id)
             .await
-            .map_err(ClientError::ConnectionError)?;
+            .map_err(ClientError::from)?;
 
         self.recorder
             .register_with_call(guild_id, call_lock.clone())
             .await;
 
+        // Everyone in the channel should know that they may be recorded
+        if !already_joined {
+            let announcement = self.recorder.announcement(guild_id).await;
+            if let Err(err) = channel_id.say(cache_and_http, announcement).await {
+                warn!(?err, "Failed to announce recording in the voice channel");
+            }
+        }
+
         Ok(call_lock)
     }
 
@@ -79,25 +117,42 @@ impl Client {
         user_id: UserId,
         cache_and_http: &CacheHttp,
     ) -> Result<(ChannelId, Arc<Mutex<songbird::Call>>), ClientError> {
-        let channel_id = {
-            let guild = guild_id
-                .to_guild_cached(cache_and_http)
-                .ok_or(ClientError::GuildNotFound)?;
-
-            guild
-                .voice_states
-                .get(&user_id)
-                .and_then(|voice_state| voice_state.channel_id)
-                .ok_or(ClientError::UserNotFound)?
-        };
+        let channel_id = self.user_channel(guild_id, user_id, cache_and_http)?;
 
         debug!(?channel_id, "Joining user in channel");
 
-        self.join_channel(guild_id, channel_id)
+        self.join_channel(guild_id, channel_id, cache_and_http)
             .await
             .map(|call| (channel_id, call))
     }
 
+    /// The voice channel the user is currently in
+    pub fn user_channel(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        cache_and_http: &CacheHttp,
+    ) -> Result<ChannelId, ClientError> {
+        let guild = guild_id
+            .to_guild_cached(cache_and_http)
+            .ok_or(ClientError::GuildNotFound)?;
+
+        guild
+            .voice_states
+            .get(&user_id)
+            .and_then(|voice_state| voice_state.channel_id)
+            .ok_or(ClientError::UserNotFound)
+    }
+
+    /// The voice channel the bot is currently connected to
+    pub async fn current_channel(&self, guild_id: GuildId) -> Option<ChannelId> {
+        let call_lock = self.songbird.get(guild_id)?;
+        let call = call_lock.lock().await;
+
+        call.current_channel()
+            .map(|channel_id| ChannelId::new(channel_id.0.get()))
+    }
+
     #[instrument(skip(self))]
     pub async fn leave(&self, guild_id: GuildId) -> Result<(), ClientError> {
         self.recorder.unregister_guild(guild_id).await;
@@ -107,14 +162,16 @@ impl Client {
             .await
             .map_err(|err| match err {
                 JoinError::NoCall => ClientError::NotInAChannel,
-                other => ClientError::ConnectionError(other),
+                other => ClientError::from(other),
             })
     }
 
-    #[instrument(skip(self, sound_path))]
+    /// Plays the sound. The file is kept until the track is over, so that it is not evicted from the cache
+    /// before songbird and the recorder have opened it.
+    #[instrument(skip(self, sound_file))]
     pub async fn play(
         &self,
-        sound_path: impl AsRef<Path>,
+        sound_file: LocalFile,
         volume_adjustment: f32,
         guild_id: GuildId,
     ) -> Result<(), ClientError> {
@@ -128,7 +185,7 @@ impl Client {
         call.stop();
 
         // Play the source
-        let source = File::new(sound_path.as_ref().to_path_buf());
+        let source = File::new(sound_file.to_path_buf());
         let handle = call.play(source.into());
 
         // Convert dB to linear scale for volume adjustment
@@ -137,6 +194,20 @@ impl Client {
         if let Err(e) = handle.set_volume(linear_volume) {
             warn!("Failed to set volume to {}: {:?}", linear_volume, e);
         }
+        drop(call);
+
+        // Songbird does not expose the audio it sends, so the recorder decodes the sound itself
+        self.recorder
+            .start_playback(guild_id, &sound_file, volume_adjustment)
+            .await;
+
+        // If the track is over already, the file is dropped right away
+        let keep_file = KeepFile(Arc::new(StdMutex::new(Some(sound_file))));
+        for event in [TrackEvent::End, TrackEvent::Error] {
+            if let Err(err) = handle.add_event(Event::Track(event), keep_file.clone()) {
+                debug!(?err, "Track is over already");
+            }
+        }
 
         Ok(())
     }
@@ -150,6 +221,9 @@ impl Client {
 
         let mut handler = handler_lock.lock().await;
         handler.stop();
+        drop(handler);
+
+        self.recorder.stop_playback(guild_id).await;
 
         Ok(())
     }
@@ -182,3 +256,15 @@ pub async fn get(ctx: &Context) -> Option<Client> {
 
     data.get::<ClientKey>().cloned()
 }
+
+/// Holds the file of a track until the track ends
+#[derive(Clone)]
+struct KeepFile(Arc<StdMutex<Option<LocalFile>>>);
+
+#[async_trait]
+impl VoiceEventHandler for KeepFile {
+    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
+        self.0.lock().unwrap().take();
+        Some(Event::Cancel)
+    }
+}
@@ -1,6 +1,12 @@
 #![allow(deprecated)] // StandardFramework is deprecated but we continue using it for compatibility
 
+use crate::api::audit_log;
+use crate::api::audit_log::AuditAction;
+use crate::api::audit_log::AuditEntry;
+use crate::api::quotas;
+use crate::api::quotas::QuotaError;
 use crate::discord::client;
+use crate::discord::client::Client;
 use crate::discord::recorder::RecordingError;
 use crate::BASE_URL;
 use crate::BUILD_ID;
@@ -9,6 +15,7 @@ use crate::VERSION;
 use serenity::client::Context;
 use serenity::framework::standard::macros::command;
 use serenity::framework::standard::macros::group;
+use serenity::framework::standard::Args;
 use serenity::framework::standard::CommandResult;
 use serenity::framework::standard::Configuration;
 use serenity::framework::StandardFramework;
@@ -16,6 +23,7 @@ use serenity::model::channel::Message;
 use serenity::model::prelude::ReactionType;
 use serenity::model::prelude::UserId;
 use serenity::prelude::*;
+use serenity::utils::parse_user_mention;
 use serenity::Result as SerenityResult;
 use std::convert::TryFrom;
 use std::fmt::Write;
@@ -43,14 +51,23 @@ async fn join(ctx: &Context, msg: &Message) -> CommandResult {
         .expect("Discord client placed in at initialization");
 
     match client.join_user(guild_id, msg.author.id, &ctx.into()).await {
-        Ok((channel_id, _)) => check_msg(
-            msg.channel_id
-                .say(
-                    &ctx.http,
-                    &format!(":white_check_mark: Joined {}", channel_id.mention()),
-                )
-                .await,
-        ),
+        Ok((channel_id, _)) => {
+            check_msg(
+                msg.channel_id
+                    .say(
+                        &ctx.http,
+                        &format!(":white_check_mark: Joined {}", channel_id.mention()),
+                    )
+                    .await,
+            );
+            let channel_name = channel_id.name(ctx).await.unwrap_or_default();
+            audit(
+                &client,
+                AuditEntry::new(guild_id, msg.author.id, AuditAction::ChannelJoined)
+                    .target(channel_name),
+            )
+            .await;
+        }
         Err(client::ClientError::UserNotFound) => {
             check_msg(msg.reply(&ctx, ":x: Not in a voice channel").await)
         }
@@ -72,7 +89,14 @@ async fn leave(ctx: &Context, msg: &Message) -> CommandResult {
         .expect("Discord client placed in at initialization");
 
     match client.leave(guild_id).await {
-        Ok(_) => check_msg(msg.channel_id.say(&ctx.http, "Left voice channel").await),
+        Ok(_) => {
+            check_msg(msg.channel_id.say(&ctx.http, "Left voice channel").await);
+            audit(
+                &client,
+                AuditEntry::new(guild_id, msg.author.id, AuditAction::ChannelLeft),
+            )
+            .await;
+        }
         Err(client::ClientError::NotInAChannel) => {
             check_msg(msg.reply(&ctx, ":x: Not in a voice channel").await)
         }
@@ -100,7 +124,14 @@ async fn stop(ctx: &Context, msg: &Message) -> CommandResult {
         .expect("Discord client placed in at initialisation.");
 
     match client.stop(guild_id).await {
-        Ok(_) => check_msg(msg.channel_id.say(&ctx.http, ":stop_button: Stopped").await),
+        Ok(_) => {
+            check_msg(msg.channel_id.say(&ctx.http, ":stop_button: Stopped").await);
+            audit(
+                &client,
+                AuditEntry::new(guild_id, msg.author.id, AuditAction::PlaybackStopped),
+            )
+            .await;
+        }
         Err(client::ClientError::NotInAChannel) => check_msg(
             msg.channel_id
                 .say(&ctx.http, ":x: Not in a voice channel to play in")
@@ -114,7 +145,27 @@ async fn stop(ctx: &Context, msg: &Message) -> CommandResult {
 
 #[command]
 #[only_in(guilds)]
-async fn record(ctx: &Context, msg: &Message) -> CommandResult {
+async fn record(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    // `~record 15` only saves the last 15 seconds. The bot may be mentioned after the command.
+    let window = match args.current() {
+        None => None,
+        Some(arg) if parse_user_mention(arg).is_some() => None,
+        Some(_) => match args.single::<u64>() {
+            Ok(window) if window > 0 => Some(window),
+            _ => {
+                check_msg(
+                    msg.channel_id
+                        .say(
+                            &ctx.http,
+                            ":x: Usage: `~record` or `~record <seconds>` with at least one second",
+                        )
+                        .await,
+                );
+                return Ok(());
+            }
+        },
+    };
+
     let reaction = msg
         .react(&ctx.http, ReactionType::try_from("⏬").unwrap())
         .await;
@@ -126,22 +177,54 @@ async fn record(ctx: &Context, msg: &Message) -> CommandResult {
         .await
         .expect("Recorder placed in at initialization");
 
-    match client.recorder.save_recording(guild_id, &ctx.into()).await {
-        Ok(_) => check_msg(
-            msg.channel_id
-                .say(&ctx.http, ":white_check_mark: Recording saved")
-                .await,
-        ),
-        Err(err) => {
-            error!(?err, "Failed to record");
-            match err {
-                RecordingError::IoError(_) => check_msg(
-                    msg.channel_id
-                        .say(&ctx.http, ":x: Failed to save recording")
-                        .await,
-                ),
-                RecordingError::NoData => {
-                    check_msg(msg.channel_id.say(&ctx.http, ":x: No data to record").await)
+    // The buffer only holds the last seconds, so the recording is saved if the quota cannot be checked
+    let quota_res = match client.db().await {
+        Some(db) => match quotas::check_recording_storage(&db, guild_id).await {
+            Err(err @ QuotaError::Exceeded(_)) => Err(err),
+            Err(err) => {
+                warn!(?err, "Failed to check the recording quota, saving anyway");
+                Ok(())
+            }
+            Ok(()) => Ok(()),
+        },
+        None => {
+            warn!("No database connection available to check the recording quota, saving anyway");
+            Ok(())
+        }
+    };
+
+    match quota_res {
+        Err(err) => check_msg(msg.channel_id.say(&ctx.http, format!(":x: {err}")).await),
+        Ok(()) => {
+            match client
+                .recorder
+                .save_recording(guild_id, &ctx.into(), Some(msg.author.id), window)
+                .await
+            {
+                Ok(_) => {
+                    check_msg(
+                        msg.channel_id
+                            .say(&ctx.http, ":white_check_mark: Recording saved")
+                            .await,
+                    );
+                    audit(
+                        &client,
+                        AuditEntry::new(guild_id, msg.author.id, AuditAction::RecordingSaved),
+                    )
+                    .await;
+                }
+                Err(err) => {
+                    error!(?err, "Failed to record");
+                    match err {
+                        RecordingError::IoError(_) => check_msg(
+                            msg.channel_id
+                                .say(&ctx.http, ":x: Failed to save recording")
+                                .await,
+                        ),
+                        RecordingError::NoData => {
+                            check_msg(msg.channel_id.say(&ctx.http, ":x: No data to record").await)
+                        }
+                    }
                 }
             }
         }
@@ -187,6 +270,14 @@ async fn info(ctx: &Context, msg: &Message) -> CommandResult {
     Ok(())
 }
 
+/// Commands are audited like their counterparts in the API
+async fn audit(client: &Client, entry: AuditEntry) {
+    match client.db().await {
+        Some(db) => audit_log::log(&db, entry).await,
+        None => warn!(?entry, "No database connection available for audit log"),
+    }
+}
+
 /// Checks that a message successfully sent; if not, then logs why to stdout.
 #[instrument]
 fn check_msg(result: SerenityResult<Message>) {
@@ -1,3 +1,4 @@
+use crate::db::models;
 use crate::db::DbConn;
 use crate::CacheHttp;
 use bigdecimal::BigDecimal;
@@ -5,8 +6,11 @@ use bigdecimal::FromPrimitive;
 use bigdecimal::ToPrimitive;
 use diesel::prelude::*;
 use diesel::result::Error as DieselError;
+use serde::Deserialize;
+use serde::Serialize;
 use serenity::model::guild::Guild;
 use serenity::model::guild::Member;
+use serenity::model::id::ChannelId;
 use serenity::model::id::GuildId;
 use serenity::model::id::UserId;
 use thiserror::Error;
@@ -18,12 +22,161 @@ pub enum UserPermission {
     User,
 }
 
+impl UserPermission {
+    /// Name of the level as stored in the database. Admins are determined via Discord and not stored.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Admin => "admin",
+            Self::Moderator => "moderator",
+            Self::User => "user",
+        }
+    }
+}
+
+/// Actions that can be granted to the users and moderators of a guild. Admins always have all capabilities.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Capability {
+    Play,
+    Record,
+    DownloadRecordings,
+    ManageSounds,
+    ManageSettings,
+    ViewAuditLog,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 6] = [
+        Capability::Play,
+        Capability::Record,
+        Capability::DownloadRecordings,
+        Capability::ManageSounds,
+        Capability::ManageSettings,
+        Capability::ViewAuditLog,
+    ];
+
+    /// Name of the capability as stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Play => "play",
+            Self::Record => "record",
+            Self::DownloadRecordings => "download_recordings",
+            Self::ManageSounds => "manage_sounds",
+            Self::ManageSettings => "manage_settings",
+            Self::ViewAuditLog => "view_audit_log",
+        }
+    }
+
+    pub fn from_db_str(value: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|capability| capability.as_str() == value)
+            .copied()
+    }
+}
+
+/// Used when there are no settings stored for a guild. Must match the database defaults.
+pub const DEFAULT_USER_CAPABILITIES: [Capability; 3] = [
+    Capability::Play,
+    Capability::Record,
+    Capability::DownloadRecordings,
+];
+pub const DEFAULT_MODERATOR_CAPABILITIES: [Capability; 5] = [
+    Capability::Play,
+    Capability::Record,
+    Capability::DownloadRecordings,
+    Capability::ManageSounds,
+    Capability::ViewAuditLog,
+];
+
 #[derive(Debug)]
 pub struct PermissionResponse {
     pub permission: UserPermission,
+    pub capabilities: Vec<Capability>,
     pub member: Member,
 }
 
+impl PermissionResponse {
+    pub fn has_capability(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+}
+
+/// Limits who may play a sound and in which voice channels. Empty lists impose no restriction.
+#[derive(Debug, Clone, Default)]
+pub struct PlayRestriction {
+    pub role_ids: Vec<u64>,
+    pub channel_ids: Vec<u64>,
+}
+
+impl PlayRestriction {
+    pub fn from_db(
+        role_ids: &[BigDecimal],
+        channel_ids: &[BigDecimal],
+    ) -> Result<Self, PermissionError> {
+        let to_u64 = |ids: &[BigDecimal]| {
+            ids.iter()
+                .map(|id| id.to_u64().ok_or(PermissionError::BigDecimalError))
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(Self {
+            role_ids: to_u64(role_ids)?,
+            channel_ids: to_u64(channel_ids)?,
+        })
+    }
+
+    /// Admins are never restricted by roles
+    pub fn allows_member(&self, permission: &PermissionResponse) -> bool {
+        permission.permission == UserPermission::Admin
+            || self.role_ids.is_empty()
+            || permission
+                .member
+                .roles
+                .iter()
+                .any(|role| self.role_ids.contains(&role.get()))
+    }
+
+    pub fn allows_channel(&self, channel_id: Option<ChannelId>) -> bool {
+        self.channel_ids.is_empty()
+            || channel_id
+                .map(|channel_id| self.channel_ids.contains(&channel_id.get()))
+                .unwrap_or(false)
+    }
+}
+
+/// Fetches the restrictions that apply to a sound, i.e. its own and the ones of its category
+async fn get_play_restrictions(
+    db: &DbConn,
+    sound: &models::Sound,
+) -> Result<Vec<PlayRestriction>, PermissionError> {
+    let gid = sound.guild_id.clone();
+    let category = sound.category.clone();
+    let category_restriction = db
+        .run(move |c| {
+            use crate::db::schema::categoryrestrictions;
+
+            categoryrestrictions::table
+                .find((gid, category))
+                .first::<models::CategoryRestriction>(c)
+                .optional()
+        })
+        .await?;
+
+    let mut restrictions = vec![PlayRestriction::from_db(
+        &sound.allowed_role_ids,
+        &sound.allowed_channel_ids,
+    )?];
+    if let Some(category_restriction) = category_restriction {
+        restrictions.push(PlayRestriction::from_db(
+            &category_restriction.allowed_role_ids,
+            &category_restriction.allowed_channel_ids,
+        )?);
+    }
+
+    Ok(restrictions)
+}
+
 #[derive(Debug, Error)]
 pub enum PermissionError {
     #[error("Insufficient permission")]
@@ -32,60 +185,55 @@ pub enum PermissionError {
     DieselError(#[from] DieselError),
     #[error("BigDecimal conversion error")]
     BigDecimalError,
+    #[error("You do not have a role that may play this sound")]
+    RoleRestricted,
+    #[error("The sound may not be played in this voice channel")]
+    ChannelRestricted,
 }
 
-pub async fn check_guild_user(
-    cache_http: &CacheHttp,
+/// Checks the restrictions of the sound against the member and the voice channel the sound would be played in
+pub async fn check_play_restrictions(
     db: &DbConn,
-    user_id: UserId,
-    guild_id: GuildId,
-) -> Result<PermissionResponse, PermissionError> {
-    get_permission_level(cache_http, db, user_id, guild_id).await
-}
-
-pub async fn check_guild_moderator(
-    cache_http: &CacheHttp,
-    db: &DbConn,
-    user_id: UserId,
-    guild_id: GuildId,
-) -> Result<PermissionResponse, PermissionError> {
-    let response = get_permission_level(cache_http, db, user_id, guild_id).await?;
+    sound: &models::Sound,
+    permission: &PermissionResponse,
+    channel_id: Option<ChannelId>,
+) -> Result<(), PermissionError> {
+    let restrictions = get_play_restrictions(db, sound).await?;
 
-    if response.permission == UserPermission::Admin
-        || response.permission == UserPermission::Moderator
+    if !restrictions
+        .iter()
+        .all(|restriction| restriction.allows_member(permission))
     {
-        Ok(response)
+        Err(PermissionError::RoleRestricted)
+    } else if !restrictions
+        .iter()
+        .all(|restriction| restriction.allows_channel(channel_id))
+    {
+        Err(PermissionError::ChannelRestricted)
     } else {
-        Err(PermissionError::InsufficientPermission)
+        Ok(())
     }
 }
 
-pub async fn check_guild_admin(
+/// Checks that the user is a member of the guild and has been granted the given capability there
+pub async fn check_guild_capability(
     cache_http: &CacheHttp,
+    db: &DbConn,
     user_id: UserId,
     guild_id: GuildId,
-) -> Result<(), PermissionError> {
-    let member = guild_id
-        .member(cache_http, user_id)
-        .await
-        .map_err(|_| PermissionError::InsufficientPermission)?;
-
-    let is_admin = {
-        let guild = guild_id
-            .to_guild_cached(cache_http)
-            .ok_or(PermissionError::InsufficientPermission)?;
-
-        let perms = guild.member_permissions(&member);
-        perms.administrator()
-    };
+    capability: Capability,
+) -> Result<PermissionResponse, PermissionError> {
+    let response = get_permission_level(cache_http, db, user_id, guild_id).await?;
 
-    if is_admin {
-        Ok(())
+    if response.has_capability(capability) {
+        Ok(response)
     } else {
         Err(PermissionError::InsufficientPermission)
     }
 }
 
+/// Determines the permission level and capabilities of the user. Fails if the user is not a member of the
+/// guild or does not have any of the configured roles.
 pub async fn get_permission_level(
     cache_http: &CacheHttp,
     db: &DbConn,
@@ -110,49 +258,68 @@ pub async fn get_permission_level(
         return Ok(PermissionResponse {
             member,
             permission: UserPermission::Admin,
+            capabilities: Capability::ALL.to_vec(),
         });
     }
 
     let gid = BigDecimal::from_u64(guild_id.get()).ok_or(PermissionError::BigDecimalError)?;
-    let (user_role_id, moderator_role_id) = db
+    let (guild_settings, guild_roles) = db
         .run(move |c| {
-            use crate::db::schema::guildsettings::dsl::*;
-            guildsettings
-                .find(gid)
-                .select((user_role_id, moderator_role_id))
-                .first::<(Option<BigDecimal>, Option<BigDecimal>)>(c)
-                .optional()
+            use crate::db::schema::guildroles;
+            use crate::db::schema::guildsettings;
+
+            let guild_settings = guildsettings::table
+                .find(gid.clone())
+                .first::<models::GuildSettings>(c)
+                .optional()?;
+            let guild_roles = guildroles::table
+                .filter(guildroles::guild_id.eq(gid))
+                .load::<models::GuildRole>(c)?;
+
+            Ok::<_, DieselError>((guild_settings, guild_roles))
         })
-        .await?
-        .unwrap_or((None, None));
+        .await?;
 
-    if let Some(moderator_role_id) = moderator_role_id {
-        let rid = moderator_role_id
+    let mut permission = None;
+    for guild_role in guild_roles.iter() {
+        let rid = guild_role
+            .role_id
             .to_u64()
             .ok_or(PermissionError::BigDecimalError)?;
 
         if member.roles.iter().any(|role| role.get() == rid) {
-            return Ok(PermissionResponse {
-                member,
-                permission: UserPermission::Moderator,
-            });
+            if guild_role.permission_level == UserPermission::Moderator.as_str() {
+                permission = Some(UserPermission::Moderator);
+                break;
+            } else if guild_role.permission_level == UserPermission::User.as_str() {
+                permission = Some(UserPermission::User);
+            }
         }
     }
+    let permission = permission.ok_or(PermissionError::InsufficientPermission)?;
 
-    if let Some(user_role_id) = user_role_id {
-        let rid = user_role_id
-            .to_u64()
-            .ok_or(PermissionError::BigDecimalError)?;
-
-        if member.roles.iter().any(|role| role.get() == rid) {
-            return Ok(PermissionResponse {
-                member,
-                permission: UserPermission::User,
-            });
+    let capabilities = match (guild_settings, permission) {
+        (Some(settings), UserPermission::Moderator) => {
+            parse_capabilities(&settings.moderator_capabilities)
         }
-    }
+        (Some(settings), _) => parse_capabilities(&settings.user_capabilities),
+        (None, UserPermission::Moderator) => DEFAULT_MODERATOR_CAPABILITIES.to_vec(),
+        (None, _) => DEFAULT_USER_CAPABILITIES.to_vec(),
+    };
+
+    Ok(PermissionResponse {
+        member,
+        permission,
+        capabilities,
+    })
+}
 
-    Err(PermissionError::InsufficientPermission)
+/// Unknown capabilities are ignored
+pub fn parse_capabilities(values: &[String]) -> Vec<Capability> {
+    values
+        .iter()
+        .filter_map(|value| Capability::from_db_str(value))
+        .collect()
 }
 
 #[instrument(skip(cache_http, db), err)]
@@ -160,12 +327,12 @@ pub async fn get_guilds_for_user(
     cache_http: &CacheHttp,
     db: &DbConn,
     user_id: UserId,
-) -> Result<Vec<(Guild, UserPermission)>, serenity::Error> {
+) -> Result<Vec<(Guild, PermissionResponse)>, serenity::Error> {
     let mut response = vec![];
     for guild_id in cache_http.cache.guilds() {
         if let Ok(perm) = get_permission_level(cache_http, db, user_id, guild_id).await {
             if let Some(guild) = guild_id.to_guild_cached(&cache_http.cache) {
-                response.push(((*guild).clone(), perm.permission));
+                response.push(((*guild).clone(), perm));
             }
         }
     }
@@ -1,5 +1,10 @@
-use crate::file_handling::RECORDINGS_FOLDER;
+use crate::file_handling;
+use crate::file_handling::RecordingTrack;
+use crate::storage::STORAGE;
 use crate::CacheHttp;
+use crate::BASE_URL;
+use serde::Deserialize;
+use serde::Serialize;
 use serenity::async_trait;
 use serenity::model::prelude::GuildId;
 use serenity::model::voice_gateway::id::UserId;
@@ -12,37 +17,118 @@ use songbird::Event;
 use songbird::EventContext;
 use songbird::EventHandler as VoiceEventHandler;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::collections::VecDeque;
 use std::env::var;
+use std::future::Future;
+use std::io::SeekFrom;
 use std::ops::Deref;
+use std::path::Path;
 use std::path::PathBuf;
 use std::process::Stdio;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::sync::LazyLock;
 use std::time::SystemTime;
 use thiserror::Error;
 use tokio::fs;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncSeekExt;
 use tokio::io::AsyncWriteExt;
 use tokio::process::Command;
-use tokio::sync::MutexGuard;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TryRecvError;
 use tokio::time::sleep;
 use tokio::time::Duration;
 use tracing::Instrument;
 use tracing::Level;
 
-static RECORDING_LENGTH: LazyLock<u64> = LazyLock::new(|| {
+/// Maximum length of the rolling buffer in seconds. Guilds can choose a shorter one, which this is the default for.
+pub static RECORDING_LENGTH: LazyLock<u64> = LazyLock::new(|| {
     var("RECORDING_LENGTH")
         .ok()
         .and_then(|content| content.parse::<u64>().ok())
         .unwrap_or(60)
 });
 
+/// Memory in bytes the buffered audio of all guilds may use. Audio beyond it is spilled to disk.
+static MEMORY_BUDGET: LazyLock<u64> = LazyLock::new(|| {
+    var("RECORDER_MEMORY_LIMIT_MB")
+        .ok()
+        .and_then(|content| content.parse::<u64>().ok())
+        .unwrap_or(256)
+        * 1024
+        * 1024
+});
+
 /// The sample rate and channel count the voice stream is assumed to have
 pub const SAMPLE_RATE: f64 = 48_000.0;
 pub const CHANNEL_COUNT: u8 = 2;
 
 /// Samples per tick: 48kHz * 20ms * 2 channels = 1920 samples total (interleaved stereo)
 pub const SAMPLES_PER_TICK: usize = 1920;
+const BYTES_PER_TICK: u64 = (SAMPLES_PER_TICK * std::mem::size_of::<i16>()) as u64;
+
+/// Number of ticks a spill file holds. Recordings are cleaned up every 5 seconds and saving takes a moment, so
+/// the buffer can be somewhat longer than [RECORDING_LENGTH].
+static SPILL_FILE_TICKS: LazyLock<u64> = LazyLock::new(|| (*RECORDING_LENGTH + 10) * 50);
+
+/// File name of the track containing what the bot played, without the extension
+const SOUNDBOARD_TRACK_NAME: &str = "soundboard";
+
+/// 50 ticks per second (1 tick = 20ms)
+fn ticks_to_seconds(ticks: u64) -> f32 {
+    ticks as f32 / 50.0
+}
+
+/// Format the tracks of saved recordings are encoded in
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RecordingFormat {
+    #[default]
+    Mp3,
+    Flac,
+    Opus,
+    Wav,
+}
+
+impl RecordingFormat {
+    pub const ALL: [RecordingFormat; 4] = [
+        RecordingFormat::Mp3,
+        RecordingFormat::Flac,
+        RecordingFormat::Opus,
+        RecordingFormat::Wav,
+    ];
+
+    /// Name of the format as stored in the database, which is also the file extension
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Mp3 => "mp3",
+            Self::Flac => "flac",
+            Self::Opus => "opus",
+            Self::Wav => "wav",
+        }
+    }
+
+    pub fn from_db_str(value: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|format| format.as_str() == value)
+            .copied()
+    }
+
+    /// Encoder arguments for ffmpeg. Everything else follows from the file extension.
+    fn encoder_args(&self) -> &'static [&'static str] {
+        match self {
+            Self::Mp3 => &[],
+            Self::Flac => &["-c:a", "flac"],
+            Self::Opus => &["-c:a", "libopus", "-b:a", "128k"],
+            Self::Wav => &["-c:a", "pcm_s16le"],
+        }
+    }
+}
 
 #[derive(Debug, Error)]
 pub enum RecordingError {
@@ -52,19 +138,393 @@ pub enum RecordingError {
     NoData,
 }
 
+/// Who may be recorded. The recorder cannot access the database, so the API keeps this in sync with it.
+/// Until it has been loaded, nobody is recorded.
+#[derive(Debug, Default)]
+pub struct RecordingConsent {
+    /// Users who do not want to be recorded in any guild
+    pub opted_out_users: HashSet<u64>,
+    /// Choices of users for single guilds by guild and user id. They take precedence over the guild default.
+    pub guild_choices: HashMap<(u64, u64), bool>,
+    /// Guilds that only record users who opted in
+    pub opt_in_guilds: HashSet<u64>,
+}
+
+impl RecordingConsent {
+    pub fn allows(&self, guild_id: u64, user_id: u64) -> bool {
+        if self.opted_out_users.contains(&user_id) {
+            return false;
+        }
+
+        self.guild_choices
+            .get(&(guild_id, user_id))
+            .copied()
+            .unwrap_or_else(|| !self.opt_in_guilds.contains(&guild_id))
+    }
+}
+
+/// Consent that has not been loaded yet allows nobody to be recorded
+fn consent_allows(consent: &Option<RecordingConsent>, guild_id: u64, user_id: u64) -> bool {
+    consent
+        .as_ref()
+        .is_some_and(|consent| consent.allows(guild_id, user_id))
+}
+
+/// Usage of the buffers of all guilds
+#[derive(Default)]
+struct BufferMetrics {
+    memory_bytes: AtomicU64,
+    spilled_bytes: AtomicU64,
+    /// Ticks written to disk since the start, including ones that were removed since
+    spilled_ticks_total: AtomicU64,
+    /// Whether new audio is spilled to disk, as the memory budget is exhausted
+    spilling: AtomicBool,
+}
+
+static METRICS: LazyLock<BufferMetrics> = LazyLock::new(Default::default);
+
+impl BufferMetrics {
+    /// Accounts for audio kept in memory if it fits into the budget
+    fn try_reserve_memory(&self, bytes: u64) -> bool {
+        let result = self
+            .memory_bytes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                (used + bytes <= *MEMORY_BUDGET).then_some(used + bytes)
+            });
+
+        match result {
+            Ok(used) => {
+                // Some headroom is required, so the log does not flap while the budget is almost used up
+                if used + bytes < *MEMORY_BUDGET / 10 * 9
+                    && self.spilling.swap(false, Ordering::Relaxed)
+                {
+                    info!("Recorder buffers fit into memory again");
+                }
+                true
+            }
+            Err(_) => {
+                if !self.spilling.swap(true, Ordering::Relaxed) {
+                    warn!(
+                        budget = *MEMORY_BUDGET,
+                        "Recorder memory budget exhausted, spilling audio to disk"
+                    );
+                }
+                false
+            }
+        }
+    }
+}
+
+/// Current usage of the recorder buffers
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RecorderMetrics {
+    pub memory_bytes: u64,
+    pub memory_budget_bytes: u64,
+    pub spilled_bytes: u64,
+    pub spilled_ticks_total: u64,
+}
+
+/// A fixed-size ring on disk with one slot per tick. It holds the audio of a user that did not fit into memory.
+struct SpillFile {
+    path: PathBuf,
+    file: fs::File,
+}
+
+impl SpillFile {
+    async fn create() -> Result<Self, std::io::Error> {
+        let path = file_handling::temp_path("pcm");
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await?;
+
+        Ok(Self { path, file })
+    }
+
+    fn offset(tick: u64) -> SeekFrom {
+        SeekFrom::Start(tick % *SPILL_FILE_TICKS * BYTES_PER_TICK)
+    }
+
+    async fn write_tick(&mut self, tick: u64, audio: &[i16]) -> Result<(), std::io::Error> {
+        // Every tick contains 20ms of audio, which the slots are sized for
+        let mut bytes = Vec::with_capacity(BYTES_PER_TICK as usize);
+        for sample in audio
+            .iter()
+            .chain(std::iter::repeat(&0))
+            .take(SAMPLES_PER_TICK)
+        {
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        self.file.seek(Self::offset(tick)).await?;
+        self.file.write_all(&bytes).await
+    }
+
+    async fn read_ticks(
+        &mut self,
+        first_tick: u64,
+        count: u64,
+        data: &mut Vec<i16>,
+    ) -> Result<(), std::io::Error> {
+        let mut bytes = vec![0; BYTES_PER_TICK as usize];
+        for tick in first_tick..first_tick + count {
+            self.file.seek(Self::offset(tick)).await?;
+            self.file.read_exact(&mut bytes).await?;
+            data.extend(
+                bytes
+                    .chunks_exact(2)
+                    .map(|sample| i16::from_le_bytes([sample[0], sample[1]])),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            warn!(?err, path = ?self.path, "Failed to remove spill file");
+        }
+    }
+}
+
+/// A continuous recording of a user as kept in the buffer. It starts in memory and continues in the spill file
+/// of the user once the memory budget is exhausted.
+struct BufferedRecording {
+    start_tick: u64,
+    end_tick: Option<u64>, // None = still recording, Some = ended
+    samples: Vec<i16>,
+    /// Number of ticks in `samples`
+    memory_ticks: u64,
+    /// Number of ticks following the ones in memory that are kept in the spill file
+    spilled_ticks: u64,
+}
+
+impl BufferedRecording {
+    fn new(start_tick: u64) -> Self {
+        Self {
+            start_tick,
+            end_tick: None,
+            samples: Vec::new(),
+            memory_ticks: 0,
+            spilled_ticks: 0,
+        }
+    }
+
+    /// Reads the recording back into memory for saving
+    async fn materialize(
+        &self,
+        spill_file: Option<&mut SpillFile>,
+    ) -> Result<VoiceRecording, std::io::Error> {
+        let mut data = self.samples.clone();
+        if self.spilled_ticks > 0 {
+            let spill_file = spill_file.ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::NotFound, "Spill file is missing")
+            })?;
+            spill_file
+                .read_ticks(
+                    self.start_tick + self.memory_ticks,
+                    self.spilled_ticks,
+                    &mut data,
+                )
+                .await?;
+        }
+
+        Ok(VoiceRecording {
+            start_tick: self.start_tick,
+            end_tick: self.end_tick,
+            data,
+        })
+    }
+}
+
+impl Drop for BufferedRecording {
+    fn drop(&mut self) {
+        METRICS.memory_bytes.fetch_sub(
+            (self.samples.len() * std::mem::size_of::<i16>()) as u64,
+            Ordering::Relaxed,
+        );
+        METRICS
+            .spilled_bytes
+            .fetch_sub(self.spilled_ticks * BYTES_PER_TICK, Ordering::Relaxed);
+    }
+}
+
+/// A recording with all of its audio in memory, as used for saving
 #[derive(Clone)]
 struct VoiceRecording {
     start_tick: u64,
-    end_tick: Option<u64>, // None = still recording, Some = ended
+    end_tick: Option<u64>,
     data: Vec<i16>,
 }
 
+/// The buffered audio of a user or the soundboard
+#[derive(Default)]
+struct TrackBuffer {
+    recordings: VecDeque<BufferedRecording>,
+    /// Created once audio of the track does not fit into memory
+    spill_file: Option<SpillFile>,
+}
+
+impl TrackBuffer {
+    /// Extends the active recording by the audio of the tick or starts a new recording
+    async fn push_audio(&mut self, current_tick: u64, audio: &[i16]) {
+        if !matches!(self.recordings.back(), Some(recording) if recording.end_tick.is_none()) {
+            self.recordings
+                .push_back(BufferedRecording::new(current_tick));
+            trace!(
+                recording_count = self.recordings.len(),
+                len = audio.len(),
+                "Starting new recording"
+            );
+        }
+        let recording = self
+            .recordings
+            .back_mut()
+            .expect("Recordings cannot be empty");
+
+        // Once a recording is spilled, it continues on disk, so the part in memory stays contiguous
+        let bytes = std::mem::size_of_val(audio) as u64;
+        if recording.spilled_ticks == 0 && METRICS.try_reserve_memory(bytes) {
+            recording.samples.extend_from_slice(audio);
+            recording.memory_ticks += 1;
+            trace!(
+                total_len = recording.samples.len(),
+                "Extending active recording"
+            );
+            return;
+        }
+
+        let tick = recording.start_tick + recording.memory_ticks + recording.spilled_ticks;
+        let result = match &mut self.spill_file {
+            Some(spill_file) => spill_file.write_tick(tick, audio).await,
+            None => match SpillFile::create().await {
+                Ok(spill_file) => {
+                    self.spill_file
+                        .insert(spill_file)
+                        .write_tick(tick, audio)
+                        .await
+                }
+                Err(err) => Err(err),
+            },
+        };
+
+        match result {
+            Ok(()) => {
+                recording.spilled_ticks += 1;
+                METRICS
+                    .spilled_bytes
+                    .fetch_add(BYTES_PER_TICK, Ordering::Relaxed);
+                METRICS.spilled_ticks_total.fetch_add(1, Ordering::Relaxed);
+                trace!(
+                    spilled_ticks = recording.spilled_ticks,
+                    "Spilled audio to disk"
+                );
+            }
+            Err(err) => {
+                // The audio is lost, so the recording ends here and the tick is saved as silence
+                warn!(?err, "Failed to spill audio to disk");
+                recording.end_tick = Some(current_tick);
+            }
+        }
+    }
+
+    /// Marks the active recording as ended
+    fn end_recording(&mut self, current_tick: u64) {
+        if let Some(recording) = self.recordings.back_mut() {
+            if recording.end_tick.is_none() {
+                recording.end_tick = Some(current_tick);
+                trace!(tick = current_tick, "Ending recording");
+            }
+        }
+    }
+
+    /// Removes recordings that are older than the buffer length
+    #[instrument(skip(self))]
+    fn cleanup(&mut self, current_tick: u64, buffer_length: u64) {
+        let mut counter: u32 = 0;
+
+        // Remove recordings older than the buffer length
+        // 50 ticks per second (1 tick = 20ms)
+        let max_age_ticks = buffer_length * 50;
+
+        while let Some(true) = self.recordings.front().map(|front| {
+            // Check if recording is too old
+            front.start_tick < current_tick.saturating_sub(max_age_ticks)
+        }) {
+            self.recordings
+                .pop_front()
+                .expect("Missing element in Deque");
+            counter += 1;
+        }
+
+        // The spill file is only kept while it is needed
+        if self.spill_file.is_some()
+            && self
+                .recordings
+                .iter()
+                .all(|recording| recording.spilled_ticks == 0)
+        {
+            self.spill_file = None;
+        }
+
+        if counter > 0 {
+            debug!(
+                remaining_recordings = self.recordings.len(),
+                "Removed {} timed out recordings", counter
+            );
+        }
+    }
+
+    /// Reads all recordings into memory. Everything before `start_tick` is left out if given.
+    async fn materialize(
+        &mut self,
+        start_tick: Option<u64>,
+    ) -> Result<VecDeque<VoiceRecording>, std::io::Error> {
+        let mut recordings = VecDeque::with_capacity(self.recordings.len());
+        for recording in self.recordings.iter() {
+            recordings.push_back(recording.materialize(self.spill_file.as_mut()).await?);
+        }
+        if let Some(start_tick) = start_tick {
+            trim_recordings(&mut recordings, start_tick);
+        }
+
+        Ok(recordings)
+    }
+
+    /// Drops all buffered audio
+    fn clear(&mut self) {
+        self.recordings.clear();
+        self.spill_file = None;
+    }
+}
+
 struct UserData {
     user_id: UserId,
-    recordings: VecDeque<VoiceRecording>,
+    buffer: TrackBuffer,
     last_voice_activity: SystemTime,
 }
 
+/// What the bot itself plays. Songbird does not expose the audio it sends, so sounds are decoded a second time
+/// and buffered tick by tick as if the bot was speaking.
+#[derive(Default)]
+struct SoundboardTrack {
+    buffer: TrackBuffer,
+    /// Decoded audio of the current sound, one tick per message
+    playback: Option<mpsc::Receiver<Vec<i16>>>,
+}
+
+/// Whose audio a track contains
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum TrackSource {
+    User(UserId),
+    Soundboard,
+}
+
 struct GuildRecorder {
     guild_id: GuildId,
     /// Maps an ssrc to a user
@@ -73,6 +533,13 @@ struct GuildRecorder {
     tick_counter: Arc<Mutex<u64>>,
     /// Counter for periodic cleanup (only cleanup every N ticks to avoid spam)
     cleanup_counter: Arc<Mutex<u64>>,
+    /// Shared by all guilds
+    consent: Arc<RwLock<Option<RecordingConsent>>>,
+    /// Buffer lengths in seconds chosen by guilds. Shared by all guilds.
+    buffer_lengths: Arc<RwLock<HashMap<u64, u64>>>,
+    /// Formats chosen by guilds. Shared by all guilds.
+    formats: Arc<RwLock<HashMap<u64, RecordingFormat>>>,
+    soundboard: Mutex<SoundboardTrack>,
 }
 
 #[derive(Clone)]
@@ -99,6 +566,7 @@ impl VoiceEventHandler for GuildRecorderArc {
                 // Clear all user data
                 let mut users = self.users.write().await;
                 users.clear();
+                *self.soundboard.lock().await = Default::default();
 
                 // Note: The Recorder's guild entry will be cleaned up when
                 // the bot explicitly joins a new channel, as register_with_call
@@ -126,7 +594,7 @@ impl VoiceEventHandler for GuildRecorderArc {
                                 Arc::new(Mutex::new(UserData {
                                     user_id: *user_id,
                                     last_voice_activity: SystemTime::now(),
-                                    recordings: Default::default(),
+                                    buffer: Default::default(),
                                 })),
                             );
                         }
@@ -146,6 +614,7 @@ impl VoiceEventHandler for GuildRecorderArc {
                 };
 
                 // Process speaking users - extend or create recordings
+                let consent = self.consent.read().await;
                 for (ssrc, decoded_voice) in voice_tick.speaking.iter() {
                     let audio = decoded_voice.decoded_voice.as_ref();
 
@@ -166,56 +635,49 @@ impl VoiceEventHandler for GuildRecorderArc {
                         if let Some(user_lock) = user_lock {
                             let mut user = user_lock.lock().await;
 
-                            // Check if we have an active recording (end_tick = None)
-                            let has_active_recording = user
-                                .recordings
-                                .back()
-                                .map(|r| r.end_tick.is_none())
-                                .unwrap_or(false);
-
-                            if has_active_recording {
-                                // Extend the active recording
-                                let recording = user
-                                    .recordings
-                                    .back_mut()
-                                    .expect("Recordings cannot be empty");
-                                recording.data.extend_from_slice(audio);
-
-                                trace!(
-                                    total_len = recording.data.len(),
-                                    "Extending active recording"
-                                );
-                            } else {
-                                // Create a new recording
-                                user.recordings.push_back(VoiceRecording {
-                                    start_tick: current_tick,
-                                    end_tick: None, // Active recording
-                                    data: audio.to_vec(),
-                                });
-                                trace!(
-                                    recording_count = user.recordings.len(),
-                                    len = audio.len(),
-                                    "Starting new recording"
-                                );
+                            // Users without consent are never buffered
+                            if !consent_allows(&consent, self.guild_id.get(), user.user_id.0) {
+                                continue;
                             }
+
+                            user.buffer.push_audio(current_tick, audio).await;
                             user.last_voice_activity = SystemTime::now();
                         }
                     }
                 }
 
+                drop(consent);
+
                 // Process silent users - mark recordings as ended
                 let users = self.users.read().await;
                 for (ssrc, user_lock) in users.iter() {
                     if !voice_tick.speaking.contains_key(ssrc) {
                         let mut user = user_lock.lock().await;
-
-                        // Mark the active recording as ended
-                        if let Some(recording) = user.recordings.back_mut() {
-                            if recording.end_tick.is_none() {
-                                recording.end_tick = Some(current_tick);
-                                trace!(ssrc = ssrc, tick = current_tick, "Ending recording");
-                            }
+                        user.buffer.end_recording(current_tick);
+                    }
+                }
+                drop(users);
+
+                // The sound that is playing, if any, is buffered like a speaking user
+                {
+                    let mut soundboard = self.soundboard.lock().await;
+                    let received = soundboard
+                        .playback
+                        .as_mut()
+                        .map(|playback| playback.try_recv());
+                    let audio = match received {
+                        Some(Ok(audio)) => Some(audio),
+                        Some(Err(TryRecvError::Disconnected)) => {
+                            // The sound is over
+                            soundboard.playback = None;
+                            None
                         }
+                        // Decoding has not caught up yet
+                        Some(Err(TryRecvError::Empty)) | None => None,
+                    };
+                    match audio {
+                        Some(audio) => soundboard.buffer.push_audio(current_tick, &audio).await,
+                        None => soundboard.buffer.end_recording(current_tick),
                     }
                 }
 
@@ -228,11 +690,17 @@ impl VoiceEventHandler for GuildRecorderArc {
                 };
 
                 if should_cleanup {
+                    let buffer_length = self.buffer_length().await;
                     let users = self.users.read().await;
                     for user_lock in users.values() {
                         let mut user = user_lock.lock().await;
-                        self.cleanup_user_recordings(&mut user, current_tick);
+                        user.buffer.cleanup(current_tick, buffer_length);
                     }
+                    self.soundboard
+                        .lock()
+                        .await
+                        .buffer
+                        .cleanup(current_tick, buffer_length);
                 }
             }
             _ => {
@@ -246,15 +714,100 @@ impl VoiceEventHandler for GuildRecorderArc {
 }
 
 impl GuildRecorderArc {
-    pub fn new(guild_id: GuildId) -> Self {
+    pub fn new(
+        guild_id: GuildId,
+        consent: Arc<RwLock<Option<RecordingConsent>>>,
+        buffer_lengths: Arc<RwLock<HashMap<u64, u64>>>,
+        formats: Arc<RwLock<HashMap<u64, RecordingFormat>>>,
+    ) -> Self {
         GuildRecorderArc(Arc::new(GuildRecorder {
             guild_id,
             users: Default::default(),
             tick_counter: Arc::new(Mutex::new(0)),
             cleanup_counter: Arc::new(Mutex::new(0)),
+            consent,
+            buffer_lengths,
+            formats,
+            soundboard: Default::default(),
         }))
     }
 
+    /// Length of the rolling buffer in seconds
+    async fn buffer_length(&self) -> u64 {
+        buffer_length(&*self.buffer_lengths.read().await, self.guild_id)
+    }
+
+    /// Decodes the sound the bot starts playing, so it can be buffered along with the users. It replaces the
+    /// previous sound, just like the playback does.
+    #[instrument(skip(self))]
+    async fn start_playback(
+        &self,
+        sound_path: &Path,
+        volume_adjustment: f32,
+    ) -> Result<(), std::io::Error> {
+        let mut child = Command::new("ffmpeg")
+            .kill_on_drop(true)
+            .arg("-i")
+            .arg(sound_path)
+            .args([
+                "-af",
+                &format!("volume={}dB", volume_adjustment),
+                "-f",
+                "s16le",
+                "-ar",
+                &SAMPLE_RATE.to_string(),
+                "-ac",
+                &CHANNEL_COUNT.to_string(),
+                "pipe:",
+            ])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let mut stdout = child.stdout.take().expect("Stdout is piped");
+
+        // Decoding is faster than playing, so it waits for the ticks to take the audio
+        let (sender, receiver) = mpsc::channel(50);
+        tokio::spawn(
+            async move {
+                // Killed on drop, i.e. once the playback was replaced or stopped
+                let _child = child;
+                let mut bytes = vec![0; BYTES_PER_TICK as usize];
+                loop {
+                    let mut filled = 0;
+                    while filled < bytes.len() {
+                        match stdout.read(&mut bytes[filled..]).await {
+                            Ok(0) => break,
+                            Ok(read) => filled += read,
+                            Err(err) => {
+                                warn!(?err, "Failed to decode sound for the recorder");
+                                return;
+                            }
+                        }
+                    }
+                    if filled == 0 {
+                        break;
+                    }
+
+                    // The end of the sound is padded to a full tick
+                    bytes[filled..].fill(0);
+                    let audio = bytes
+                        .chunks_exact(2)
+                        .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+                        .collect();
+                    if sender.send(audio).await.is_err() || filled < bytes.len() {
+                        break;
+                    }
+                }
+            }
+            .instrument(span!(Level::INFO, "playback_decoder")),
+        );
+
+        self.soundboard.lock().await.playback = Some(receiver);
+
+        Ok(())
+    }
+
     /// Spawns a garbage collector thread for the given user. The thread periodically checks whether the user is
     /// inactive and removes them if so.
     #[instrument(skip(self))]
@@ -297,41 +850,21 @@ impl GuildRecorderArc {
         );
     }
 
-    /// Cleans up timed out recordings for a user
-    #[instrument(skip(self, user))]
-    fn cleanup_user_recordings(&self, user: &mut MutexGuard<UserData>, current_tick: u64) {
-        let mut counter: u32 = 0;
-
-        // Remove recordings older than RECORDING_LENGTH seconds
-        // 50 ticks per second (1 tick = 20ms)
-        let max_age_ticks = *RECORDING_LENGTH * 50;
-
-        while let Some(true) = user.recordings.front().map(|front| {
-            // Check if recording is too old
-            front.start_tick < current_tick.saturating_sub(max_age_ticks)
-        }) {
-            user.recordings
-                .pop_front()
-                .expect("Missing element in Deque");
-            counter += 1;
-        }
-
-        if counter > 0 {
-            debug!(
-                remaining_recordings = user.recordings.len(),
-                "Removed {} timed out recordings", counter
-            );
-        }
-    }
-
-    /// Saves the recording to disk
+    /// Saves the recording to disk. Only the last `window` seconds are saved if given, otherwise the whole
+    /// buffer.
     #[instrument(skip(self, cache_and_http), err)]
     pub async fn save_channel_recording(
         &self,
         cache_and_http: &CacheHttp,
+        triggered_by: Option<serenity::model::prelude::UserId>,
+        window: Option<u64>,
     ) -> Result<(), RecordingError> {
-        let mut recordings: HashMap<UserId, VecDeque<VoiceRecording>> = HashMap::new();
+        let mut recordings: HashMap<TrackSource, VecDeque<VoiceRecording>> = HashMap::new();
         let current_tick = *self.tick_counter.lock().await;
+        let buffer_length = self.buffer_length().await;
+        let window_start_tick = window
+            .filter(|window| *window < buffer_length)
+            .map(|window| current_tick.saturating_sub(window * 50));
 
         {
             let users = self.users.read().await;
@@ -340,14 +873,25 @@ impl GuildRecorderArc {
                 let mut user = user.lock().await;
 
                 // Make sure we only consider recordings that are within scope
-                self.cleanup_user_recordings(&mut user, current_tick);
+                user.buffer.cleanup(current_tick, buffer_length);
 
-                if !user.recordings.is_empty() {
-                    recordings.insert(user.user_id, user.recordings.clone());
+                let user_recordings = user.buffer.materialize(window_start_tick).await?;
+                if !user_recordings.is_empty() {
+                    recordings.insert(TrackSource::User(user.user_id), user_recordings);
                 }
             }
         }
 
+        {
+            let mut soundboard = self.soundboard.lock().await;
+            soundboard.buffer.cleanup(current_tick, buffer_length);
+
+            let soundboard_recordings = soundboard.buffer.materialize(window_start_tick).await?;
+            if !soundboard_recordings.is_empty() {
+                recordings.insert(TrackSource::Soundboard, soundboard_recordings);
+            }
+        }
+
         // Find the earliest start_tick and latest end_tick across all users
         let first_start_tick = recordings
             .values()
@@ -367,51 +911,71 @@ impl GuildRecorderArc {
             .ok_or(RecordingError::NoData)?;
 
         debug!(
-            recording_user_count = recordings.len(),
+            track_count = recordings.len(),
             first_start_tick, last_end_tick, "Saving recordings"
         );
 
-        let folder = (*RECORDINGS_FOLDER)
-            .join(self.guild_id.get().to_string())
-            .join(
-                SystemTime::now()
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs()
-                    .to_string(),
-            );
-        fs::create_dir_all(&folder).await?;
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let format = self
+            .formats
+            .read()
+            .await
+            .get(&self.guild_id.get())
+            .copied()
+            .unwrap_or_default();
 
         let mut tasks = Vec::new();
-        for (uid, rec) in recordings.into_iter() {
-            tasks.push(tokio::spawn(GuildRecorderArc::save_user_recording(
+        for (source, rec) in recordings.into_iter() {
+            tasks.push(tokio::spawn(GuildRecorderArc::save_track(
                 cache_and_http.clone(),
                 self.guild_id,
-                uid,
+                source,
                 rec,
-                folder.clone(),
+                format,
+                timestamp,
                 first_start_tick,
                 last_end_tick,
             )));
         }
 
+        let mut tracks = Vec::new();
         for join_handle in tasks {
-            join_handle.await.map_err(std::io::Error::from)??;
+            tracks.push(join_handle.await.map_err(std::io::Error::from)??);
         }
 
+        // Written last, so the recording is only listed with all of its tracks
+        let manifest = file_handling::RecordingManifest::new(
+            self.guild_id.get(),
+            timestamp,
+            ticks_to_seconds(last_end_tick - first_start_tick),
+            triggered_by.map(|user_id| user_id.get()),
+            tracks,
+        );
+        file_handling::save_recording_manifest(&manifest).await?;
+
         Ok(())
     }
 
-    #[instrument(skip(cache_and_http, rec, folder))]
-    async fn save_user_recording(
+    #[allow(clippy::too_many_arguments)]
+    #[instrument(skip(cache_and_http, rec))]
+    async fn save_track(
         cache_and_http: CacheHttp,
         guild_id: GuildId,
-        user_id: UserId,
+        source: TrackSource,
         mut rec: VecDeque<VoiceRecording>,
-        folder: PathBuf,
+        format: RecordingFormat,
+        timestamp: u64,
         first_start_tick: u64,
         last_end_tick: u64,
-    ) -> Result<(), RecordingError> {
+    ) -> Result<RecordingTrack, RecordingError> {
+        let start_offset = rec
+            .front()
+            .map(|first| ticks_to_seconds(first.start_tick - first_start_tick))
+            .unwrap_or(0.0);
+
         // Add a last empty recording at last_end_tick to ensure everything ends at same timepoint
         rec.push_back(VoiceRecording {
             start_tick: last_end_tick,
@@ -427,7 +991,7 @@ impl GuildRecorderArc {
             recording_count = rec.len(),
             first_start_tick,
             last_end_tick,
-            "Saving recording of user"
+            "Saving track"
         );
 
         for mut r in rec.into_iter() {
@@ -448,15 +1012,31 @@ impl GuildRecorderArc {
         }
         debug!("Extracted {} samples", data.len());
 
-        // Convert songbird's UserId to serenity's UserId
-        let serenity_user_id = serenity::model::prelude::UserId::new(user_id.0);
-        let name = guild_id
-            .member(cache_and_http, serenity_user_id)
-            .await
-            .map(|member| member.user.name)
-            .unwrap_or_else(|_| user_id.0.to_string());
-
-        let file = folder.join(sanitize_filename::sanitize(format!("{}.mp3", name)));
+        // Named after the id, as names are neither unique nor stable
+        let (user_id, name, file_name) = match source {
+            TrackSource::User(user_id) => {
+                // Convert songbird's UserId to serenity's UserId
+                let serenity_user_id = serenity::model::prelude::UserId::new(user_id.0);
+                let name = guild_id
+                    .member(cache_and_http, serenity_user_id)
+                    .await
+                    .map(|member| member.display_name().to_string())
+                    .unwrap_or_else(|_| user_id.0.to_string());
+                (
+                    Some(user_id.0),
+                    name,
+                    format!("{}.{}", user_id.0, format.as_str()),
+                )
+            }
+            TrackSource::Soundboard => (
+                None,
+                String::from("Soundboard"),
+                format!("{}.{}", SOUNDBOARD_TRACK_NAME, format.as_str()),
+            ),
+        };
+
+        // Encoded locally first, as ffmpeg cannot write to the storage directly
+        let file = file_handling::temp_path(format.as_str());
         let args = [
             "-f",
             "s16le",
@@ -470,6 +1050,7 @@ impl GuildRecorderArc {
         let mut child = Command::new("ffmpeg")
             .kill_on_drop(true)
             .args(args)
+            .args(format.encoder_args())
             .arg(file.as_os_str())
             .stdin(Stdio::piped())
             .stdout(Stdio::null())
@@ -483,19 +1064,184 @@ impl GuildRecorderArc {
         }
 
         child.wait_with_output().await?;
-        Ok(())
+
+        let key = file_handling::recording_key(guild_id.get(), timestamp, &file_name);
+        let result = STORAGE.put_file(&key, &file).await;
+        fs::remove_file(&file).await.ok();
+        result?;
+
+        Ok(RecordingTrack {
+            user_id,
+            name,
+            file_name,
+            start_offset,
+            length: ticks_to_seconds(last_end_tick - first_start_tick),
+        })
     }
 }
 
+/// Removes everything before the given tick
+fn trim_recordings(recordings: &mut VecDeque<VoiceRecording>, start_tick: u64) {
+    recordings.retain(|r| !matches!(r.end_tick, Some(end_tick) if end_tick <= start_tick));
+
+    // Only the first remaining recording can begin before the start
+    if let Some(first) = recordings.front_mut() {
+        if first.start_tick < start_tick {
+            let skipped_samples = (start_tick - first.start_tick) as usize * SAMPLES_PER_TICK;
+            first.data.drain(..skipped_samples.min(first.data.len()));
+            first.start_tick = start_tick;
+        }
+    }
+}
+
+/// The buffer length of the guild in seconds, which is never longer than [RECORDING_LENGTH]
+fn buffer_length(buffer_lengths: &HashMap<u64, u64>, guild_id: GuildId) -> u64 {
+    buffer_lengths
+        .get(&guild_id.get())
+        .map_or(*RECORDING_LENGTH, |length| (*length).min(*RECORDING_LENGTH))
+}
+
 pub struct Recorder {
     guilds: RwLock<HashMap<GuildId, GuildRecorderArc>>,
+    consent: Arc<RwLock<Option<RecordingConsent>>>,
+    buffer_lengths: Arc<RwLock<HashMap<u64, u64>>>,
+    formats: Arc<RwLock<HashMap<u64, RecordingFormat>>>,
 }
 
 impl Recorder {
     pub fn create() -> Arc<Self> {
         Arc::new(Self {
             guilds: Default::default(),
+            consent: Default::default(),
+            buffer_lengths: Default::default(),
+            formats: Default::default(),
+        })
+    }
+
+    /// Replaces the buffer lengths of all guilds, e.g. after loading them from the database
+    pub async fn set_buffer_lengths(&self, buffer_lengths: HashMap<u64, u64>) {
+        *self.buffer_lengths.write().await = buffer_lengths;
+    }
+
+    /// `None` uses the default length. Audio beyond a shorter length is removed on the next cleanup.
+    pub async fn set_buffer_length(&self, guild_id: u64, length: Option<u64>) {
+        let mut buffer_lengths = self.buffer_lengths.write().await;
+        match length {
+            Some(length) => buffer_lengths.insert(guild_id, length),
+            None => buffer_lengths.remove(&guild_id),
+        };
+    }
+
+    pub fn metrics(&self) -> RecorderMetrics {
+        RecorderMetrics {
+            memory_bytes: METRICS.memory_bytes.load(Ordering::Relaxed),
+            memory_budget_bytes: *MEMORY_BUDGET,
+            spilled_bytes: METRICS.spilled_bytes.load(Ordering::Relaxed),
+            spilled_ticks_total: METRICS.spilled_ticks_total.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Replaces the formats of all guilds, e.g. after loading them from the database
+    pub async fn set_formats(&self, formats: HashMap<u64, RecordingFormat>) {
+        *self.formats.write().await = formats;
+    }
+
+    /// Applies to recordings saved from now on
+    pub async fn set_format(&self, guild_id: u64, format: RecordingFormat) {
+        self.formats.write().await.insert(guild_id, format);
+    }
+
+    /// Replaces all consent information with the one returned by `load`. The consent stays locked while
+    /// loading, so changes pushed meanwhile are applied on top of it instead of being overwritten.
+    pub async fn load_consent<E>(
+        &self,
+        load: impl Future<Output = Result<RecordingConsent, E>>,
+    ) -> Result<(), E> {
+        {
+            let mut consent = self.consent.write().await;
+            *consent = Some(load.await?);
+        }
+        self.forget_unconsented().await;
+        Ok(())
+    }
+
+    pub async fn set_user_opted_out(&self, user_id: u64, opted_out: bool) {
+        self.update_consent(|consent| {
+            if opted_out {
+                consent.opted_out_users.insert(user_id);
+            } else {
+                consent.opted_out_users.remove(&user_id);
+            }
+        })
+        .await;
+    }
+
+    /// `None` removes the choice of the user, so the default of the guild applies
+    pub async fn set_guild_choice(&self, guild_id: u64, user_id: u64, choice: Option<bool>) {
+        self.update_consent(|consent| {
+            match choice {
+                Some(choice) => consent.guild_choices.insert((guild_id, user_id), choice),
+                None => consent.guild_choices.remove(&(guild_id, user_id)),
+            };
+        })
+        .await;
+    }
+
+    pub async fn set_opt_in_required(&self, guild_id: u64, required: bool) {
+        self.update_consent(|consent| {
+            if required {
+                consent.opt_in_guilds.insert(guild_id);
+            } else {
+                consent.opt_in_guilds.remove(&guild_id);
+            }
         })
+        .await;
+    }
+
+    /// Changes before the consent has been loaded are ignored, as the load includes them
+    async fn update_consent(&self, update: impl FnOnce(&mut RecordingConsent)) {
+        if let Some(consent) = self.consent.write().await.as_mut() {
+            update(consent);
+        }
+        self.forget_unconsented().await;
+    }
+
+    /// Message informing a voice channel that the recorder is active
+    pub async fn announcement(&self, guild_id: GuildId) -> String {
+        let opt_in_required = self
+            .consent
+            .read()
+            .await
+            .as_ref()
+            .is_none_or(|consent| consent.opt_in_guilds.contains(&guild_id.get()));
+        let who = if opt_in_required {
+            "Users who opted in are"
+        } else {
+            "Everyone who speaks is"
+        };
+
+        format!(
+            ":red_circle: {} recorded. The last {} seconds can be saved by members of the soundboard. \
+            You can opt out at {}/settings/user",
+            who,
+            buffer_length(&*self.buffer_lengths.read().await, guild_id),
+            BASE_URL.as_str()
+        )
+    }
+
+    /// Drops the buffered audio of users that may not be recorded anymore
+    async fn forget_unconsented(&self) {
+        let consent = self.consent.read().await;
+        let guilds = self.guilds.read().await;
+        for guild_recorder in guilds.values() {
+            let users = guild_recorder.users.read().await;
+            for user_lock in users.values() {
+                let mut user = user_lock.lock().await;
+                if !consent_allows(&consent, guild_recorder.guild_id.get(), user.user_id.0) {
+                    user.buffer.clear();
+                }
+            }
+        }
     }
 
     /// Register the recorder as event handler
@@ -509,7 +1255,14 @@ impl Recorder {
             let mut guilds = self.guilds.write().await;
             guild_recorder = guilds
                 .entry(guild_id)
-                .or_insert_with(|| GuildRecorderArc::new(guild_id))
+                .or_insert_with(|| {
+                    GuildRecorderArc::new(
+                        guild_id,
+                        self.consent.clone(),
+                        self.buffer_lengths.clone(),
+                        self.formats.clone(),
+                    )
+                })
                 .clone();
         }
 
@@ -524,6 +1277,31 @@ impl Recorder {
         }
     }
 
+    /// Buffers the sound the bot plays, so it is part of saved recordings
+    pub async fn start_playback(
+        &self,
+        guild_id: GuildId,
+        sound_path: &Path,
+        volume_adjustment: f32,
+    ) {
+        let guild_recorder = self.guilds.read().await.get(&guild_id).cloned();
+        if let Some(guild_recorder) = guild_recorder {
+            if let Err(err) = guild_recorder
+                .start_playback(sound_path, volume_adjustment)
+                .await
+            {
+                warn!(?err, "Failed to buffer the playback for the recorder");
+            }
+        }
+    }
+
+    pub async fn stop_playback(&self, guild_id: GuildId) {
+        let guild_recorder = self.guilds.read().await.get(&guild_id).cloned();
+        if let Some(guild_recorder) = guild_recorder {
+            guild_recorder.soundboard.lock().await.playback = None;
+        }
+    }
+
     /// Removes the guild recorder and cleans up all associated data
     pub async fn unregister_guild(&self, guild_id: GuildId) {
         let mut guilds = self.guilds.write().await;
@@ -536,6 +1314,8 @@ impl Recorder {
         &self,
         guild_id: GuildId,
         cache_and_http: &CacheHttp,
+        triggered_by: Option<serenity::model::prelude::UserId>,
+        window: Option<u64>,
     ) -> Result<(), RecordingError> {
         let guild_recorder;
         {
@@ -543,6 +1323,86 @@ impl Recorder {
             guild_recorder = guilds.get(&guild_id).ok_or(RecordingError::NoData)?.clone();
         }
 
-        guild_recorder.save_channel_recording(cache_and_http).await
+        guild_recorder
+            .save_channel_recording(cache_and_http, triggered_by, window)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD: u64 = 1;
+    const USER: u64 = 2;
+
+    #[test]
+    fn unloaded_consent_allows_nobody() {
+        assert!(!consent_allows(&None, GUILD, USER));
+        assert!(consent_allows(
+            &Some(RecordingConsent::default()),
+            GUILD,
+            USER
+        ));
+    }
+
+    #[test]
+    fn opt_out_applies_to_all_guilds() {
+        let consent = RecordingConsent {
+            opted_out_users: HashSet::from([USER]),
+            guild_choices: HashMap::from([((GUILD, USER), true)]),
+            ..Default::default()
+        };
+
+        assert!(!consent.allows(GUILD, USER));
+        assert!(!consent.allows(GUILD + 1, USER));
+    }
+
+    #[test]
+    fn guild_choice_overrides_guild_default() {
+        let consent = RecordingConsent {
+            guild_choices: HashMap::from([((GUILD, USER), true), ((GUILD + 1, USER), false)]),
+            opt_in_guilds: HashSet::from([GUILD]),
+            ..Default::default()
+        };
+
+        assert!(consent.allows(GUILD, USER));
+        assert!(!consent.allows(GUILD + 1, USER));
+        assert!(!consent.allows(GUILD, USER + 1));
+        assert!(consent.allows(GUILD + 1, USER + 1));
+    }
+
+    #[tokio::test]
+    async fn recorder_refuses_until_consent_is_loaded() {
+        let recorder = Recorder::create();
+        recorder.set_user_opted_out(USER + 1, true).await;
+        assert!(!consent_allows(
+            &*recorder.consent.read().await,
+            GUILD,
+            USER
+        ));
+
+        let failed = recorder
+            .load_consent(async { Err::<RecordingConsent, ()>(()) })
+            .await;
+        assert!(failed.is_err());
+        assert!(!consent_allows(
+            &*recorder.consent.read().await,
+            GUILD,
+            USER
+        ));
+
+        recorder
+            .load_consent(async { Ok::<_, ()>(RecordingConsent::default()) })
+            .await
+            .unwrap();
+        assert!(consent_allows(&*recorder.consent.read().await, GUILD, USER));
+
+        recorder.set_user_opted_out(USER, true).await;
+        assert!(!consent_allows(
+            &*recorder.consent.read().await,
+            GUILD,
+            USER
+        ));
     }
 }
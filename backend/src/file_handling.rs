@@ -1,5 +1,13 @@
 use crate::audio_utils;
-use std::ffi::OsString;
+use crate::storage::StoredObject;
+use crate::storage::STORAGE;
+use rand::random;
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::env::var;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::LazyLock;
@@ -7,27 +15,346 @@ use std::time::Duration;
 use std::time::SystemTime;
 use thiserror::Error;
 use tokio::fs;
-use tokio::fs::ReadDir;
 use tokio::io;
 
-static SOUNDS_FOLDER: LazyLock<&Path> = LazyLock::new(|| Path::new("data/sounds"));
-pub static RECORDINGS_FOLDER: LazyLock<&Path> = LazyLock::new(|| Path::new("data/recorder"));
-pub static MIXES_FOLDER: LazyLock<&Path> = LazyLock::new(|| Path::new("data/mixes"));
+fn folder_from_env(name: &str, default: impl FnOnce() -> PathBuf) -> PathBuf {
+    var(name)
+        .ok()
+        .filter(|folder| !folder.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(default)
+}
+
+/// Base folder for the data of the app. The other folders are placed inside by default.
+pub static DATA_FOLDER: LazyLock<PathBuf> =
+    LazyLock::new(|| folder_from_env("DATA_DIR", || PathBuf::from("data")));
+static SOUNDS_FOLDER: LazyLock<PathBuf> =
+    LazyLock::new(|| folder_from_env("SOUNDS_DIR", || DATA_FOLDER.join("sounds")));
+static RECORDINGS_FOLDER: LazyLock<PathBuf> =
+    LazyLock::new(|| folder_from_env("RECORDINGS_DIR", || DATA_FOLDER.join("recorder")));
+static MIXES_FOLDER: LazyLock<PathBuf> =
+    LazyLock::new(|| folder_from_env("MIXES_DIR", || DATA_FOLDER.join("mixes")));
+/// Local scratch space, e.g. for uploads that are analyzed before they are stored. `TEMP_DIR` might be shared
+/// with other programs, so the app uses its own folder inside and only removes its own files on startup.
+pub static TEMP_FOLDER: LazyLock<PathBuf> =
+    LazyLock::new(|| folder_from_env("TEMP_DIR", || DATA_FOLDER.join("tmp")).join(TEMP_SUBFOLDER));
+const TEMP_SUBFOLDER: &str = "soundboard-tmp";
+/// Local copies of files in object storage
+pub static CACHE_FOLDER: LazyLock<PathBuf> =
+    LazyLock::new(|| folder_from_env("CACHE_DIR", || DATA_FOLDER.join("cache")));
+
+/// How long mixes are available for download
+pub const MIX_LIFETIME: Duration = Duration::from_secs(5 * 60);
+
+/// Records where sounds and recordings were kept, so they can be moved when the folders change
+const LAYOUT_FILE: &str = "layout.json";
+
+#[derive(Debug, Error)]
+pub enum FolderError {
+    #[error("{name} ({}) cannot be used: {source}", .path.display())]
+    Inaccessible {
+        name: &'static str,
+        path: PathBuf,
+        source: io::Error,
+    },
+
+    #[error(
+        "{name} ({}) must not be the same as or inside {other} ({})",
+        .path.display(),
+        .other_path.display()
+    )]
+    Overlapping {
+        name: &'static str,
+        path: PathBuf,
+        other: &'static str,
+        other_path: PathBuf,
+    },
+
+    #[error(
+        "{name} moved from {} to {}, but both contain files. Move the files manually.",
+        .from.display(),
+        .to.display()
+    )]
+    MigrationConflict {
+        name: &'static str,
+        from: PathBuf,
+        to: PathBuf,
+    },
+
+    #[error("Failed to move {name} from {} to {}: {source}", .from.display(), .to.display())]
+    MigrationFailed {
+        name: &'static str,
+        from: PathBuf,
+        to: PathBuf,
+        source: io::Error,
+    },
+
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+}
+
+/// Folder of local storage for the first part of a storage key
+pub fn local_folder(area: &str) -> PathBuf {
+    match area {
+        SOUNDS_KEY => SOUNDS_FOLDER.clone(),
+        RECORDINGS_KEY => RECORDINGS_FOLDER.clone(),
+        MIXES_KEY => MIXES_FOLDER.clone(),
+        other => DATA_FOLDER.join(other),
+    }
+}
 
-pub async fn create_folders() -> Result<(), io::Error> {
-    fs::create_dir_all(*SOUNDS_FOLDER).await?;
-    fs::create_dir_all(*RECORDINGS_FOLDER).await?;
-    if MIXES_FOLDER.exists() {
-        // Clean temporary mixes that might be remaining
-        fs::remove_dir_all(*MIXES_FOLDER).await?;
+/// Checks that all folders can be used, moves data from a previous layout, writes missing recording manifests
+/// and cleans up temporary files
+pub async fn prepare_folders() -> Result<(), FolderError> {
+    let mut folders = vec![
+        ("DATA_DIR", DATA_FOLDER.clone()),
+        ("TEMP_DIR", TEMP_FOLDER.clone()),
+    ];
+    if STORAGE.is_local() {
+        folders.push(("SOUNDS_DIR", SOUNDS_FOLDER.clone()));
+        folders.push(("RECORDINGS_DIR", RECORDINGS_FOLDER.clone()));
+        folders.push(("MIXES_DIR", MIXES_FOLDER.clone()));
+    } else {
+        folders.push(("CACHE_DIR", CACHE_FOLDER.clone()));
+    }
+
+    let mut canonical = Vec::new();
+    for (name, path) in folders {
+        check_writable(&path)
+            .await
+            .map_err(|source| FolderError::Inaccessible {
+                name,
+                path: path.clone(),
+                source,
+            })?;
+        canonical.push((name, fs::canonicalize(&path).await?));
+    }
+
+    // Files of different folders would be mixed up. Only the data folder may contain the others.
+    for (name, path) in &canonical {
+        for (other, other_path) in &canonical {
+            if name != other && *other != "DATA_DIR" && path.starts_with(other_path) {
+                return Err(FolderError::Overlapping {
+                    name,
+                    path: path.clone(),
+                    other,
+                    other_path: other_path.clone(),
+                });
+            }
+        }
+    }
+
+    if STORAGE.is_local() {
+        migrate_layout().await?;
+    } else {
+        // Recordings and mixes were cached by earlier versions
+        for area in [RECORDINGS_KEY, MIXES_KEY] {
+            match fs::remove_dir_all(CACHE_FOLDER.join(area)).await {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        STORAGE.index_cache().await?;
+    }
+    migrate_recording_manifests().await?;
+
+    clear_temp_folder().await?;
+
+    // Other instances might share the storage, so only mixes that timed out are removed
+    for mix in STORAGE.list(MIXES_KEY).await? {
+        let expired = mix
+            .last_modified
+            .elapsed()
+            .map(|age| age > MIX_LIFETIME)
+            .unwrap_or(false);
+        if expired {
+            STORAGE.delete(&mix.key).await?;
+        }
     }
-    fs::create_dir_all(*MIXES_FOLDER).await?;
 
     Ok(())
 }
 
-pub fn get_full_sound_path(filename: &str) -> PathBuf {
-    (*SOUNDS_FOLDER).join(filename)
+/// Removes the files left over in the temporary folder, e.g. after a crash
+async fn clear_temp_folder() -> Result<(), io::Error> {
+    fs::create_dir_all(&*TEMP_FOLDER).await?;
+    let mut entries = fs::read_dir(&*TEMP_FOLDER).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if !entry.file_name().to_str().is_some_and(is_temp_name) {
+            continue;
+        }
+        if entry.file_type().await?.is_dir() {
+            fs::remove_dir_all(entry.path()).await?;
+        } else {
+            fs::remove_file(entry.path()).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether the name was created by `temp_path`
+fn is_temp_name(name: &str) -> bool {
+    name.split_once('.').is_some_and(|(stem, extension)| {
+        !stem.is_empty()
+            && stem.bytes().all(|byte| byte.is_ascii_digit())
+            && !extension.is_empty()
+            && extension.bytes().all(|byte| byte.is_ascii_alphanumeric())
+    })
+}
+
+async fn check_writable(path: &Path) -> Result<(), io::Error> {
+    fs::create_dir_all(path).await?;
+    let probe = path.join(format!(".write-test-{}", random::<u32>()));
+    fs::write(&probe, b"").await?;
+    fs::remove_file(&probe).await
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Layout {
+    sounds: PathBuf,
+    recordings: PathBuf,
+}
+
+/// Moves sounds and recordings to the configured folders if they were kept elsewhere before. Without a
+/// record of the previous layout, the default folders relative to the working directory are assumed.
+async fn migrate_layout() -> Result<(), FolderError> {
+    let layout_file = DATA_FOLDER.join(LAYOUT_FILE);
+    let previous = match fs::read(&layout_file).await {
+        Ok(content) => serde_json::from_slice::<Layout>(&content).map_err(io::Error::other)?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Layout {
+            sounds: PathBuf::from("data/sounds"),
+            recordings: PathBuf::from("data/recorder"),
+        },
+        Err(err) => return Err(err.into()),
+    };
+    let current = Layout {
+        sounds: fs::canonicalize(&*SOUNDS_FOLDER).await?,
+        recordings: fs::canonicalize(&*RECORDINGS_FOLDER).await?,
+    };
+
+    move_folder_contents("Sounds", &previous.sounds, &current.sounds).await?;
+    move_folder_contents("Recordings", &previous.recordings, &current.recordings).await?;
+
+    let content = serde_json::to_vec_pretty(&current).map_err(io::Error::other)?;
+    fs::write(&layout_file, content).await?;
+    Ok(())
+}
+
+async fn move_folder_contents(
+    name: &'static str,
+    from: &Path,
+    to: &Path,
+) -> Result<(), FolderError> {
+    let from = match fs::canonicalize(from).await {
+        Ok(from) => from,
+        // Nothing to move
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    if from == to || is_empty(&from).await? {
+        return Ok(());
+    }
+    if !is_empty(to).await? {
+        return Err(FolderError::MigrationConflict {
+            name,
+            from,
+            to: to.to_path_buf(),
+        });
+    }
+
+    info!(name, ?from, ?to, "Moving files to the new folder");
+    let mut entries = fs::read_dir(&from).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        move_entry(&entry.path(), &to.join(entry.file_name()))
+            .await
+            .map_err(|source| FolderError::MigrationFailed {
+                name,
+                from: from.clone(),
+                to: to.to_path_buf(),
+                source,
+            })?;
+    }
+    // Fails for mount points, which is fine
+    fs::remove_dir(&from).await.ok();
+
+    Ok(())
+}
+
+async fn is_empty(path: &Path) -> Result<bool, io::Error> {
+    Ok(fs::read_dir(path).await?.next_entry().await?.is_none())
+}
+
+/// Moves a file or folder. Renaming does not work across file systems, so the data is copied then.
+async fn move_entry(from: &Path, to: &Path) -> Result<(), io::Error> {
+    if fs::rename(from, to).await.is_ok() {
+        return Ok(());
+    }
+
+    if fs::metadata(from).await?.is_dir() {
+        fs::create_dir_all(to).await?;
+        let mut entries = fs::read_dir(from).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            Box::pin(move_entry(&entry.path(), &to.join(entry.file_name()))).await?;
+        }
+        fs::remove_dir(from).await
+    } else {
+        fs::copy(from, to).await?;
+        fs::remove_file(from).await
+    }
+}
+
+// First parts of the storage keys
+pub const SOUNDS_KEY: &str = "sounds";
+const RECORDINGS_KEY: &str = "recorder";
+const MIXES_KEY: &str = "mixes";
+
+/// Storage key of a sound file
+pub fn sound_key(file_name: &str) -> String {
+    format!("{}/{}", SOUNDS_KEY, file_name)
+}
+
+/// Storage folder of all recordings of the guild, or of a single one if a timestamp is given
+pub fn recording_folder_key(guild_id: u64, timestamp: Option<u64>) -> String {
+    match timestamp {
+        Some(timestamp) => format!("{}/{}/{}", RECORDINGS_KEY, guild_id, timestamp),
+        None => format!("{}/{}", RECORDINGS_KEY, guild_id),
+    }
+}
+
+/// Storage key of the file of one user in a recording
+pub fn recording_key(guild_id: u64, timestamp: u64, file_name: &str) -> String {
+    format!(
+        "{}/{}",
+        recording_folder_key(guild_id, Some(timestamp)),
+        sanitize_filename::sanitize(file_name)
+    )
+}
+
+pub fn mix_key(guild_id: u64, file_name: &str) -> String {
+    format!(
+        "{}/{}/{}",
+        MIXES_KEY,
+        guild_id,
+        sanitize_filename::sanitize(file_name)
+    )
+}
+
+/// A new path in the temporary folder with the given extension
+pub fn temp_path(extension: &str) -> PathBuf {
+    TEMP_FOLDER.join(format!("{}.{}", random::<u64>(), extension))
+}
+
+/// SHA-256 of the file content, hex encoded
+pub async fn hash_file(path: impl AsRef<Path>) -> Result<String, io::Error> {
+    let path = path.as_ref().to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(path)?;
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut file, &mut hasher)?;
+        Ok(format!("{:x}", hasher.finalize()))
+    })
+    .await
+    .map_err(io::Error::other)?
 }
 
 #[derive(Debug, Error)]
@@ -36,71 +363,251 @@ pub enum FileError {
     IoError(#[from] io::Error),
 }
 
-#[derive(Debug)]
-pub struct Recording {
+/// Recordings without manifest are only migrated once they are older, as they might still be saved
+const MANIFEST_MIGRATION_MIN_AGE: Duration = Duration::from_secs(10 * 60);
+
+/// Name of the file describing a recording. It is stored next to the audio files of the recording.
+pub const RECORDING_MANIFEST: &str = "manifest.json";
+const RECORDING_MANIFEST_VERSION: u32 = 1;
+
+/// Describes a saved recording, so recordings can be listed without inspecting their audio files. User ids are
+/// kept as well, as names change.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordingManifest {
+    pub version: u32,
     pub guild_id: u64,
-    pub timestamp: SystemTime,
+    /// Seconds since the unix epoch. This is the name of the recording folder as well.
+    pub timestamp: u64,
+    /// Length of the recording in seconds
     pub length: f32,
-    pub users: Vec<RecordingUser>,
+    /// The user who saved the recording, if known
+    pub triggered_by: Option<u64>,
+    /// Pinned recordings are never removed by the retention policy
+    #[serde(default)]
+    pub pinned: bool,
+    pub tracks: Vec<RecordingTrack>,
 }
 
-#[derive(Debug)]
-pub struct RecordingUser {
-    /// Parsed name. This is not equivalent to the file name.
+/// The audio of a single user or of the soundboard in a recording
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordingTrack {
+    /// Unknown for recordings saved before manifests were written. Not set for the soundboard.
+    pub user_id: Option<u64>,
+    /// Display name at the time of the recording
     pub name: String,
-    /// This is the file name in the recordings folder
-    pub file_name: OsString,
+    /// File name in the recording folder
+    pub file_name: String,
+    /// Seconds from the start of the recording until the user spoke first
+    pub start_offset: f32,
+    /// Length of the file in seconds
+    pub length: f32,
+}
+
+impl RecordingManifest {
+    pub fn new(
+        guild_id: u64,
+        timestamp: u64,
+        length: f32,
+        triggered_by: Option<u64>,
+        tracks: Vec<RecordingTrack>,
+    ) -> Self {
+        Self {
+            version: RECORDING_MANIFEST_VERSION,
+            guild_id,
+            timestamp,
+            length,
+            triggered_by,
+            pinned: false,
+            tracks,
+        }
+    }
+}
+
+/// Stores the manifest in the folder of its recording. An existing manifest is replaced.
+pub async fn save_recording_manifest(manifest: &RecordingManifest) -> Result<(), io::Error> {
+    let key = recording_key(manifest.guild_id, manifest.timestamp, RECORDING_MANIFEST);
+    STORAGE
+        .write(&key, serde_json::to_vec_pretty(manifest)?)
+        .await
+}
+
+pub async fn read_recording_manifest(
+    guild_id: u64,
+    timestamp: u64,
+) -> Result<RecordingManifest, io::Error> {
+    let key = recording_key(guild_id, timestamp, RECORDING_MANIFEST);
+    Ok(serde_json::from_slice(&STORAGE.read(&key).await?)?)
+}
+
+/// The stored files of each recording of the guild by timestamp
+async fn list_recording_files(
+    guild_id: u64,
+) -> Result<BTreeMap<u64, Vec<StoredObject>>, io::Error> {
+    let folder = recording_folder_key(guild_id, None);
+    let mut recordings: BTreeMap<u64, Vec<StoredObject>> = BTreeMap::new();
+    for object in STORAGE.list(&folder).await? {
+        let relative = object
+            .key
+            .strip_prefix(&folder)
+            .unwrap_or(&object.key)
+            .trim_start_matches('/');
+        match relative.split_once('/') {
+            Some((timestamp, file_name)) if !file_name.contains('/') => {
+                if let Ok(timestamp) = timestamp.parse::<u64>() {
+                    recordings.entry(timestamp).or_default().push(object);
+                } else {
+                    warn!(key = %object.key, "Folder has invalid name. Must be a number.");
+                }
+            }
+            _ => warn!(key = %object.key, "File found in invalid location"),
+        }
+    }
+
+    Ok(recordings)
+}
+
+/// Size in bytes of each recording of the guild by timestamp
+pub async fn get_recording_sizes(guild_id: u64) -> Result<BTreeMap<u64, u64>, FileError> {
+    Ok(list_recording_files(guild_id)
+        .await?
+        .into_iter()
+        .map(|(timestamp, objects)| (timestamp, objects.iter().map(|object| object.size).sum()))
+        .collect())
 }
 
+/// All recordings of the guild, oldest first
 #[instrument(err)]
-pub async fn get_recordings_for_guild(guild_id: u64) -> Result<Vec<Recording>, FileError> {
+pub async fn get_recordings_for_guild(guild_id: u64) -> Result<Vec<RecordingManifest>, FileError> {
+    let recordings = list_recording_files(guild_id).await?;
+
     let mut results = Vec::new();
-    let start_dir = (*RECORDINGS_FOLDER).join(guild_id.to_string());
-    if !start_dir.exists() {
-        return Ok(vec![]);
-    }
-
-    let mut dir = (fs::read_dir(start_dir).await as Result<ReadDir, io::Error>)?;
-    while let Some(file) = dir.next_entry().await? {
-        let filename = file.file_name();
-        let filename = filename.to_string_lossy();
-        let metadata = file.metadata().await?;
-
-        if metadata.is_dir() {
-            if let Ok(timestamp) = filename.parse::<u64>() {
-                let mut rec_dir = (fs::read_dir(file.path()).await as Result<ReadDir, io::Error>)?;
-
-                let mut users = Vec::new();
-                let mut length: f32 = 0.0;
-                while let Some(rec_file) = rec_dir.next_entry().await? {
-                    if let Some(file_stem) =
-                        rec_file.path().file_stem().and_then(|stem| stem.to_str())
-                    {
-                        users.push(RecordingUser {
-                            name: file_stem.to_string(),
-                            file_name: rec_file.file_name(),
-                        });
-                        length = length.max(
-                            audio_utils::get_length(&rec_file.path())
-                                .await
-                                .unwrap_or(0.0),
-                        );
-                    }
-                }
+    for (timestamp, objects) in recordings {
+        let file_names = objects
+            .into_iter()
+            .filter_map(|object| {
+                object
+                    .key
+                    .rsplit_once('/')
+                    .map(|(_, file_name)| file_name.to_string())
+            })
+            .collect::<Vec<_>>();
+        // The manifest is written last, so recordings without one are still being saved
+        if !file_names.iter().any(|name| name == RECORDING_MANIFEST) {
+            debug!(timestamp, "Skipping recording without manifest");
+            continue;
+        }
+        match read_recording_manifest(guild_id, timestamp).await {
+            Ok(manifest) => results.push(manifest),
+            Err(err) => warn!(timestamp, ?err, "Failed to read recording manifest"),
+        }
+    }
 
-                results.push(Recording {
-                    guild_id,
-                    timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(timestamp),
-                    length,
-                    users,
-                });
-            } else {
-                warn!(?file, "Directory has invalid name. Must be a number.");
+    Ok(results)
+}
+
+/// Writes the manifests of recordings saved before manifests were introduced. Recent recordings are
+/// skipped, as another instance sharing the storage might still be saving them.
+async fn migrate_recording_manifests() -> Result<(), io::Error> {
+    let mut recordings: BTreeMap<(u64, u64), Vec<String>> = BTreeMap::new();
+    for object in STORAGE.list(RECORDINGS_KEY).await? {
+        let relative = object
+            .key
+            .strip_prefix(RECORDINGS_KEY)
+            .unwrap_or(&object.key)
+            .trim_start_matches('/');
+        let mut parts = relative.splitn(3, '/');
+        if let (Some(guild_id), Some(timestamp), Some(file_name)) =
+            (parts.next(), parts.next(), parts.next())
+        {
+            if let (Ok(guild_id), Ok(timestamp), false) = (
+                guild_id.parse::<u64>(),
+                timestamp.parse::<u64>(),
+                file_name.contains('/'),
+            ) {
+                recordings
+                    .entry((guild_id, timestamp))
+                    .or_default()
+                    .push(file_name.to_string());
             }
-        } else {
-            warn!(?file, "File found in invalid location");
         }
     }
 
-    Ok(results)
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    for ((guild_id, timestamp), file_names) in recordings {
+        let recent = timestamp + MANIFEST_MIGRATION_MIN_AGE.as_secs() > now;
+        if recent || file_names.iter().any(|name| name == RECORDING_MANIFEST) {
+            continue;
+        }
+
+        let result = match infer_recording_manifest(guild_id, timestamp, file_names).await {
+            Ok(manifest) => save_recording_manifest(&manifest).await,
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(()) => info!(guild_id, timestamp, "Wrote manifest of previous recording"),
+            Err(err) => warn!(
+                guild_id,
+                timestamp,
+                ?err,
+                "Failed to write manifest of recording"
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+/// Builds a manifest from the audio files of a recording. The user names are parsed from the file names.
+async fn infer_recording_manifest(
+    guild_id: u64,
+    timestamp: u64,
+    file_names: Vec<String>,
+) -> Result<RecordingManifest, io::Error> {
+    let mut tracks = Vec::new();
+    for file_name in file_names {
+        if file_name == RECORDING_MANIFEST {
+            continue;
+        }
+
+        if let Some(file_stem) = Path::new(&file_name)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+        {
+            let path = STORAGE
+                .local_path(&recording_key(guild_id, timestamp, &file_name))
+                .await?;
+            tracks.push(RecordingTrack {
+                user_id: None,
+                name: file_stem.to_string(),
+                file_name: file_name.clone(),
+                start_offset: 0.0,
+                length: audio_utils::get_length(&path).await.unwrap_or(0.0),
+            });
+        }
+    }
+
+    let length = tracks.iter().map(|track| track.length).fold(0.0, f32::max);
+    Ok(RecordingManifest::new(
+        guild_id, timestamp, length, None, tracks,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_temp_names_are_removed() {
+        assert!(is_temp_name("1234.mp3"));
+        assert!(is_temp_name("98765.d"));
+        assert!(!is_temp_name("1234"));
+        assert!(!is_temp_name("1234."));
+        assert!(!is_temp_name(".mp3"));
+        assert!(!is_temp_name("notes.txt"));
+        assert!(!is_temp_name("12a4.mp3"));
+        assert!(!is_temp_name("1234.tar.gz"));
+    }
 }
@@ -10,6 +10,7 @@ mod audio_utils;
 mod db;
 mod discord;
 mod file_handling;
+mod storage;
 
 use discord::connector::Connector as DiscordConnector;
 use discord::CacheHttp;
@@ -40,9 +41,9 @@ async fn main() {
     let subscriber = fmt().event_format(format).with_env_filter(filter).finish();
     tracing::subscriber::set_global_default(subscriber).expect("setting tracing default failed");
 
-    file_handling::create_folders()
-        .await
-        .expect("failed to create data-folders");
+    if let Err(err) = file_handling::prepare_folders().await {
+        panic!("Invalid data folders: {}", err);
+    }
 
     let mut connector = DiscordConnector::new().await;
     let cache_http = connector.cache_http.clone();
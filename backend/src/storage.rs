@@ -0,0 +1,627 @@
+//! Persistent storage of sounds, recordings and mixes.
+//!
+//! Files are addressed by keys like `sounds/<file name>`. They are either kept in the local data folders or
+//! in an S3-compatible object storage. Since ffmpeg and songbird need files on disk, objects from the
+//! object storage are downloaded before they are used. Sounds are kept in a local cache of limited size,
+//! while recordings and mixes are rarely used more than once and only downloaded temporarily.
+
+use std::collections::HashMap;
+use std::env::var;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::LazyLock;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use futures::StreamExt;
+use futures::TryStreamExt;
+use object_store::aws::AmazonS3Builder;
+use object_store::buffered::BufWriter;
+use object_store::path::Path as ObjectPath;
+use object_store::ObjectStore;
+use rand::random;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+use crate::file_handling;
+use crate::file_handling::CACHE_FOLDER;
+
+pub static STORAGE: LazyLock<Storage> = LazyLock::new(|| {
+    Storage::from_env().unwrap_or_else(|err| panic!("Invalid storage configuration: {}", err))
+});
+
+/// Size the cached sounds may take up. The least recently used ones are removed first.
+static CACHE_MAX_SIZE: LazyLock<u64> = LazyLock::new(|| {
+    var("CACHE_MAX_MB")
+        .ok()
+        .and_then(|mb| mb.parse::<u64>().ok())
+        .unwrap_or(1024)
+        .saturating_mul(1024 * 1024)
+});
+
+/// A stored file
+#[derive(Debug, Clone)]
+pub struct StoredObject {
+    pub key: String,
+    pub size: u64,
+    pub last_modified: SystemTime,
+}
+
+enum Backend {
+    /// Files are kept in the data folders. The first part of the key selects the folder, e.g. the sounds
+    /// folder for `sounds/<file name>`.
+    Local,
+    /// Files are kept in an S3-compatible bucket and cached locally on demand
+    ObjectStore(Arc<dyn ObjectStore>),
+}
+
+pub struct Storage {
+    backend: Backend,
+    cache: Arc<Mutex<CacheIndex>>,
+}
+
+/// A local copy of a stored file. Copies that are not cached are removed once this is dropped. Files that
+/// are still open remain readable after that. Cached files are not evicted while they are in use.
+#[derive(Debug)]
+pub struct LocalFile {
+    path: PathBuf,
+    temporary: bool,
+    /// The cache and key of the file, if it is cached
+    cached: Option<(Arc<Mutex<CacheIndex>>, String)>,
+}
+
+impl Deref for LocalFile {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for LocalFile {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for LocalFile {
+    fn drop(&mut self) {
+        if let Some((cache, key)) = &self.cached {
+            cache.lock().unwrap().release(key);
+        }
+        if self.temporary {
+            match std::fs::remove_file(&self.path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                    warn!(?err, path = ?self.path, "Failed to remove temporary download")
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    size: u64,
+    last_used: u64,
+    /// Number of [LocalFile]s of the file that have not been dropped yet
+    users: usize,
+}
+
+/// Keeps track of the cached files to limit the size of the cache
+#[derive(Debug, Default)]
+struct CacheIndex {
+    entries: HashMap<String, CacheEntry>,
+    size: u64,
+    /// Incremented on every use to order the entries by their last use
+    clock: u64,
+}
+
+impl CacheIndex {
+    /// Marks the file as used. Files cached again replace the previous entry, but keep their users.
+    fn insert(&mut self, key: &str, size: u64) {
+        self.clock += 1;
+        let mut entry = CacheEntry {
+            size,
+            last_used: self.clock,
+            users: 0,
+        };
+        if let Some(previous) = self.entries.remove(key) {
+            self.size -= previous.size;
+            entry.users = previous.users;
+        }
+        self.entries.insert(key.to_string(), entry);
+        self.size += size;
+    }
+
+    /// Marks the file as used and registers a user, who has to call [Self::release] when done. Returns false
+    /// if the file is not known.
+    fn acquire(&mut self, key: &str) -> bool {
+        self.clock += 1;
+        match self.entries.get_mut(key) {
+            Some(entry) => {
+                entry.last_used = self.clock;
+                entry.users += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn release(&mut self, key: &str) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.users = entry.users.saturating_sub(1);
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.size -= entry.size;
+        }
+    }
+
+    /// Removes the least recently used files until the cache fits into `max_size` and returns their keys.
+    /// Files in use are kept, even if the cache stays too large.
+    fn evict(&mut self, max_size: u64) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while self.size > max_size {
+            let key = match self
+                .entries
+                .iter()
+                .filter(|(_, entry)| entry.users == 0)
+                .min_by_key(|(_, entry)| entry.last_used)
+            {
+                Some((key, _)) => key.clone(),
+                None => break,
+            };
+            self.remove(&key);
+            evicted.push(key);
+        }
+        evicted
+    }
+}
+
+impl Storage {
+    /// Configured via `STORAGE_BACKEND`, which is either `local` (default) or `s3`
+    fn from_env() -> Result<Self, String> {
+        match var("STORAGE_BACKEND").as_deref() {
+            Err(_) | Ok("local") => Ok(Self {
+                backend: Backend::Local,
+                cache: Default::default(),
+            }),
+            Ok("s3") => {
+                // Allows configuring the usual AWS_* variables as well, e.g. for instance credentials
+                let mut builder = AmazonS3Builder::from_env().with_bucket_name(
+                    var("S3_BUCKET").map_err(|_| "S3_BUCKET must be supplied in env")?,
+                );
+                if let Ok(endpoint) = var("S3_ENDPOINT") {
+                    // MinIO and other stand-ins are often reachable without TLS
+                    builder = builder
+                        .with_allow_http(endpoint.starts_with("http://"))
+                        .with_endpoint(endpoint);
+                }
+                if let Ok(region) = var("S3_REGION") {
+                    builder = builder.with_region(region);
+                }
+                if let Ok(access_key_id) = var("S3_ACCESS_KEY_ID") {
+                    builder = builder.with_access_key_id(access_key_id);
+                }
+                if let Ok(secret_access_key) = var("S3_SECRET_ACCESS_KEY") {
+                    builder = builder.with_secret_access_key(secret_access_key);
+                }
+
+                Ok(Self {
+                    backend: Backend::ObjectStore(Arc::new(
+                        builder.build().map_err(|err| err.to_string())?,
+                    )),
+                    cache: Default::default(),
+                })
+            }
+            Ok(other) => Err(format!("unknown STORAGE_BACKEND {}", other)),
+        }
+    }
+
+    pub fn is_local(&self) -> bool {
+        matches!(self.backend, Backend::Local)
+    }
+
+    /// Only sounds are cached, as they are played over and over again
+    fn is_cached(key: &str) -> bool {
+        key.starts_with(&format!("{}/", file_handling::SOUNDS_KEY))
+    }
+
+    /// Registers the sounds cached by previous runs, so that they are evicted as well. Partial downloads
+    /// are removed.
+    pub async fn index_cache(&self) -> Result<(), io::Error> {
+        let folder = CACHE_FOLDER.join(file_handling::SOUNDS_KEY);
+        if !folder.exists() {
+            return Ok(());
+        }
+
+        let mut files = Vec::new();
+        let mut entries = fs::read_dir(&folder).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if !metadata.is_file() {
+                continue;
+            }
+            if file_name.ends_with(".part") {
+                fs::remove_file(entry.path()).await?;
+                continue;
+            }
+            files.push((file_name, metadata.len(), metadata.modified()?));
+        }
+
+        // The oldest files are evicted first
+        files.sort_by_key(|(_, _, modified)| *modified);
+        let mut cache = self.cache.lock().unwrap();
+        for (file_name, size, _) in files {
+            cache.insert(&file_handling::sound_key(&file_name), size);
+        }
+        Ok(())
+    }
+
+    /// Where the file is kept locally. For object storage, this is the location in the cache.
+    fn local_file(&self, key: &str) -> PathBuf {
+        match &self.backend {
+            Backend::Local => {
+                let (area, rest) = key.split_once('/').unwrap_or((key, ""));
+                file_handling::local_folder(area).join(rest)
+            }
+            Backend::ObjectStore(_) => CACHE_FOLDER.join(key),
+        }
+    }
+
+    /// Copies the local file into the storage. An existing file with the same key is replaced.
+    pub async fn put_file(&self, key: &str, path: &Path) -> Result<(), io::Error> {
+        match &self.backend {
+            Backend::Local => {
+                let target = self.local_file(key);
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent).await?;
+                }
+                fs::copy(path, target).await?;
+            }
+            Backend::ObjectStore(store) => {
+                let mut file = fs::File::open(path).await?;
+                let mut writer = BufWriter::new(store.clone(), ObjectPath::from(key));
+                if let Err(err) = tokio::io::copy(&mut file, &mut writer).await {
+                    writer.abort().await.ok();
+                    return Err(err);
+                }
+                writer.shutdown().await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stores small files like manifests directly from memory
+    pub async fn write(&self, key: &str, content: Vec<u8>) -> Result<(), io::Error> {
+        match &self.backend {
+            Backend::Local => {
+                let target = self.local_file(key);
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent).await?;
+                }
+                fs::write(target, content).await
+            }
+            Backend::ObjectStore(store) => store
+                .put(&ObjectPath::from(key), content.into())
+                .await
+                .map(|_| ())
+                .map_err(to_io_error),
+        }
+    }
+
+    /// Reads the whole file into memory. Unlike [Self::local_path], this bypasses the cache, so files that
+    /// are changed can be read as well.
+    pub async fn read(&self, key: &str) -> Result<Vec<u8>, io::Error> {
+        match &self.backend {
+            Backend::Local => fs::read(self.local_file(key)).await,
+            Backend::ObjectStore(store) => Ok(store
+                .get(&ObjectPath::from(key))
+                .await
+                .map_err(to_io_error)?
+                .bytes()
+                .await
+                .map_err(to_io_error)?
+                .to_vec()),
+        }
+    }
+
+    /// A local copy of the file, e.g. for ffmpeg. Sounds in the object storage are downloaded into the cache
+    /// first. Stored files are never changed, so cached files stay valid. Other files in the object storage
+    /// are downloaded for each use.
+    pub async fn local_path(&self, key: &str) -> Result<LocalFile, io::Error> {
+        let path = self.local_file(key);
+        match &self.backend {
+            Backend::Local => {
+                if !path.exists() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("{} does not exist", key),
+                    ));
+                }
+            }
+            Backend::ObjectStore(store) if !Self::is_cached(key) => {
+                let extension = Path::new(key)
+                    .extension()
+                    .and_then(|extension| extension.to_str())
+                    .unwrap_or("tmp");
+                let file = LocalFile {
+                    path: file_handling::temp_path(extension),
+                    temporary: true,
+                    cached: None,
+                };
+                download(store.as_ref(), key, &file).await?;
+                return Ok(file);
+            }
+            Backend::ObjectStore(store) => {
+                // The file is registered as used first, so that it cannot be evicted in the meantime
+                if self.cache.lock().unwrap().acquire(key) {
+                    let file = self.cached_file(key, path.clone());
+                    if file.exists() {
+                        return Ok(file);
+                    }
+                    // Removed from outside the app. Dropping the file releases it before it is downloaded again.
+                }
+
+                if path.exists() {
+                    let size = fs::metadata(&path).await?.len();
+                    let mut cache = self.cache.lock().unwrap();
+                    cache.insert(key, size);
+                    cache.acquire(key);
+                } else {
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent).await?;
+                    }
+
+                    // Download to a temporary name, so concurrent requests never see partial files
+                    let download_path = path.with_extension(format!("{}.part", random::<u32>()));
+                    let result = download(store.as_ref(), key, &download_path).await;
+                    match result {
+                        Ok(()) => fs::rename(&download_path, &path).await?,
+                        Err(err) => {
+                            fs::remove_file(&download_path).await.ok();
+                            return Err(err);
+                        }
+                    }
+
+                    let size = fs::metadata(&path).await?.len();
+                    // Evicted files are removed under the lock, so that nobody starts using them meanwhile
+                    let mut cache = self.cache.lock().unwrap();
+                    cache.insert(key, size);
+                    cache.acquire(key);
+                    for key in cache.evict(*CACHE_MAX_SIZE) {
+                        if let Err(err) = std::fs::remove_file(self.local_file(&key)) {
+                            warn!(?err, key, "Failed to evict file from cache");
+                        }
+                    }
+                }
+
+                return Ok(self.cached_file(key, path));
+            }
+        }
+
+        Ok(LocalFile {
+            path,
+            temporary: false,
+            cached: None,
+        })
+    }
+
+    /// A file in the cache that was registered as used
+    fn cached_file(&self, key: &str, path: PathBuf) -> LocalFile {
+        LocalFile {
+            path,
+            temporary: false,
+            cached: Some((self.cache.clone(), key.to_string())),
+        }
+    }
+
+    pub async fn exists(&self, key: &str) -> Result<bool, io::Error> {
+        match self.size(key).await {
+            Ok(_) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Size of the file in bytes
+    pub async fn size(&self, key: &str) -> Result<u64, io::Error> {
+        match &self.backend {
+            Backend::Local => Ok(fs::metadata(self.local_file(key)).await?.len()),
+            Backend::ObjectStore(store) => Ok(store
+                .head(&ObjectPath::from(key))
+                .await
+                .map_err(to_io_error)?
+                .size),
+        }
+    }
+
+    /// Removes the file. Fails with [io::ErrorKind::NotFound] for local files that do not exist.
+    pub async fn delete(&self, key: &str) -> Result<(), io::Error> {
+        if let Backend::ObjectStore(store) = &self.backend {
+            store
+                .delete(&ObjectPath::from(key))
+                .await
+                .map_err(to_io_error)?;
+            // The cached copy is not needed anymore
+            self.cache.lock().unwrap().remove(key);
+            return match fs::remove_file(self.local_file(key)).await {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+                _ => Ok(()),
+            };
+        }
+
+        fs::remove_file(self.local_file(key)).await
+    }
+
+    /// Removes all files with keys in the given folder, e.g. `recorder/<guild id>/<timestamp>`
+    pub async fn delete_folder(&self, folder: &str) -> Result<(), io::Error> {
+        let path = self.local_file(folder);
+        if let Backend::ObjectStore(store) = &self.backend {
+            let locations = store
+                .list(Some(&ObjectPath::from(folder)))
+                .map_ok(|meta| meta.location)
+                .boxed();
+            store
+                .delete_stream(locations)
+                .try_collect::<Vec<_>>()
+                .await
+                .map_err(to_io_error)?;
+
+            let prefix = format!("{}/", folder);
+            let mut cache = self.cache.lock().unwrap();
+            let cached_keys = cache
+                .entries
+                .keys()
+                .filter(|key| key.starts_with(&prefix))
+                .cloned()
+                .collect::<Vec<_>>();
+            for key in cached_keys {
+                cache.remove(&key);
+            }
+        }
+
+        match fs::remove_dir_all(path).await {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    /// All files with keys in the given folder and its subfolders
+    pub async fn list(&self, folder: &str) -> Result<Vec<StoredObject>, io::Error> {
+        match &self.backend {
+            Backend::Local => {
+                let start = self.local_file(folder);
+                if !start.exists() {
+                    return Ok(vec![]);
+                }
+
+                let area = folder.split('/').next().unwrap_or(folder);
+                let area_folder = file_handling::local_folder(area);
+                let mut objects = Vec::new();
+                let mut folders = vec![start];
+                while let Some(folder) = folders.pop() {
+                    let mut entries = fs::read_dir(folder).await?;
+                    while let Some(entry) = entries.next_entry().await? {
+                        let metadata = entry.metadata().await?;
+                        if metadata.is_dir() {
+                            folders.push(entry.path());
+                        } else if let Ok(relative) = entry.path().strip_prefix(&area_folder) {
+                            objects.push(StoredObject {
+                                key: format!(
+                                    "{}/{}",
+                                    area,
+                                    relative.to_string_lossy().replace('\\', "/")
+                                ),
+                                size: metadata.len(),
+                                last_modified: metadata.modified()?,
+                            });
+                        }
+                    }
+                }
+
+                Ok(objects)
+            }
+            Backend::ObjectStore(store) => Ok(store
+                .list(Some(&ObjectPath::from(folder)))
+                .map_ok(|meta| StoredObject {
+                    key: meta.location.to_string(),
+                    size: meta.size,
+                    last_modified: meta.last_modified.into(),
+                })
+                .try_collect::<Vec<_>>()
+                .await
+                .map_err(to_io_error)?),
+        }
+    }
+}
+
+async fn download(
+    store: &dyn ObjectStore,
+    key: &str,
+    path: impl AsRef<Path>,
+) -> Result<(), io::Error> {
+    let mut stream = store
+        .get(&ObjectPath::from(key))
+        .await
+        .map_err(to_io_error)?
+        .into_stream();
+    let mut file = fs::File::create(path).await?;
+    while let Some(bytes) = stream.next().await {
+        file.write_all(&bytes.map_err(to_io_error)?).await?;
+    }
+    file.flush().await?;
+
+    Ok(())
+}
+
+fn to_io_error(err: object_store::Error) -> io::Error {
+    match err {
+        object_store::Error::NotFound { .. } => io::Error::new(io::ErrorKind::NotFound, err),
+        err => io::Error::other(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn least_recently_used_files_are_evicted() {
+        let mut cache = CacheIndex::default();
+        cache.insert("sounds/a.mp3", 40);
+        cache.insert("sounds/b.mp3", 40);
+        assert!(cache.acquire("sounds/a.mp3"));
+        cache.release("sounds/a.mp3");
+        cache.insert("sounds/c.mp3", 40);
+
+        assert_eq!(cache.evict(100), vec![String::from("sounds/b.mp3")]);
+        assert_eq!(cache.size, 80);
+        assert!(!cache.acquire("sounds/b.mp3"));
+    }
+
+    #[test]
+    fn files_in_use_are_kept() {
+        let mut cache = CacheIndex::default();
+        cache.insert("sounds/a.mp3", 100);
+        assert!(cache.acquire("sounds/a.mp3"));
+        cache.insert("sounds/b.mp3", 200);
+        assert!(cache.acquire("sounds/b.mp3"));
+
+        assert!(cache.evict(100).is_empty());
+        assert_eq!(cache.size, 300);
+
+        cache.release("sounds/a.mp3");
+        assert_eq!(cache.evict(200), vec![String::from("sounds/a.mp3")]);
+        assert_eq!(cache.size, 200);
+    }
+
+    #[test]
+    fn cached_again_replaces_the_entry() {
+        let mut cache = CacheIndex::default();
+        cache.insert("sounds/a.mp3", 10);
+        cache.insert("sounds/a.mp3", 20);
+        cache.remove("sounds/b.mp3");
+
+        assert_eq!(cache.size, 20);
+        cache.remove("sounds/a.mp3");
+        assert_eq!(cache.size, 0);
+    }
+
+    #[test]
+    fn only_sounds_are_cached() {
+        assert!(Storage::is_cached(&file_handling::sound_key("1_2.mp3")));
+        assert!(!Storage::is_cached(&file_handling::recording_key(
+            1, 2, "3.mp3"
+        )));
+        assert!(!Storage::is_cached(&file_handling::mix_key(1, "mix.mp3")));
+    }
+}
@@ -17,6 +17,7 @@ import { Observable } from 'rxjs';
 import { map } from 'rxjs/operators';
 import { MatToolbar } from '@angular/material/toolbar';
 import { MatFormField, MatLabel } from '@angular/material/form-field';
+import { MatInput } from '@angular/material/input';
 import { MatSelect } from '@angular/material/select';
 import { FormsModule } from '@angular/forms';
 import { DatePipe, DecimalPipe } from '@angular/common';
@@ -24,6 +25,7 @@ import { MatOption } from '@angular/material/core';
 import { MatButton, MatIconButton } from '@angular/material/button';
 import { MatIcon } from '@angular/material/icon';
 import { MatTooltip } from '@angular/material/tooltip';
+import { MatMenu, MatMenuItem, MatMenuTrigger } from '@angular/material/menu';
 import {
   MatAccordion,
   MatExpansionPanel,
@@ -39,7 +41,13 @@ import { MatSlider, MatSliderRangeThumb } from '@angular/material/slider';
 import { VolumeSliderComponent } from '../../common/volume-slider/volume-slider.component';
 import { DataLoadDirective } from '../../common/data-load/data-load.directive';
 import { HeaderComponent } from '../../common/header/header.component';
-import { RecorderService, Recording as SrvRecording, RecordingUser } from '../../services/recorder.service';
+import {
+  RecorderService,
+  RecordingMix,
+  Recording as SrvRecording,
+  RecordingUser,
+} from '../../services/recorder.service';
+import { SoundsService } from '../../services/sounds.service';
 import { AppSettingsService } from '../../services/app-settings.service';
 import { User } from '../../services/api.service';
 import { FooterComponent } from '../../common/footer/footer.component';
@@ -48,6 +56,9 @@ interface Recording extends SrvRecording {
   selected: boolean[];
   start: number;
   end: number;
+  /** Name and category of a sound created from the selected part */
+  soundName: string;
+  soundCategory: string;
 }
 
 @Component({
@@ -60,6 +71,7 @@ interface Recording extends SrvRecording {
     MatToolbar,
     MatFormField,
     MatLabel,
+    MatInput,
     MatSelect,
     FormsModule,
     MatOption,
@@ -68,6 +80,9 @@ interface Recording extends SrvRecording {
     MatIcon,
     MatIconButton,
     MatTooltip,
+    MatMenuTrigger,
+    MatMenu,
+    MatMenuItem,
     MatAccordion,
     MatExpansionPanel,
     MatExpansionPanelHeader,
@@ -92,6 +107,7 @@ interface Recording extends SrvRecording {
 export class RecorderComponent {
   private recorderService = inject(RecorderService);
   private settingsService = inject(AppSettingsService);
+  private soundsService = inject(SoundsService);
   private snackBar = inject(MatSnackBar);
   private cdRef = inject(ChangeDetectorRef);
 
@@ -114,6 +130,9 @@ export class RecorderComponent {
 
   readonly gain = computed(() => clamp(this.settings.localVolume() / 100, 0, 1));
   readonly currentlyPlaying = signal<Recording | null>(null);
+  /** Seconds to save. `null` saves the whole buffer. */
+  readonly recordWindow = signal<number | null>(null);
+  readonly recordWindows = [15, 30, 60];
 
   data$ = this.getRecordingsObservable();
 
@@ -155,11 +174,26 @@ export class RecorderComponent {
             selected: recording.users.map(_ => true),
             start: 0,
             end: recording.length,
+            soundName: '',
+            soundCategory: '',
           })),
       ),
     );
   }
 
+  togglePinned(recording: Recording) {
+    const pinned = !recording.pinned;
+    this.recorderService.setPinned(recording, pinned).subscribe({
+      next: () => {
+        recording.pinned = pinned;
+        this.cdRef.markForCheck();
+      },
+      error: () => {
+        this.snackBar.open(`Failed to ${pinned ? 'pin' : 'unpin'} recording.`, 'Damn', { duration: undefined });
+      },
+    });
+  }
+
   deleteRecording(recording: Recording) {
     this.recorderService.deleteRecording(recording).subscribe({
       next: () => {
@@ -178,7 +212,7 @@ export class RecorderComponent {
     if (!guildId) return;
 
     this.snackBar.open(`Preparing recording. This may take up to one minute.`);
-    this.recorderService.record(guildId).subscribe({
+    this.recorderService.record(guildId, this.recordWindow() ?? undefined).subscribe({
       next: () => {
         this.snackBar.open(`Recording saved!`, undefined, { duration: 1500 });
         this.reload();
@@ -206,19 +240,49 @@ export class RecorderComponent {
     this.currentlyPlaying.set(null);
   }
 
+  tracksDownloadUrl(recording: Recording, layout: 'zip' | 'multichannel') {
+    return this.recorderService.tracksDownloadUrl(recording, layout);
+  }
+
+  private getMix(recording: Recording): RecordingMix {
+    return {
+      start: recording.start,
+      end: recording.end,
+      userIds: recording.users.filter((_, i) => recording.selected[i]).map(user => user.id),
+    };
+  }
+
   downloadMix(recording: Recording) {
-    this.recorderService
-      .mixRecording(recording, {
-        start: recording.start,
-        end: recording.end,
-        userIds: recording.users.filter((_, i) => recording.selected[i]).map(user => user.id),
-      })
+    this.recorderService.mixRecording(recording, this.getMix(recording)).subscribe({
+      next: data => {
+        window.open(data.downloadUrl, '_blank');
+      },
+      error: () => {
+        this.snackBar.open('Unknown error when mixing.', 'Damn', { duration: undefined });
+      },
+    });
+  }
+
+  saveAsSound(recording: Recording) {
+    this.soundsService
+      .createSoundFromRecording(
+        recording,
+        this.getMix(recording),
+        recording.soundName.trim(),
+        recording.soundCategory.trim(),
+      )
       .subscribe({
-        next: data => {
-          window.open(data.downloadUrl, '_blank');
+        next: sound => {
+          this.snackBar.open(`Sound "${sound.name}" created!`, undefined, { duration: 1500 });
         },
-        error: () => {
-          this.snackBar.open('Unknown error when mixing.', 'Damn', { duration: undefined });
+        error: error => {
+          if (error.status === 403) {
+            this.snackBar.open('Not allowed to create the sound. Is a quota of the server exceeded?', 'Damn', {
+              duration: undefined,
+            });
+          } else {
+            this.snackBar.open('Unknown error when creating the sound.', 'Damn', { duration: undefined });
+          }
         },
       });
   }
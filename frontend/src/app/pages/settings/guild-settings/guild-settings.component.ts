@@ -9,6 +9,7 @@ import {
   PipeTransform,
   signal,
   ViewChild,
+  WritableSignal,
 } from '@angular/core';
 import { MatSnackBar } from '@angular/material/snack-bar';
 import { finalize } from 'rxjs/operators';
@@ -23,10 +24,15 @@ import { FormsModule } from '@angular/forms';
 import { MatOption } from '@angular/material/core';
 import { MatInput } from '@angular/material/input';
 import { MatProgressSpinner } from '@angular/material/progress-spinner';
+import { MatCheckbox } from '@angular/material/checkbox';
 import { DataLoadDirective } from '../../../common/data-load/data-load.directive';
-import { GuildSettingsService } from '../../../services/guild-settings.service';
+import {
+  GuildSettingsService,
+  RecordingFormat,
+  RecordingRetentionSetting,
+} from '../../../services/guild-settings.service';
 import { RandomInfixesComponent } from '../random-infixes/random-infixes.component';
-import { ApiService, RandomInfix, User } from '../../../services/api.service';
+import { ApiService, Capability, RandomInfix, User } from '../../../services/api.service';
 import { UnsavedChangesBoxComponent } from '../unsaved-changes-box/unsaved-changes-box.component';
 
 type SavingState = 'saved' | 'saving' | 'error';
@@ -56,6 +62,7 @@ export class FilterRandomInfixesPipe implements PipeTransform {
     MatInput,
     MatSuffix,
     MatProgressSpinner,
+    MatCheckbox,
     RandomInfixesComponent,
     UnsavedChangesBoxComponent,
     KeyValuePipe,
@@ -74,7 +81,7 @@ export class GuildSettingsComponent {
   private readonly guild = computed(() => this.user.guilds.find(guild => guild.id === this.guildId()));
 
   readonly guildName = computed(() => this.guild()?.name ?? 'Unknown guild');
-  readonly guildRole = computed(() => this.guild()?.role);
+  readonly canManageSettings = computed(() => this.guild()?.capabilities.includes('manageSettings') ?? false);
 
   readonly data$ = computed(() => {
     return forkJoin({
@@ -83,10 +90,41 @@ export class GuildSettingsComponent {
     });
   });
 
+  readonly capabilities: { value: Capability; name: string }[] = [
+    { value: 'play', name: 'Play sounds' },
+    { value: 'record', name: 'Save and delete recordings' },
+    { value: 'downloadRecordings', name: 'Listen to and download recordings' },
+    { value: 'manageSounds', name: 'Manage sounds and random buttons' },
+    { value: 'manageSettings', name: 'Manage server settings' },
+    { value: 'viewAuditLog', name: 'View the audit log' },
+  ];
+
+  readonly recordingRetentionSettings: { key: RecordingRetentionSetting; name: string; unit: string }[] = [
+    { key: 'recordingMaxAgeDays', name: 'Maximum age', unit: 'days' },
+    { key: 'recordingMaxCount', name: 'Maximum number of recordings', unit: 'recordings' },
+    { key: 'recordingMaxStorageMb', name: 'Maximum storage', unit: 'MiB' },
+  ];
+
   readonly userIsSaving = signal<SavingState | null>(null);
   readonly moderatorIsSaving = signal<SavingState | null>(null);
+  readonly userCapabilitiesIsSaving = signal<SavingState | null>(null);
+  readonly moderatorCapabilitiesIsSaving = signal<SavingState | null>(null);
   readonly meanVolumeIsSaving = signal<SavingState | null>(null);
   readonly maxVolumeIsSaving = signal<SavingState | null>(null);
+  readonly recordingOptInIsSaving = signal<SavingState | null>(null);
+  readonly recordingLengthIsSaving = signal<SavingState | null>(null);
+  readonly recordingFormatIsSaving = signal<SavingState | null>(null);
+  readonly recordingFormats: { format: RecordingFormat; name: string }[] = [
+    { format: 'mp3', name: 'MP3' },
+    { format: 'opus', name: 'Opus' },
+    { format: 'flac', name: 'FLAC (lossless)' },
+    { format: 'wav', name: 'WAV (lossless, uncompressed)' },
+  ];
+  readonly recordingRetentionIsSaving: Record<RecordingRetentionSetting, WritableSignal<SavingState | null>> = {
+    recordingMaxAgeDays: signal(null),
+    recordingMaxCount: signal(null),
+    recordingMaxStorageMb: signal(null),
+  };
 
   readonly randomInfixesHasChanges = signal(false);
   readonly randomInfixIsSaving = signal(false);
@@ -104,22 +142,88 @@ export class GuildSettingsComponent {
       });
   }
 
-  setUserRoleId(roleId: string, guildId: string) {
+  setUserRoleIds(roleIds: string[], guildId: string) {
     this.userIsSaving.set('saving');
-    this.guildSettingsService.updateGuildSettings(guildId, { userRoleId: roleId }).subscribe(
+    this.guildSettingsService.updateGuildSettings(guildId, { userRoleIds: roleIds }).subscribe(
       () => this.userIsSaving.set('saved'),
       () => this.userIsSaving.set('error'),
     );
   }
 
-  setModeratorRoleId(roleId: string, guildId: string) {
+  setModeratorRoleIds(roleIds: string[], guildId: string) {
     this.moderatorIsSaving.set('saving');
-    this.guildSettingsService.updateGuildSettings(guildId, { moderatorRoleId: roleId }).subscribe(
+    this.guildSettingsService.updateGuildSettings(guildId, { moderatorRoleIds: roleIds }).subscribe(
       () => this.moderatorIsSaving.set('saved'),
       () => this.moderatorIsSaving.set('error'),
     );
   }
 
+  setUserCapabilities(capabilities: Capability[], guildId: string) {
+    this.userCapabilitiesIsSaving.set('saving');
+    this.guildSettingsService.updateGuildSettings(guildId, { userCapabilities: capabilities }).subscribe(
+      () => this.userCapabilitiesIsSaving.set('saved'),
+      () => this.userCapabilitiesIsSaving.set('error'),
+    );
+  }
+
+  setModeratorCapabilities(capabilities: Capability[], guildId: string) {
+    this.moderatorCapabilitiesIsSaving.set('saving');
+    this.guildSettingsService.updateGuildSettings(guildId, { moderatorCapabilities: capabilities }).subscribe(
+      () => this.moderatorCapabilitiesIsSaving.set('saved'),
+      () => this.moderatorCapabilitiesIsSaving.set('error'),
+    );
+  }
+
+  formatSize(bytes: number | null) {
+    if (bytes === null) {
+      return 'unlimited';
+    }
+    return `${(bytes / 1024 / 1024).toFixed(1)} MiB`;
+  }
+
+  setRecordingOptInRequired(required: boolean, guildId: string) {
+    this.recordingOptInIsSaving.set('saving');
+    this.guildSettingsService.updateGuildSettings(guildId, { recordingOptInRequired: required }).subscribe(
+      () => this.recordingOptInIsSaving.set('saved'),
+      () => this.recordingOptInIsSaving.set('error'),
+    );
+  }
+
+  /** An empty value uses the maximum */
+  setRecordingLength(length: string, maxLength: number, guildId: string) {
+    if (length.length > 0 && (!Number.isInteger(+length) || +length < 1 || +length > maxLength)) {
+      return;
+    }
+    this.recordingLengthIsSaving.set('saving');
+    this.guildSettingsService
+      .updateGuildSettings(guildId, { recordingLength: length.length > 0 ? +length : null })
+      .subscribe(
+        () => this.recordingLengthIsSaving.set('saved'),
+        () => this.recordingLengthIsSaving.set('error'),
+      );
+  }
+
+  setRecordingFormat(format: RecordingFormat, guildId: string) {
+    this.recordingFormatIsSaving.set('saving');
+    this.guildSettingsService.updateGuildSettings(guildId, { recordingFormat: format }).subscribe(
+      () => this.recordingFormatIsSaving.set('saved'),
+      () => this.recordingFormatIsSaving.set('error'),
+    );
+  }
+
+  /** An empty value removes the limit */
+  setRecordingRetention(setting: RecordingRetentionSetting, value: string, guildId: string) {
+    if (value.length > 0 && (!Number.isInteger(+value) || +value < 0)) {
+      return;
+    }
+    const isSaving = this.recordingRetentionIsSaving[setting];
+    isSaving.set('saving');
+    this.guildSettingsService.updateGuildSettings(guildId, { [setting]: value.length > 0 ? +value : null }).subscribe(
+      () => isSaving.set('saved'),
+      () => isSaving.set('error'),
+    );
+  }
+
   setMeanVolume(volume: string, guildId: string) {
     if (volume.length > 0 && +volume > -30 && +volume < 30) {
       this.meanVolumeIsSaving.set('saving');
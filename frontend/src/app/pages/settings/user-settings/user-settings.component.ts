@@ -1,14 +1,57 @@
-import { ChangeDetectionStrategy, Component, inject } from '@angular/core';
+import { ChangeDetectionStrategy, Component, inject, Input, signal } from '@angular/core';
 import { MatCheckbox } from '@angular/material/checkbox';
 import { FormsModule } from '@angular/forms';
+import { MatFormField, MatLabel } from '@angular/material/form-field';
+import { MatSelect } from '@angular/material/select';
+import { MatOption } from '@angular/material/core';
+import { MatSnackBar } from '@angular/material/snack-bar';
 import { AppSettingsService } from '../../../services/app-settings.service';
+import { RecorderService, RecordingConsent } from '../../../services/recorder.service';
+import { User } from '../../../services/api.service';
 
 @Component({
   templateUrl: './user-settings.component.html',
   styleUrls: ['./user-settings.component.scss'],
   changeDetection: ChangeDetectionStrategy.OnPush,
-  imports: [MatCheckbox, FormsModule],
+  imports: [MatCheckbox, FormsModule, MatFormField, MatLabel, MatSelect, MatOption],
 })
 export class UserSettingsComponent {
   settingsService = inject(AppSettingsService);
+  private recorderService = inject(RecorderService);
+  private snackBar = inject(MatSnackBar);
+
+  @Input({ required: true }) user!: User;
+
+  readonly consent = signal<RecordingConsent | null>(null);
+
+  constructor() {
+    this.recorderService.loadRecordingConsent().subscribe({
+      next: consent => this.consent.set(consent),
+      error: () => this.snackBar.open('Failed to load recording settings.', 'Damn', { duration: undefined }),
+    });
+  }
+
+  setOptedOut(optedOut: boolean) {
+    this.recorderService.setRecordingOptOut(optedOut).subscribe({
+      next: () => this.consent.update(consent => consent && { ...consent, optedOut }),
+      error: () => this.snackBar.open('Failed to save recording settings.', 'Damn', { duration: undefined }),
+    });
+  }
+
+  setGuildConsent(guildId: string, choice: boolean | null) {
+    this.recorderService.setGuildRecordingConsent(guildId, choice).subscribe({
+      next: () =>
+        this.consent.update(consent => {
+          if (!consent) return consent;
+          const guildChoices = { ...consent.guildChoices };
+          if (choice === null) {
+            delete guildChoices[guildId];
+          } else {
+            guildChoices[guildId] = choice;
+          }
+          return { ...consent, guildChoices };
+        }),
+      error: () => this.snackBar.open('Failed to save recording settings.', 'Damn', { duration: undefined }),
+    });
+  }
 }
@@ -5,11 +5,14 @@ import { sortBy } from 'lodash-es';
 
 export type UserRole = 'admin' | 'moderator' | 'user';
 
+export type Capability = 'play' | 'record' | 'downloadRecordings' | 'manageSounds' | 'manageSettings' | 'viewAuditLog';
+
 export interface Guild {
   id: string;
   name: string;
   iconUrl?: string;
   role: UserRole;
+  capabilities: Capability[];
 }
 
 export interface User {
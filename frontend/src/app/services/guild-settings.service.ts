@@ -1,15 +1,48 @@
 import { Injectable, inject } from '@angular/core';
 import { HttpClient } from '@angular/common/http';
-import { RandomInfix } from './api.service';
+import { Capability, RandomInfix } from './api.service';
+
+/** Limits set by the bot operator. Storage is in bytes, clip length in seconds and `null` means unlimited. */
+export interface GuildQuota {
+  maxSoundStorage: number | null;
+  maxSounds: number | null;
+  maxClipLength: number | null;
+  maxRecordingStorage: number | null;
+}
+
+export interface GuildUsage {
+  soundStorage: number;
+  sounds: number;
+  recordingStorage: number;
+}
+
+/** Format the tracks of saved recordings are encoded in */
+export type RecordingFormat = 'mp3' | 'flac' | 'opus' | 'wav';
 
 export interface GuildSettings {
-  userRoleId: string;
-  moderatorRoleId: string;
+  userRoleIds: string[];
+  moderatorRoleIds: string[];
+  userCapabilities: Capability[];
+  moderatorCapabilities: Capability[];
   targetMeanVolume: number;
   targetMaxVolume: number;
+  /** Recordings exceeding any of these limits are removed automatically unless pinned. `null` means no limit. */
+  recordingMaxAgeDays: number | null;
+  recordingMaxCount: number | null;
+  recordingMaxStorageMb: number | null;
+  /** Only users who opted in are recorded */
+  recordingOptInRequired: boolean;
+  /** Seconds of audio the recorder keeps. `null` uses the maximum. */
+  recordingLength: number | null;
+  maxRecordingLength: number;
+  recordingFormat: RecordingFormat;
   roles: Map<string, string>;
+  quota: GuildQuota;
+  usage: GuildUsage;
 }
 
+export type RecordingRetentionSetting = 'recordingMaxAgeDays' | 'recordingMaxCount' | 'recordingMaxStorageMb';
+
 @Injectable({ providedIn: 'root' })
 export class GuildSettingsService {
   private http = inject(HttpClient);
@@ -24,7 +57,10 @@ export class GuildSettingsService {
     return this.http.get<GuildSettings>(`/api/guilds/${encodeURIComponent(guildId)}/settings`);
   }
 
-  updateGuildSettings(guildId: string, guildSettings: Partial<Omit<GuildSettings, 'roles'>>) {
+  updateGuildSettings(
+    guildId: string,
+    guildSettings: Partial<Omit<GuildSettings, 'roles' | 'quota' | 'usage' | 'maxRecordingLength'>>,
+  ) {
     return this.http.put(`/api/guilds/${encodeURIComponent(guildId)}/settings`, guildSettings, {
       responseType: 'text',
     });
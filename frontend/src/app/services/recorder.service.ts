@@ -9,6 +9,9 @@ interface ApiRecording {
   timestamp: number;
   users: ApiRecordingUser[];
   length: number;
+  triggeredBy: string | null;
+  /** Pinned recordings are never removed automatically */
+  pinned: boolean;
 }
 
 export interface Recording extends ApiRecording {
@@ -18,6 +21,11 @@ export interface Recording extends ApiRecording {
 interface ApiRecordingUser {
   username: string;
   id: string;
+  /** Unknown for recordings saved before user ids were tracked */
+  userId: string | null;
+  /** Seconds from the start of the recording until the user spoke first */
+  startOffset: number;
+  length: number;
 }
 
 export interface RecordingUser extends ApiRecordingUser {
@@ -32,6 +40,12 @@ export interface RecordingMix {
   userIds: string[];
 }
 
+/** Whether the user may be recorded. Guilds without a choice use their default. */
+export interface RecordingConsent {
+  optedOut: boolean;
+  guildChoices: Record<string, boolean>;
+}
+
 export interface MixingResult {
   downloadUrl: string;
 }
@@ -42,9 +56,11 @@ export interface MixingResult {
 export class RecorderService {
   private http = inject(HttpClient);
 
-  record(guild: Guild | string) {
+  /** Saves the buffered audio. `seconds` only saves the last seconds of it. */
+  record(guild: Guild | string, seconds?: number) {
     const guildId = typeof guild === 'string' ? guild : guild.id;
-    return this.http.post(`/api/guilds/${guildId}/record`, {}, { responseType: 'text' });
+    const params: Record<string, number> = seconds != null ? { seconds } : {};
+    return this.http.post(`/api/guilds/${guildId}/record`, {}, { responseType: 'text', params });
   }
 
   loadRecordings(): Observable<Recording[]> {
@@ -65,6 +81,29 @@ export class RecorderService {
     return this.http.post<MixingResult>(`/api/guilds/${recording.guildId}/recordings/${recording.timestamp}`, mix);
   }
 
+  loadRecordingConsent() {
+    return this.http.get<RecordingConsent>('/api/user/recording-consent');
+  }
+
+  setRecordingOptOut(optedOut: boolean) {
+    return this.http.put('/api/user/recording-consent', { optedOut });
+  }
+
+  /** `null` uses the default of the server */
+  setGuildRecordingConsent(guildId: string, consent: boolean | null) {
+    return this.http.put(`/api/guilds/${guildId}/recording-consent`, { consent });
+  }
+
+  setPinned(recording: Recording, pinned: boolean) {
+    const url = `/api/guilds/${recording.guildId}/recordings/${recording.timestamp}/pin`;
+    return pinned ? this.http.put(url, {}) : this.http.delete(url);
+  }
+
+  /** Download of all tracks, either as zip archive or as one WAV file with two channels per track */
+  tracksDownloadUrl(recording: Recording, layout: 'zip' | 'multichannel') {
+    return `/api/guilds/${recording.guildId}/recordings/${recording.timestamp}/download?layout=${layout}`;
+  }
+
   deleteRecording(recording: Recording) {
     return this.http.delete(`/api/guilds/${recording.guildId}/recordings/${recording.timestamp}`);
   }
@@ -3,6 +3,7 @@ import { Injectable, inject } from '@angular/core';
 import { map } from 'rxjs/operators';
 import { sortBy } from 'lodash-es';
 import { Guild } from './api.service';
+import { Recording, RecordingMix } from './recorder.service';
 
 interface ApiSound {
   id: string;
@@ -84,6 +85,19 @@ export class SoundsService {
     return this.http.post<ApiSound>(`/api/sounds`, { guildId, name, category }).pipe(map(sound => new Sound(sound)));
   }
 
+  /** Creates a sound from the mixed part of the recording, analyzed like an uploaded file */
+  createSoundFromRecording(recording: Recording, mix: RecordingMix, name: string, category: string) {
+    return this.http
+      .post<ApiSound>(`/api/sounds/from-recording`, {
+        guildId: recording.guildId,
+        timestamp: recording.timestamp,
+        ...mix,
+        name,
+        category,
+      })
+      .pipe(map(sound => new Sound(sound)));
+  }
+
   updateSound(sound: Sound) {
     return this.http.put(
       `/api/sounds/${encodeURIComponent(sound.id)}`,